@@ -0,0 +1,139 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ Benchmarks reading a posix database source with different numbers of worker threads. The
+ source is a synthetic database written to a temporary directory, with enough products and
+ versions that the time is dominated by reading the directories and files of the source.
+
+ Run with `cargo bench --bench posix_load`. Setting REUPS_BENCH_PRODUCTS and
+ REUPS_BENCH_VERSIONS changes the size of the database, and REUPS_BENCH_DIR writes it into a
+ directory other than the temporary directory, such as one on a network file system.
+*/
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use reups_lib as reups;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Writes a ups_db directory into root holding the given number of products, each declared with
+/// the given number of versions and tagged current at the last of them. The product directories
+/// and table files the versions point at are not created, as reading the source does not need
+/// them.
+fn generate_db(root: &Path, products: usize, versions: usize) -> PathBuf {
+    let ups_db = root.join("ups_db");
+    for product in (0..products).map(|x| format!("product{}", x)) {
+        let product_dir = ups_db.join(&product);
+        fs::create_dir_all(&product_dir).unwrap();
+        for version in (0..versions).map(|x| format!("v{}", x)) {
+            let contents = format!(
+                "FILE = version
+PRODUCT = {product}
+VERSION = {version}
+#***************************************
+
+Group:
+   FLAVOR = Linux64
+   QUALIFIERS = \"\"
+   DECLARER = bench
+   DECLARED = Tue Mar 26 11:21:26 2019
+   PROD_DIR = products/{product}/{version}
+   UPS_DIR = ups
+   TABLE_FILE = {product}.table
+End:
+",
+                product = product,
+                version = version
+            );
+            fs::write(product_dir.join(format!("{}.version", version)), contents).unwrap();
+        }
+        let chain = format!(
+            "FILE = version
+PRODUCT = {product}
+CHAIN = current
+#***************************************
+
+#Group:
+   FLAVOR = Linux64
+   VERSION = v{version}
+   QUALIFIERS = \"\"
+   DECLARER = bench
+   DECLARED = Tue Mar 26 11:21:26 2019
+#End:
+",
+            product = product,
+            version = versions - 1
+        );
+        fs::write(product_dir.join("current.chain"), chain).unwrap();
+    }
+    ups_db
+}
+
+fn posix_load(c: &mut Criterion) {
+    let root = std::env::var("REUPS_BENCH_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir())
+        .join(format!("reups_bench_{}", std::process::id()));
+    let products = env_or("REUPS_BENCH_PRODUCTS", 1000);
+    let versions = env_or("REUPS_BENCH_VERSIONS", 5);
+    let ups_db = generate_db(&root, products, versions);
+
+    // Two workers matches the number of threads each stage used before the pool was configurable
+    let mut worker_counts = vec![1, 2, 4, 8, reups::default_workers()];
+    worker_counts.sort_unstable();
+    worker_counts.dedup();
+
+    let mut group = c.benchmark_group("posix_load");
+    group.sample_size(10);
+    for workers in worker_counts {
+        let options = reups::PosixReadOptions {
+            cache_dir: None,
+            workers,
+        };
+        group.bench_with_input(
+            BenchmarkId::new("workers", workers),
+            &options,
+            |b, options| {
+                b.iter(|| {
+                    reups::PosixDBImpl::new_with_options(
+                        ups_db.clone(),
+                        Some(&reups::DBLoadControl::All),
+                        None,
+                        options,
+                    )
+                    .unwrap()
+                })
+            },
+        );
+    }
+
+    // Reading through a warm index cache skips walking the product directories
+    let options = reups::PosixReadOptions {
+        cache_dir: Some(root.join("cache")),
+        workers: reups::default_workers(),
+    };
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            reups::PosixDBImpl::new_with_options(
+                ups_db.clone(),
+                Some(&reups::DBLoadControl::All),
+                None,
+                &options,
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+    let _ = fs::remove_dir_all(&root);
+}
+
+criterion_group!(benches, posix_load);
+criterion_main!(benches);
@@ -5,7 +5,7 @@
 
 #[doc(no_inline)]
 pub use clap::ArgMatches;
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 /**
  * Builds and returns the sub command struct, containing all the options for the setup command
@@ -13,6 +13,13 @@ use clap::{App, Arg, SubCommand};
 pub fn build_setup<'a, 'b>() -> App<'a, 'b> {
     return SubCommand::with_name("setup")
         .arg(Arg::with_name("product").help("Product to setup").index(1))
+        .arg(
+            Arg::with_name("version")
+                .help("Version of the product to setup, instead of resolving it with tags")
+                .index(2)
+                .requires("product")
+                .conflicts_with("ident"),
+        )
         .arg(
             Arg::with_name("just")
                 .help("ignore dependncies")
@@ -52,6 +59,22 @@ pub fn build_setup<'a, 'b>() -> App<'a, 'b> {
                 .help("Unsetup a product instead of setting it up")
                 .short("u")
                 .long("unsetup"),
+        )
+        .arg(
+            Arg::with_name("ident")
+                .help("Setup the version of the product with this identity")
+                .short("i")
+                .long("ident")
+                .takes_value(true)
+                .requires("product")
+                .conflicts_with("relative"),
+        )
+        .arg(
+            Arg::with_name("qualifiers")
+                .help("Setup the builds of products declared with these qualifiers, falling back to builds without qualifiers")
+                .short("q")
+                .long("qualifiers")
+                .takes_value(true),
         );
 }
 
@@ -83,6 +106,10 @@ fn build_list<'a, 'b>() -> App<'a, 'b> {
                                .help("Only list product & versions (faster than tags and versions)")
                                .long("onlyVers")
                                .conflicts_with("onlyTags"))
+                          .arg(Arg::with_name("flavors")
+                               .help("Show the flavors each version is declared with")
+                               .long("flavors")
+                               .conflicts_with_all(&["onlyTags", "onlyVers"]))
                           .arg(Arg::with_name("local")
                                .help("Only list products that are setup as local products")
                                .short("l")
@@ -118,13 +145,19 @@ fn build_env<'a, 'b>() -> App<'a, 'b> {
         .arg(
             Arg::with_name("command")
                 .required(true)
-                .possible_values(&["save", "restore", "delete", "list"])
+                .possible_values(&["save", "restore", "delete", "list", "enter", "leave", "hook"])
                 .help("Action to take for a given environment, to restore you most likely want to use the rrestore shell function"),
         )
         .arg(
             Arg::with_name("name")
                 .required(false)
-                .help("Optional name to save/restore"),
+                .help("Optional name to save/restore, or directory to search for a project file when entering"),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .short("y")
+                .long("yes")
+                .help("Create a new env store without prompting if none exists"),
         );
 }
 
@@ -142,7 +175,7 @@ fn build_declare<'a, 'b>() -> App<'a, 'b> {
         )
         .arg(
             Arg::with_name("version")
-                .required(true)
+                .required_unless("git")
                 .help("Version name/number to assign to product"),
         )
         .arg(
@@ -165,7 +198,8 @@ fn build_declare<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("source")
                 .required(false)
                 .help("Database source to declare to, list with reups list --sources")
-                .long("source"),
+                .long("source")
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("ident")
@@ -174,15 +208,172 @@ fn build_declare<'a, 'b>() -> App<'a, 'b> {
                 .long("ident")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("qualifiers")
+                .required(false)
+                .help("Qualifiers distinguishing this build of the product, such as debug")
+                .long("qualifiers")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("relative")
                 .required(false)
                 .help("Set this to allow declaring relative paths, otherwise paths are expanded")
                 .long("relative")
                 .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("overlay")
+                .required(false)
+                .help("Declare into the overlay, which is looked up first and leaves the other sources unchanged")
+                .long("overlay")
+                .takes_value(false)
+                .conflicts_with_all(&["source", "relative"]),
+        )
+        .arg(
+            Arg::with_name("table")
+                .required(false)
+                .help("Table file to declare the product with, in place of the one in its ups directory")
+                .long("table")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ups-dir")
+                .required(false)
+                .help("Directory holding the table file, absolute or relative to the product directory, ups if not set")
+                .long("ups-dir")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("git")
+                .required(false)
+                .help("Take the version and ident from the git commit checked out in the product directory, unless they are given")
+                .long("git")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .required(false)
+                .help("Refuse to declare a product whose table requires products or versions not declared in any source")
+                .long("strict")
+                .takes_value(false),
         );
 }
 
+/**
+ * Builds cli interface for the subcommand db, and the actions it has for maintaining the
+ * database sources.
+ **/
+fn build_db<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("db")
+        .about("Maintain the database sources reups uses")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check every database source for broken entries")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Write the problems found as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fix")
+                .about("Repair common damage to the database sources")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Print the repairs that would be made without making them"),
+                )
+                .arg(
+                    Arg::with_name("paths")
+                        .long("paths")
+                        .takes_value(true)
+                        .possible_values(&["absolute", "relative"])
+                        .help("Rewrite the product directories recorded as absolute paths, or relative to the source"),
+                )
+                .arg(
+                    Arg::with_name("source")
+                        .long("source")
+                        .takes_value(true)
+                        .help("Only repair this source, list with reups list --sources"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("Copy the products of one database source into another of any type")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Source to copy from, a json or sqlite file, or a directory containing a ups_db"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Source to copy into, a json or sqlite file, or a directory containing a ups_db"),
+                )
+                .arg(
+                    Arg::with_name("merge")
+                        .long("merge")
+                        .help("Add into an existing destination, reporting entries it already has"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("List the differences between two database sources")
+                .arg(
+                    Arg::with_name("source_a")
+                        .required(true)
+                        .help("Source to compare from, a json or sqlite file, or a directory containing a ups_db"),
+                )
+                .arg(
+                    Arg::with_name("source_b")
+                        .required(true)
+                        .help("Source to compare to, a json or sqlite file, or a directory containing a ups_db"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Show the audit log of the changes made to each database source")
+                .arg(
+                    Arg::with_name("product")
+                        .help("Only show the changes made to this product"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Write the history as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Manage the index caches of posix database sources")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Remove the index caches, so they are rebuilt the next time they are used"),
+                ),
+        )
+}
+
+/**
+ * Builds cli interface for the subcommand overlay, which manages the products declared into the
+ * overlay.
+ **/
+fn build_overlay<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("overlay")
+        .about("Manage the overlay of products declared with declare --overlay")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("clear")
+                .about("Discard the overlay and every product declared into it"),
+        )
+}
+
 /**
  * Builds and returns the sub command struct, containing all the options for the prep command.
  *
@@ -190,7 +381,11 @@ fn build_declare<'a, 'b>() -> App<'a, 'b> {
  * this command may take optional arguments such as a configuration file to use in preping.
  */
 fn build_prep<'a, 'b>() -> App<'a, 'b> {
-    return SubCommand::with_name("prep");
+    return SubCommand::with_name("prep").arg(
+        Arg::with_name("hook")
+            .long("hook")
+            .help("Automatically enter and leave project environments when changing directories"),
+    );
 }
 
 /**
@@ -231,12 +426,27 @@ pub fn build_cli() -> App<'static, 'static> {
                 .long("nosys")
                 .help("Disable loading database found in system environment variables"),
         )
+        .arg(
+            Arg::with_name("no-cache")
+                .global(true)
+                .long("no-cache")
+                .help("Read posix database sources without their index caches"),
+        )
+        .arg(
+            Arg::with_name("flavor")
+                .global(true)
+                .long("flavor")
+                .takes_value(true)
+                .help("Flavor to use in place of the one configured or detected for this system"),
+        )
         .subcommand(build_setup())
         .subcommand(build_prep())
         .subcommand(build_list())
         .subcommand(build_completions())
         .subcommand(build_env())
         .subcommand(build_declare())
+        .subcommand(build_db())
+        .subcommand(build_overlay())
 }
 
 /**
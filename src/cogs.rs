@@ -17,18 +17,46 @@ use dirs;
 use std::env;
 use std::path::PathBuf;
 
-const APP_INFO: app_dirs::AppInfo = app_dirs::AppInfo {
+pub(crate) const APP_INFO: app_dirs::AppInfo = app_dirs::AppInfo {
     name: "reups",
     author: "Reups Community",
 };
 
 // Determine the system on which this comand is run. In eups past there used to be
 // more flavors (i.e. just linux) but these systems are almost never used and are
-// dropped from consideration in reups.
-#[cfg(target_os = "macos")]
+// dropped from consideration in reups. Systems which are not recognized use the
+// generic flavor, and will only match products declared as generic.
+#[cfg(all(target_os = "macos", target_arch = "x86_64"))]
 pub static SYSTEM_OS: &str = "Darwin64";
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+pub static SYSTEM_OS: &str = "DarwinARM64";
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
 pub static SYSTEM_OS: &str = "Linux64";
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+pub static SYSTEM_OS: &str = "LinuxARM64";
+#[cfg(all(target_os = "linux", target_arch = "powerpc64"))]
+pub static SYSTEM_OS: &str = "LinuxPPC64";
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub static SYSTEM_OS: &str = "Linux";
+#[cfg(not(any(
+    all(target_os = "macos", target_arch = "x86_64"),
+    all(target_os = "macos", target_arch = "aarch64"),
+    all(target_os = "linux", target_arch = "x86_64"),
+    all(target_os = "linux", target_arch = "aarch64"),
+    all(target_os = "linux", target_arch = "powerpc64"),
+    all(target_os = "linux", target_arch = "x86")
+)))]
+pub static SYSTEM_OS: &str = "generic";
+
+/// Flavors which mark a product as usable on any system
+const GENERIC_FLAVORS: [&str; 3] = ["generic", "NULL", ""];
+
+/// Returns true if the flavor is one that can be used on any system
+pub fn is_generic_flavor(flavor: &str) -> bool {
+    GENERIC_FLAVORS
+        .iter()
+        .any(|generic| generic.eq_ignore_ascii_case(flavor))
+}
 
 /** Macro used to print an error message to the console and terminate execution
  *
@@ -43,9 +71,44 @@ macro_rules! exit_with_message {
     };
 }
 
-/// Splits apart a string with paths separated by colons into a vector of paths
+/// Returns true if the string is a valid url scheme, a letter followed by letters, digits, plus
+/// signs, periods, or hyphens
+pub fn is_scheme(scheme: &str) -> bool {
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '.' || c == '-')
+}
+
+/// Splits a string on colons, keeping the colons of any urls in the string. The colon after the
+/// scheme of a url is kept, as is the one before a port following the host
+fn split_paths(path_string: &str) -> Vec<String> {
+    let mut parts = path_string.split(':').peekable();
+    let mut paths = vec![];
+    while let Some(part) = parts.next() {
+        match parts.peek() {
+            Some(next) if is_scheme(part) && next.starts_with("//") => {
+                let host = parts.next().unwrap();
+                let mut url = format!("{}:{}", part, host);
+                let has_port = parts
+                    .peek()
+                    .is_some_and(|x| x.starts_with(|c: char| c.is_ascii_digit()));
+                if !host[2..].contains('/') && has_port {
+                    url = format!("{}:{}", url, parts.next().unwrap());
+                }
+                paths.push(url);
+            }
+            _ => paths.push(part.to_string()),
+        }
+    }
+    paths
+}
+
+/// Splits apart a string with paths separated by colons into a vector of paths. Each path is one
+/// opened by a registered source constructor, such as a json file or the url of a remote source,
+/// or a directory containing a ups_db directory
 pub fn path_string_to_vec(path_string: &str) -> Result<Vec<PathBuf>, String> {
-    let eups_path_vec: Vec<&str> = path_string.split(":").collect();
+    let eups_path_vec = split_paths(path_string);
     if eups_path_vec.is_empty() {
         return Err("Path is empty".to_string());
     }
@@ -53,9 +116,8 @@ pub fn path_string_to_vec(path_string: &str) -> Result<Vec<PathBuf>, String> {
         .iter()
         .filter_map(|path| {
             let mut converted_path = PathBuf::from(path);
-            let extension = converted_path.extension();
-            // Check if the supplied path is a json file, if so just return it
-            if extension.is_some() && extension.unwrap() == "json" {
+            // Check if the supplied path is opened by a registered constructor, if so just return it
+            if crate::db::is_registered_source(&converted_path) {
                 Some(converted_path)
             } else {
                 converted_path.push("ups_db");
@@ -145,3 +207,32 @@ pub fn get_reups_user_db() -> Option<PathBuf> {
         None
     }
 }
+
+/// Returns the directory the local copies of remote database sources are kept in, which is the
+/// remote directory inside REUPS_CACHE_DIR if it is set, or is otherwise within the user cache
+/// directory
+pub fn get_remote_cache_dir() -> Option<PathBuf> {
+    if let Ok(path) = env::var("REUPS_CACHE_DIR") {
+        return Some(PathBuf::from(path).join("remote"));
+    }
+    app_dirs::get_app_dir(app_dirs::AppDataType::UserCache, &APP_INFO, "remote").ok()
+}
+
+/// Returns the path of the overlay file holding products declared with declare --overlay, which
+/// is set with the REUPS_OVERLAY environment variable, or is otherwise a file for the current user
+/// in the temporary directory
+pub fn get_overlay_path() -> PathBuf {
+    if let Ok(path) = env::var("REUPS_OVERLAY") {
+        return PathBuf::from(path);
+    }
+    env::temp_dir().join(format!("reups_overlay_{}.json", users::get_current_uid()))
+}
+
+/// Returns the directory the index caches of posix database sources are kept in, which is set with
+/// the REUPS_CACHE_DIR environment variable, or is otherwise within the user cache directory
+pub fn get_index_cache_dir() -> Option<PathBuf> {
+    if let Ok(path) = env::var("REUPS_CACHE_DIR") {
+        return Some(PathBuf::from(path));
+    }
+    app_dirs::get_app_dir(app_dirs::AppDataType::UserCache, &APP_INFO, "index").ok()
+}
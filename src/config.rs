@@ -0,0 +1,204 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ The config module reads the configuration files which control the default behavior of reups.
+ Configuration is layered, a site wide file is read first, followed by a file for the current
+ user, and finally the `[config]` table of the `.reups.toml` project file found in the current
+ directory or any of its parents. Each layer overrides the options set in the layers before it,
+ except for database sources which accumulate across all layers.
+
+ The site file is named `reups.toml` and is located in the shared configuration directory for
+ the platform, or at the path given by the REUPS_SITE_CONFIG environment variable. The user file
+ is also named `reups.toml` and is located in the user configuration directory for the platform,
+ or at the path given by the REUPS_USER_CONFIG environment variable.
+
+ An example configuration file:
+
+ ```toml
+ # Tags to use when setting up products if none are given on the command line
+ tags = ["stable"]
+ # Source declare writes to if none is given on the command line
+ declare_source = "team"
+ # Flavor to use in place of the one detected for this system
+ flavor = "Linux64"
+ # Shell syntax setup output is written in, one of sh, csh, or fish
+ shell = "sh"
+ # Regular expression used to extract identities from the versions in posix sources, if it
+ # contains a group named ident, only the text matched by that group is used
+ ident_regex = "g(?P<ident>[0-9a-f]{7,})"
+ # Set to false to disable loading sources from system environment variables or user locations
+ system = true
+ user = true
+ # Number of threads used to read each posix source, defaults to the available parallelism
+ workers = 8
+ # Set to true to create an env store without prompting if none exists, as with env --yes
+ create_env_store = true
+
+ # Additional database sources, a higher priority source is preferred when the same
+ # version is found in more than one source. Sources added with -Z have a priority of 100,
+ # configured sources 0, the user sources -100, and the system sources -200 unless set below
+ [[source]]
+ name = "team"
+ path = "/software/team/reups_db.json"
+ priority = 10
+
+ # Posix sources may use their own expression to extract identities
+ [[source]]
+ name = "stack"
+ path = "/software/stack"
+ ident_regex = "(?P<ident>[0-9a-f]{40})$"
+
+ # Priorities for the sources reups finds by default
+ [priority]
+ posix_user = 5
+ ```
+*/
+
+use crate::cogs;
+use crate::project;
+use fnv::FnvHashMap;
+use serde_derive::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the site and user configuration files
+pub const CONFIG_FILE_NAME: &str = "reups.toml";
+
+lazy_static::lazy_static! {
+    // The configuration is read once per execution, from the directory reups was run in
+    static ref CONFIG: Result<Config, String> = Config::load();
+}
+
+/// A database source to add in addition to those reups finds by default
+#[derive(Deserialize, Debug, Clone)]
+pub struct SourceConfig {
+    pub name: Option<String>,
+    pub path: String,
+    #[serde(default)]
+    pub priority: i32,
+    pub ident_regex: Option<String>,
+}
+
+/// The options that may be set in a configuration file
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub tags: Vec<String>,
+    pub declare_source: Option<String>,
+    pub flavor: Option<String>,
+    pub shell: Option<String>,
+    pub ident_regex: Option<String>,
+    pub system: Option<bool>,
+    pub user: Option<bool>,
+    pub workers: Option<usize>,
+    pub create_env_store: Option<bool>,
+    pub source: Vec<SourceConfig>,
+    pub priority: FnvHashMap<String, i32>,
+}
+
+/// Returns the configuration for this execution of reups, loading it the first time it is used
+pub fn get_config() -> Result<&'static Config, String> {
+    match CONFIG.as_ref() {
+        Ok(config) => Ok(config),
+        Err(msg) => Err(msg.clone()),
+    }
+}
+
+/// Returns the location of a configuration file, either from the supplied environment variable,
+/// or within the app directory of the given type
+fn config_path(env_var: &str, data_type: app_dirs::AppDataType) -> Option<PathBuf> {
+    if let Ok(path) = env::var(env_var) {
+        return Some(PathBuf::from(path));
+    }
+    let mut path = app_dirs::get_app_root(data_type, &cogs::APP_INFO).ok()?;
+    path.push(CONFIG_FILE_NAME);
+    Some(path)
+}
+
+impl Config {
+    /// Reads all the configuration layers, and merges them together
+    pub fn load() -> Result<Config, String> {
+        let mut config = Config::default();
+        let layers = vec![
+            config_path("REUPS_SITE_CONFIG", app_dirs::AppDataType::SharedConfig),
+            config_path("REUPS_USER_CONFIG", app_dirs::AppDataType::UserConfig),
+        ];
+        for path in layers.into_iter().flatten() {
+            if path.is_file() {
+                crate::debug!("Loading configuration from {:?}", path);
+                config.merge(Config::from_file(&path)?);
+            }
+        }
+        if let Ok(cwd) = env::current_dir() {
+            if let Some(project_path) = project::find_project_file(&cwd) {
+                let project_file = project::ProjectFile::from_file(&project_path)?;
+                let mut project_config = project_file.config;
+                // Sources in a project are relative to the directory containing the project file
+                let root = project_path
+                    .parent()
+                    .expect("Problem finding directory containing project file");
+                for source in project_config.source.iter_mut() {
+                    source.path = root.join(&source.path).to_string_lossy().to_string();
+                }
+                crate::debug!("Loading configuration from {:?}", project_path);
+                config.merge(project_config);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads and parses a single configuration file located at the supplied path
+    pub fn from_file(path: &Path) -> Result<Config, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Problem reading config file {:?}: {}", path, e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Problem parsing config file {:?}: {}", path, e))
+    }
+
+    /// Merges another configuration layer into this one, with the options in other taking
+    /// precedence. Sources and priorities are combined.
+    pub fn merge(&mut self, other: Config) {
+        if !other.tags.is_empty() {
+            self.tags = other.tags;
+        }
+        if other.declare_source.is_some() {
+            self.declare_source = other.declare_source;
+        }
+        if other.flavor.is_some() {
+            self.flavor = other.flavor;
+        }
+        if other.shell.is_some() {
+            self.shell = other.shell;
+        }
+        if other.ident_regex.is_some() {
+            self.ident_regex = other.ident_regex;
+        }
+        if other.system.is_some() {
+            self.system = other.system;
+        }
+        if other.user.is_some() {
+            self.user = other.user;
+        }
+        if other.workers.is_some() {
+            self.workers = other.workers;
+        }
+        if other.create_env_store.is_some() {
+            self.create_env_store = other.create_env_store;
+        }
+        self.source.extend(other.source);
+        self.priority.extend(other.priority);
+    }
+
+    /// Returns the flavor products should be declared and setup with, either from the
+    /// configuration or as determined for this system
+    pub fn flavor(&self) -> &str {
+        match self.flavor.as_ref() {
+            Some(flavor) => flavor.as_str(),
+            None => cogs::SYSTEM_OS,
+        }
+    }
+}
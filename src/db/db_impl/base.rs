@@ -4,9 +4,10 @@
 /// as a starting point and a way to share code. As rust does not have
 /// true inheritance, this is implemented with a factory macro that
 /// spits out structs of a given name containing all the common fields.
-/// Anywhere this struct is used, FnvHashMap must be imported.
+/// Anywhere this struct is used, FnvHashMap must be imported. The storage type must implement
+/// GroupStorage, and that trait must be imported where the default methods are used.
 macro_rules! make_db_source_struct {
-    ($name:ident, $storage:ty $(, $field:ident:$type:ty),*) => {
+    ($name:ident, $storage:ty $(, $field:ident:$type:ty)*) => {
         #[derive(Debug)]
         pub struct $name {
             pub(crate) location: PathBuf,
@@ -15,7 +16,7 @@ macro_rules! make_db_source_struct {
             pub(crate) product_to_tags: FnvHashMap<String, Vec<String>>,
             pub(crate) product_to_ident: Option<FnvHashMap<String, Vec<String>>>,
             pub(crate) product_ident_version: Option<FnvHashMap<String, FnvHashMap<String, String>>>,
-            $(pub(crate) $field:$type)*
+            $(pub(crate) $field:$type,)*
         }
     };
 }
@@ -31,11 +32,34 @@ macro_rules! make_db_source_default_methods {
         self.product_to_version_info.keys().map(|a| a.as_str()).collect()
     }
 
-    fn lookup_flavor_version(&self, product: &str, version: &str) -> Option<&str> {
-        Some(self.product_to_version_info
-            .get(product)?
-            .get(version)?
-            .get("FLAVOR")?.as_ref())
+    fn get_flavors(&self, product: &str, version: &str) -> Option<Vec<&str>> {
+        let storage = self.product_to_version_info.get(product)?.get(version)?;
+        Some(
+            (0..storage.group_count())
+                .map(|i| storage.get_group(i, "FLAVOR").unwrap_or(""))
+                .collect(),
+        )
+    }
+
+    fn get_groups(&self, product: &str, version: &str) -> Option<Vec<(&str, &str)>> {
+        let storage = self.product_to_version_info.get(product)?.get(version)?;
+        Some(
+            (0..storage.group_count())
+                .map(|i| super::group_key(storage, i))
+                .collect(),
+        )
+    }
+
+    fn get_tag_groups(&self, product: &str, tag: &str) -> Option<Vec<(&str, &str, &str)>> {
+        let storage = self.tag_to_product_info.get(tag)?.get(product)?;
+        Some(
+            (0..storage.group_count())
+                .map(|i| {
+                    let (flavor, qualifiers) = super::group_key(storage, i);
+                    (flavor, qualifiers, storage.get_group(i, "VERSION").unwrap_or(""))
+                })
+                .collect(),
+        )
     }
 
     fn get_tags(&self, product: &str) -> Option<Vec<&str>> {
@@ -69,13 +93,28 @@ macro_rules! make_db_source_default_methods {
         )
     }
 
-    fn lookup_version_tag(&self, product: &str, tag: &str) -> Option<&str> {
-        Some(
-            self.tag_to_product_info
-                .get(tag)?
-                .get(product)?
-                .get("VERSION")?,
-        )
+    fn lookup_flavor_version(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        let storage = self.product_to_version_info.get(product)?.get(version)?;
+        let index = super::find_group(storage, flavor, qualifiers)?;
+        Some(storage.get_group(index, "FLAVOR").unwrap_or(""))
+    }
+
+    fn lookup_version_tag(
+        &self,
+        product: &str,
+        tag: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        let storage = self.tag_to_product_info.get(tag)?.get(product)?;
+        let index = super::find_group(storage, flavor, qualifiers)?;
+        storage.get_group(index, "VERSION")
     }
 
     fn lookup_version_ident(&self, product: &str, ident: &str) -> Option<&str> {
@@ -0,0 +1,283 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/**
+ * http_db_impl is a read only backend database source for the main DB class. It loads a json
+ * source published on a web server, given by its url.
+ *
+ * The last copy of each source which was fetched is kept in a local cache along with its ETag.
+ * Later loads send the ETag to the server, which only sends the source again if it changed. If
+ * the server can not be reached, the cached copy is used, so a source which has been loaded once
+ * keeps working offline. The product directories recorded in a remote source should be absolute
+ * paths on a local or mounted file system, as the source itself has no directory.
+ **/
+use super::JsonDBImpl;
+use super::PathBuf;
+use super::Table;
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long to wait while connecting to the server before falling back to the cached copy
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the server to send more of a source before giving up on it
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Database backend source that reads a json source served over http
+#[derive(Debug)]
+pub struct HttpDBImpl {
+    inner: JsonDBImpl,
+}
+
+/// The json of a source and its ETag, as fetched from the server or read from the cache
+struct Fetched {
+    contents: String,
+    etag: Option<String>,
+}
+
+/// Returns the paths of the cached copy of the source at url and of its ETag, which are named by
+/// a hash of the url
+fn cache_paths(cache_dir: &Path, url: &str) -> (PathBuf, PathBuf) {
+    let mut hasher = Sha1::new();
+    hasher.input_str(url);
+    let name = hasher.result_str();
+    (
+        cache_dir.join(format!("{}.json", name)),
+        cache_dir.join(format!("{}.etag", name)),
+    )
+}
+
+/// Reads the cached copy of the source at url, if there is one
+fn read_cache(cache_dir: &Path, url: &str) -> Option<Fetched> {
+    let (json_path, etag_path) = cache_paths(cache_dir, url);
+    Some(Fetched {
+        contents: fs::read_to_string(json_path).ok()?,
+        etag: fs::read_to_string(etag_path).ok(),
+    })
+}
+
+/// Writes a copy of the source at url into the cache. Each file is written to a temporary file
+/// which is then moved into place, so that other processes never read a partially written copy
+fn write_cache(cache_dir: &Path, url: &str, fetched: &Fetched) -> std::io::Result<()> {
+    let (json_path, etag_path) = cache_paths(cache_dir, url);
+    fs::create_dir_all(cache_dir)?;
+    let write = |path: &Path, contents: &str| {
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp, contents)?;
+        fs::rename(&temp, path)
+    };
+    // The ETag is removed first, so a failure part way through never pairs an old ETag with a
+    // new copy
+    if etag_path.exists() {
+        fs::remove_file(&etag_path)?;
+    }
+    write(&json_path, &fetched.contents)?;
+    if let Some(etag) = fetched.etag.as_ref() {
+        write(&etag_path, etag)?;
+    }
+    Ok(())
+}
+
+/// Fetches the source at url from the server. If there is a cached copy its ETag is sent, and None
+/// is returned if the server reports the source has not changed since.
+fn fetch(url: &str, cached: Option<&Fetched>) -> Result<Option<Fetched>, String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build();
+    let mut request = agent.get(url);
+    if let Some(etag) = cached.and_then(|x| x.etag.as_ref()) {
+        request = request.set("If-None-Match", etag);
+    }
+    let response = request.call().map_err(|e| e.to_string())?;
+    if response.status() == 304 {
+        crate::debug!("Remote source {} has not changed", url);
+        return Ok(None);
+    }
+    let etag = response.header("ETag").map(|x| x.to_string());
+    let mut contents = String::new();
+    response
+        .into_reader()
+        .read_to_string(&mut contents)
+        .map_err(|e| e.to_string())?;
+    Ok(Some(Fetched { contents, etag }))
+}
+
+impl HttpDBImpl {
+    /// Creates a new HttpDBImpl from the json source at url, cached in the remote cache directory
+    pub fn new(url: &str) -> Result<HttpDBImpl, String> {
+        HttpDBImpl::new_with_cache(url, crate::cogs::get_remote_cache_dir().as_deref())
+    }
+
+    /// Creates a new HttpDBImpl from the json source at url, cached in the supplied directory.
+    /// Without a cache directory the source is fetched every time, and can not be used offline.
+    pub fn new_with_cache(url: &str, cache_dir: Option<&Path>) -> Result<HttpDBImpl, String> {
+        let cached = cache_dir.and_then(|x| read_cache(x, url));
+        let (fetched, changed) = match (fetch(url, cached.as_ref()), cached) {
+            (Ok(Some(fetched)), _) => (fetched, true),
+            (Ok(None), Some(cached)) => (cached, false),
+            // Only a request made with a cached etag should be told the source has not changed
+            (Ok(None), None) => {
+                return Err(format!(
+                    "Problem fetching remote source {}: not modified, but there is no cached copy",
+                    url
+                ))
+            }
+            (Err(e), Some(cached)) => {
+                crate::warn!(
+                    "Problem fetching remote source {}: {}, using the cached copy",
+                    url,
+                    e
+                );
+                (cached, false)
+            }
+            (Err(e), None) => return Err(format!("Problem fetching remote source {}: {}", url, e)),
+        };
+
+        let mut inner: JsonDBImpl = serde_json::from_str(&fetched.contents)
+            .map_err(|e| format!("Problem reading remote source {}: {}", url, e))?;
+        inner.location = PathBuf::from(url);
+        // The cache is only updated once the source is known to be valid
+        if let (true, Some(cache_dir)) = (changed, cache_dir) {
+            if let Err(e) = write_cache(cache_dir, url, &fetched) {
+                crate::warn!("Problem caching remote source {}: {}", url, e);
+            }
+        }
+        Ok(HttpDBImpl { inner })
+    }
+}
+
+// Implement the trait to make HttpDBImpl a database source, lookups use the in memory json
+// representation and anything that writes to the source is refused
+impl super::DBImpl for HttpDBImpl {
+    fn get_location(&self) -> &super::PathBuf {
+        self.inner.get_location()
+    }
+
+    fn get_table(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<Table> {
+        self.inner.get_table(product, version, flavor, qualifiers)
+    }
+
+    fn get_tags(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_tags(product)
+    }
+
+    fn get_versions(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_versions(product)
+    }
+
+    fn get_products(&self) -> Vec<&str> {
+        self.inner.get_products()
+    }
+
+    fn get_identities(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_identities(product)
+    }
+
+    fn get_flavors(&self, product: &str, version: &str) -> Option<Vec<&str>> {
+        self.inner.get_flavors(product, version)
+    }
+
+    fn get_groups(&self, product: &str, version: &str) -> Option<Vec<(&str, &str)>> {
+        self.inner.get_groups(product, version)
+    }
+
+    fn get_tag_groups(&self, product: &str, tag: &str) -> Option<Vec<(&str, &str, &str)>> {
+        self.inner.get_tag_groups(product, tag)
+    }
+
+    fn lookup_flavor_version(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.inner
+            .lookup_flavor_version(product, version, flavor, qualifiers)
+    }
+
+    fn lookup_version_tag(
+        &self,
+        product: &str,
+        tag: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.inner
+            .lookup_version_tag(product, tag, flavor, qualifiers)
+    }
+
+    fn lookup_version_ident(&self, product: &str, ident: &str) -> Option<&str> {
+        self.inner.lookup_version_ident(product, ident)
+    }
+
+    fn lookup_location_version(&self, product: &str, version: &str) -> Option<&PathBuf> {
+        self.inner.lookup_location_version(product, version)
+    }
+
+    fn has_identity(&self, product: &str, ident: &str) -> bool {
+        self.inner.has_identity(product, ident)
+    }
+
+    fn has_product(&self, product: &str) -> bool {
+        self.inner.has_product(product)
+    }
+
+    fn identities_populated(&self) -> bool {
+        self.inner.identities_populated()
+    }
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    /// Checks that the product directories recorded exist, that tables depend only on declared
+    /// products, and that each tag points at a declared version
+    fn check(&self, is_declared: &dyn Fn(&str) -> bool) -> Vec<super::CheckProblem> {
+        self.inner.check(is_declared)
+    }
+
+    /// Reports the repairs fix would make to the source
+    fn plan_fix(&self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        self.inner.plan_fix(paths)
+    }
+
+    /// Repairs can only be made to the source a remote source is published from
+    fn fix(&mut self, _paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        Err(format!(
+            "Remote source {:?} is read only, fix the source it is published from",
+            self.get_location()
+        ))
+    }
+
+    fn declare_in_memory_impl(
+        &mut self,
+        _inputs: &Vec<super::DeclareInputs>,
+    ) -> Result<(), String> {
+        Err(format!(
+            "Remote source {:?} is read only, products can not be declared in it",
+            self.get_location()
+        ))
+    }
+
+    fn sync(&self, product: &str) -> std::io::Result<()> {
+        Err(std::io::Error::other(format!(
+            "Remote source {:?} is read only, {} can not be written to it",
+            self.get_location(),
+            product
+        )))
+    }
+}
@@ -0,0 +1,279 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ An index cache keeps the parsed contents of all the files in a posix database source in a
+ single binary file, so that later invocations do not need to walk the product directories of
+ the source and read each of their files.
+
+ Each product in the cache records the modification times of its directory and of each of its
+ files at the time they were read. A product is only taken from the cache while all of those times
+ are unchanged. The time of the directory changes when files are added to or removed from it, and
+ the time of a file changes when it is edited in place, as eups does when tagging. Products which
+ are no longer in the source are dropped from the cache. reups also removes the cache of a source
+ whenever it writes to it.
+*/
+use super::DBFile;
+use super::FnvHashMap;
+use crate::db::dbfile::Contents;
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use fnv::FnvHashSet;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Changed whenever the layout of the cache changes, so that caches written by other versions of
+/// reups are rebuilt instead of misread
+const CACHE_VERSION: u32 = 2;
+
+/// Modification time of a directory or file, as seconds and nanoseconds since the unix epoch
+pub(crate) type Modified = (u64, u32);
+
+/// Modification times of the files of products, keyed by product and file name
+pub(crate) type FileTimes = FnvHashMap<(String, String), Modified>;
+
+/// Mapping of a name (a product or a tag) to a mapping of a name to the file which describes it
+type FileMap = FnvHashMap<String, FnvHashMap<String, DBFile>>;
+
+/// Parsed files of one product directory, and the modification times of the directory and of
+/// each file when they were read
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CachedProduct {
+    modified: Modified,
+    versions: Vec<(String, Modified, Contents)>,
+    tags: Vec<(String, Modified, Contents)>,
+}
+
+impl CachedProduct {
+    /// Returns the names of the files of the product along with their modification times
+    fn files(&self) -> impl Iterator<Item = (String, &Modified)> {
+        let versions = self
+            .versions
+            .iter()
+            .map(|(version, modified, _)| (format!("{}.version", version), modified));
+        let tags = self
+            .tags
+            .iter()
+            .map(|(tag, modified, _)| (format!("{}.chain", tag), modified));
+        versions.chain(tags)
+    }
+}
+
+/// Parsed contents of a posix database source, keyed by product
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct IndexCache {
+    version: u32,
+    location: PathBuf,
+    products: FnvHashMap<String, CachedProduct>,
+}
+
+/// Returns the modification time of the directory or file at path
+pub(crate) fn modified(path: &Path) -> Option<Modified> {
+    let since = fs::metadata(path)
+        .ok()?
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?;
+    Some((since.as_secs(), since.subsec_nanos()))
+}
+
+/// Sources are identified by their full path, so the same source given by different relative paths
+/// shares a cache
+fn full_location(location: &Path) -> PathBuf {
+    location
+        .canonicalize()
+        .unwrap_or_else(|_| location.to_path_buf())
+}
+
+/// Returns the path of the cache file for the source at location, which is named by a hash of the
+/// path of the source
+fn index_path(cache_dir: &Path, location: &Path) -> PathBuf {
+    let mut hasher = Sha1::new();
+    hasher.input_str(full_location(location).to_str().unwrap_or_default());
+    cache_dir.join(format!("{}.index", hasher.result_str()))
+}
+
+impl IndexCache {
+    /// Reads the cache of the source at location out of the cache directory. An empty cache is
+    /// returned if there is none, or if it can not be read
+    pub(crate) fn load(cache_dir: &Path, location: &Path) -> IndexCache {
+        let location = full_location(location);
+        let cache = fs::read(index_path(cache_dir, &location))
+            .ok()
+            .and_then(|bytes| bincode::deserialize::<IndexCache>(&bytes).ok());
+        match cache {
+            Some(cache) if cache.version == CACHE_VERSION && cache.location == location => cache,
+            _ => IndexCache {
+                version: CACHE_VERSION,
+                location,
+                products: FnvHashMap::default(),
+            },
+        }
+    }
+
+    /// Returns the number of products in the cache
+    pub(crate) fn product_count(&self) -> usize {
+        self.products.len()
+    }
+
+    /// Returns true if neither the directory of a product, nor any of its files, have changed
+    /// since it was cached. The modification time of the directory is supplied, and each of the
+    /// files in product_dir is checked
+    pub(crate) fn is_current(
+        &self,
+        product_dir: &Path,
+        product: &str,
+        modified: Option<Modified>,
+    ) -> bool {
+        let cached = match (self.products.get(product), modified) {
+            (Some(cached), Some(modified)) if cached.modified == modified => cached,
+            _ => return false,
+        };
+        cached
+            .files()
+            .all(|(name, time)| self::modified(&product_dir.join(name)).as_ref() == Some(time))
+    }
+
+    /// Moves the files of the current products out of the cache, into the maps of the source at
+    /// location. The modification times of the products are returned, the times of their files
+    /// are added to file_times, and products which are not current are discarded
+    pub(crate) fn restore(
+        &mut self,
+        location: &Path,
+        current: &FnvHashSet<String>,
+        product_to_info: &mut FileMap,
+        tags_to_info: &mut FileMap,
+        product_to_tags: &mut FnvHashMap<String, Vec<String>>,
+        file_times: &mut FileTimes,
+    ) -> FnvHashMap<String, Modified> {
+        let mut modified = FnvHashMap::default();
+        for (product, cached) in self.products.drain() {
+            if !current.contains(&product) {
+                continue;
+            }
+            let product_dir = location.join(&product);
+            for (version, time, contents) in cached.versions {
+                let name = format!("{}.version", version);
+                let path = product_dir.join(&name);
+                file_times.insert((product.clone(), name), time);
+                product_to_info
+                    .entry(product.clone())
+                    .or_default()
+                    .insert(version, DBFile::new_with_parsed(path, contents));
+            }
+            for (tag, time, contents) in cached.tags {
+                let name = format!("{}.chain", tag);
+                let path = product_dir.join(&name);
+                file_times.insert((product.clone(), name), time);
+                product_to_tags
+                    .entry(product.clone())
+                    .or_default()
+                    .push(tag.clone());
+                tags_to_info
+                    .entry(tag)
+                    .or_default()
+                    .insert(product.clone(), DBFile::new_with_parsed(path, contents));
+            }
+            modified.insert(product, cached.modified);
+        }
+        modified
+    }
+
+    /// Replaces the contents of the cache with the files of a source, recording the modification
+    /// times of each product directory and of its files. Products with a file whose time is not
+    /// known are left out, so that they are read again the next time
+    pub(crate) fn fill(
+        &mut self,
+        modified: FnvHashMap<String, Modified>,
+        file_times: &FileTimes,
+        product_to_info: &FileMap,
+        tags_to_info: &FileMap,
+    ) {
+        let time = |product: &str, name: String| file_times.get(&(product.to_string(), name));
+        let mut incomplete = FnvHashSet::default();
+        self.products = modified
+            .into_iter()
+            .map(|(product, modified)| {
+                let mut versions = vec![];
+                for (version, file) in product_to_info.get(&product).into_iter().flatten() {
+                    match time(&product, format!("{}.version", version)) {
+                        Some(time) => versions.push((version.clone(), *time, file.parsed())),
+                        None => {
+                            incomplete.insert(product.clone());
+                        }
+                    }
+                }
+                let cached = CachedProduct {
+                    modified,
+                    versions,
+                    tags: vec![],
+                };
+                (product, cached)
+            })
+            .collect();
+        for (tag, products) in tags_to_info.iter() {
+            for (product, file) in products.iter() {
+                if let Some(cached) = self.products.get_mut(product) {
+                    match time(product, format!("{}.chain", tag)) {
+                        Some(time) => cached.tags.push((tag.clone(), *time, file.parsed())),
+                        None => {
+                            incomplete.insert(product.clone());
+                        }
+                    }
+                }
+            }
+        }
+        self.products
+            .retain(|product, _| !incomplete.contains(product));
+    }
+
+    /// Writes the cache into the cache directory. The cache is written to a temporary file which
+    /// is then moved into place, so that other processes never read a partially written cache
+    pub(crate) fn save(&self, cache_dir: &Path) -> Result<(), String> {
+        let path = index_path(cache_dir, &self.location);
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let bytes = bincode::serialize(self)
+            .map_err(|e| format!("Problem serializing index cache: {}", e))?;
+        fs::create_dir_all(cache_dir)
+            .and_then(|_| fs::write(&temp, bytes))
+            .and_then(|_| fs::rename(&temp, &path))
+            .map_err(|e| format!("Problem writing index cache {:?}: {}", path, e))
+    }
+}
+
+/// Removes the cache of the source at location, so that it is rebuilt the next time the source is
+/// read
+pub(crate) fn remove_index(cache_dir: &Path, location: &Path) {
+    let path = index_path(cache_dir, location);
+    if path.exists() {
+        crate::debug!("Removing index cache {:?}", path);
+        if let Err(e) = fs::remove_file(&path) {
+            crate::warn!("Problem removing index cache {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Removes the caches of all sources from the cache directory, returning the number which were
+/// removed
+pub fn clear_index_cache(cache_dir: &Path) -> Result<usize, String> {
+    if !cache_dir.exists() {
+        return Ok(0);
+    }
+    let entries = fs::read_dir(cache_dir)
+        .map_err(|e| format!("Problem reading cache directory {:?}: {}", cache_dir, e))?;
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension() == Some(std::ffi::OsStr::new("index")) {
+            fs::remove_file(&path)
+                .map_err(|e| format!("Problem removing index cache {:?}: {}", path, e))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
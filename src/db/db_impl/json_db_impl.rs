@@ -7,8 +7,8 @@
  * json_db_impl is a backend database source for the main DB class. It
  * stores all of the information about products in a single file.
  **/
-use super::DBImpl;
 use super::FnvHashMap;
+use super::GroupStorage;
 use super::PathBuf;
 use super::Table;
 use fs2::FileExt;
@@ -49,7 +49,7 @@ impl TableDepJson {
 
 /// Structure to represent a table on disk
 #[derive(Serialize, Deserialize, Debug)]
-struct TableInfoJson {
+pub(crate) struct TableInfoJson {
     exact: TableDepJson,
     inexact: TableDepJson,
     env: FnvHashMap<String, (crate::db::table::EnvActionType, String)>,
@@ -63,12 +63,85 @@ impl TableInfoJson {
             env: FnvHashMap::default(),
         }
     }
+
+    /// Converts an in memory table into the form stored on disk, replacing the product directory
+    /// in its environment actions with a placeholder
+    pub(crate) fn from_table(in_memory_table: Table) -> TableInfoJson {
+        let mut new_table = TableInfoJson::new();
+        match in_memory_table.exact {
+            Some(deps) => {
+                new_table.exact = TableDepJson {
+                    required: deps.required.clone(),
+                    optional: deps.optional.clone(),
+                };
+            }
+            None => {
+                new_table.exact = TableDepJson::new();
+            }
+        }
+        match in_memory_table.inexact {
+            Some(deps) => {
+                new_table.inexact = TableDepJson {
+                    required: deps.required.clone(),
+                    optional: deps.optional.clone(),
+                };
+            }
+            None => {
+                new_table.inexact = TableDepJson::new();
+            }
+        }
+        let mut env_var_new = FnvHashMap::default();
+        for (k, (t, p)) in in_memory_table.env_var {
+            let new_p = p.replace(
+                in_memory_table.product_dir.to_str().unwrap(),
+                "${PRODUCT_DIR}",
+            );
+            env_var_new.insert(k.clone(), (t.clone(), new_p));
+        }
+        new_table.env = env_var_new;
+        new_table
+    }
+
+    /// Converts the on disk form of the table of a product back into an in memory table, the
+    /// placeholders in the environment actions are replaced when the table is looked up
+    pub(crate) fn into_table(self, name: String, product_dir: PathBuf) -> Table {
+        super::Table {
+            name,
+            path: None,
+            product_dir,
+            exact: Some(super::table::Deps {
+                required: self.exact.required,
+                optional: self.exact.optional,
+            }),
+            inexact: Some(super::table::Deps {
+                required: self.inexact.required,
+                optional: self.inexact.optional,
+            }),
+            env_var: self.env,
+        }
+    }
 }
 
-// Database backend source that stores data in a single json file
+/// The information for one flavor of a product version or tag
+type GroupInfo = FnvHashMap<String, String>;
+
+/// The repairs fix makes to a json source, and the changes which make them
+struct FixPlan {
+    repairs: Vec<super::Repair>,
+    // Groups of tags to remove, as (tag, product, group index) from the last index to the first
+    dangling: Vec<(String, String, usize)>,
+    // Identities to create, keyed by (product, version)
+    created: FnvHashMap<(String, String), String>,
+    // Values to set in the groups of versions, as (product, version, group index, key, value)
+    changes: Vec<(String, String, usize, &'static str, String)>,
+}
+
+// Database backend source that stores data in a single json file. Each version and tag holds a
+// group of information per flavor, and the tables of a version are in the same order as its
+// groups
 make_db_source_struct!(JsonDBImpl,
-                      FnvHashMap<String, String>,
-                      product_to_version_table: FnvHashMap<String, FnvHashMap<String, Table>>);
+                      Vec<GroupInfo>,
+                      product_to_version_table: FnvHashMap<String, FnvHashMap<String, Vec<Table>>>);
 
 impl JsonDBImpl {
     /// Creates a new empty JsonDBImpl instance, which will be stored at the location provided
@@ -113,6 +186,385 @@ impl JsonDBImpl {
     }
 
     pub fn update_paths(&mut self) {}
+
+    /// Converts Json database backend into a Posix based database backend source, whose ups_db
+    /// directory is the supplied location. Relative paths are kept if both sources are in the
+    /// same directory, and are made absolute otherwise. If tables is true the tables of this
+    /// source are kept in the ups_db directory of the new source, otherwise the tables recorded in
+    /// the product directories are used. Nothing is written to disk until products are synced.
+    pub fn to_posix(&self, loc: &std::path::Path, tables: bool) -> super::PosixDBImpl {
+        let old_base = self
+            .location
+            .parent()
+            .expect("Problem finding json db location parent");
+        let new_base = loc.parent().unwrap_or(loc);
+        let same_base = match (old_base.canonicalize(), new_base.canonicalize()) {
+            (Ok(old), Ok(new)) => old == new,
+            _ => old_base == new_base,
+        };
+        let rebase = |path: &str| -> String {
+            if same_base || std::path::Path::new(path).is_absolute() {
+                return path.to_string();
+            }
+            let joined = old_base.join(path);
+            let absolute = joined.canonicalize().unwrap_or(joined);
+            absolute.to_str().unwrap().to_string()
+        };
+
+        let mut product_to_version_info: FnvHashMap<String, FnvHashMap<String, super::DBFile>> =
+            FnvHashMap::default();
+        let mut table_cache: FnvHashMap<(String, String, usize), Table> = FnvHashMap::default();
+        for (product, versions) in self.product_to_version_info.iter() {
+            for (version, groups) in versions.iter() {
+                let mut dbfile = super::DBFile::new_with_contents(
+                    loc.join(product).join(format!("{}.version", version)),
+                    format!("PRODUCT = {}\nVERSION = {}\n", product, version),
+                );
+                for (index, group) in groups.iter().enumerate() {
+                    let mut group = group.clone();
+                    if let Some(prod_dir) = group.get("PROD_DIR").map(|x| rebase(x)) {
+                        group.insert("PROD_DIR".to_string(), prod_dir);
+                    }
+                    // Table files named without a directory are relative to the ups directory
+                    if let Some(table_file) = group
+                        .get("TABLE_FILE")
+                        .filter(|x| x.contains('/'))
+                        .map(|x| rebase(x))
+                    {
+                        group.insert("TABLE_FILE".to_string(), table_file);
+                    }
+                    let table = self
+                        .product_to_version_table
+                        .get(product)
+                        .and_then(|x| x.get(version))
+                        .and_then(|x| x.get(index));
+                    if let (true, Some(table)) = (tables, table) {
+                        let (flavor, qualifiers) = super::group_key(groups, index);
+                        let group_dir = if qualifiers.is_empty() {
+                            flavor.to_string()
+                        } else {
+                            format!("{}-{}", flavor, qualifiers.replace(':', "-"))
+                        };
+                        let ups_dir = loc.join(product).join(version).join(group_dir);
+                        let table_file = ups_dir.join(format!("{}.table", product));
+                        group.insert("UPS_DIR".to_string(), ups_dir.to_str().unwrap().to_string());
+                        group.insert(
+                            "TABLE_FILE".to_string(),
+                            table_file.to_str().unwrap().to_string(),
+                        );
+                        table_cache
+                            .insert((product.clone(), version.clone(), index), table.clone());
+                    }
+                    dbfile.push_group(group);
+                }
+                product_to_version_info
+                    .entry(product.clone())
+                    .or_default()
+                    .insert(version.clone(), dbfile);
+            }
+        }
+
+        let mut tag_to_product_info: FnvHashMap<String, FnvHashMap<String, super::DBFile>> =
+            FnvHashMap::default();
+        for (tag, products) in self.tag_to_product_info.iter() {
+            for (product, groups) in products.iter() {
+                let mut dbfile = super::DBFile::new_with_contents(
+                    loc.join(product).join(format!("{}.chain", tag)),
+                    format!("PRODUCT = {}\nCHAIN = {}\n", product, tag),
+                );
+                for group in groups.iter() {
+                    dbfile.push_group(group.clone());
+                }
+                tag_to_product_info
+                    .entry(tag.clone())
+                    .or_default()
+                    .insert(product.clone(), dbfile);
+            }
+        }
+
+        // Return new Posix database source, identities can not be stored in posix sources so
+        // those of this source are only kept in memory
+        super::PosixDBImpl {
+            location: loc.to_path_buf(),
+            tag_to_product_info,
+            product_to_version_info,
+            product_to_tags: self.product_to_tags.clone(),
+            product_to_ident: self.product_to_ident.clone(),
+            product_ident_version: self.product_ident_version.clone(),
+            table_cache: std::cell::RefCell::new(table_cache),
+            index_cache: crate::cogs::get_index_cache_dir(),
+        }
+    }
+
+    /// Returns the identity for an input declared without one. A version which is already
+    /// declared, and is only being tagged or given another flavor, keeps its identity, otherwise
+    /// one is made by hashing the product directory and table of the input
+    fn generate_ident(&self, input: &super::DeclareInputs) -> String {
+        let existing = self
+            .product_ident_version
+            .as_ref()
+            .and_then(|x| x.get(input.product))
+            .and_then(|x| x.iter().find(|(_, v)| v.as_str() == input.version));
+        if let Some((id, _)) = existing {
+            return id.clone();
+        }
+        let prod_dir = if input.relative {
+            input.prod_dir.clone()
+        } else {
+            input
+                .prod_dir
+                .canonicalize()
+                .unwrap_or_else(|_| input.prod_dir.clone())
+        };
+        let table_file = match (input.table.as_ref(), input.table_file) {
+            (Some(table), _) => table.path.clone(),
+            (None, Some("none")) => None,
+            (None, Some(file)) => Some(PathBuf::from(file)),
+            (None, None) => Some(
+                prod_dir
+                    .join(input.ups_dir.unwrap_or("ups"))
+                    .join(format!("{}.table", input.product)),
+            ),
+        };
+        let ident = super::content_ident(
+            input.product,
+            input.version,
+            &prod_dir,
+            table_file.as_deref(),
+        );
+        crate::info!(
+            "Generated identity {} for product {} version {}",
+            ident,
+            input.product,
+            input.version
+        );
+        ident
+    }
+
+    /// Returns the number of Versions and Tables entries in the file on disk, if it can be read
+    fn file_entry_counts(&self) -> Option<(usize, usize)> {
+        let contents = fs::read_to_string(&self.location).ok()?;
+        let raw: serde_json::Value = serde_json::from_str(&contents).ok()?;
+        let count = |key| raw[key].as_array().map_or(0, |x| x.len());
+        Some((count("Versions"), count("Tables")))
+    }
+
+    /// Writes the whole source out to its location, replacing what is on disk
+    fn write_file(&self) -> std::io::Result<()> {
+        let serialized = serde_json::to_string_pretty(self)?;
+        let mut json_file = fs::OpenOptions::new().write(true).open(&self.location)?;
+        json_file.try_lock_exclusive()?;
+        json_file.set_len(0)?;
+        json_file.write_all(serialized.as_bytes())?;
+        json_file.unlock()
+    }
+
+    /// Returns the table for the group at the given index of a product version, with paths
+    /// expanded relative to the location of this source
+    pub(crate) fn get_group_table(
+        &self,
+        product: &str,
+        version: &str,
+        index: usize,
+    ) -> Option<Table> {
+        let mut table = self
+            .product_to_version_table
+            .get(product)?
+            .get(version)?
+            .get(index)?
+            .clone();
+        if table.product_dir.is_relative() {
+            // The table is kept in the source, so it is still returned if the product directory
+            // no longer exists
+            let product_dir = self
+                .location
+                .parent()
+                .expect("Problem finding json db location parent")
+                .join(table.product_dir);
+            table.product_dir = product_dir.canonicalize().unwrap_or(product_dir);
+        }
+        for (_, entry) in &mut table.env_var {
+            entry.1 = entry.1.replace(
+                "${PRODUCT_DIR}",
+                table
+                    .product_dir
+                    .to_str()
+                    .expect("convert table product_dir to stri"),
+            );
+        }
+        Some(table)
+    }
+
+    /// Finds the repairs fix makes to this source, along with the changes which make them
+    fn plan_fix_changes(&self, paths: Option<super::PathStyle>) -> FixPlan {
+        use super::Repair;
+        let base = self
+            .location
+            .parent()
+            .expect("Problem finding json db location parent")
+            .to_path_buf();
+        let mut repairs = vec![];
+
+        let mut dangling =
+            super::dangling_tags(&self.tag_to_product_info, &self.product_to_version_info);
+        // Remove the dangling groups from the end so the indices stay valid
+        dangling.sort_unstable_by_key(|x| std::cmp::Reverse(x.2));
+        for (tag, product, index) in dangling.iter() {
+            let group = &self.tag_to_product_info[tag][product][*index];
+            repairs.push(Repair::new(
+                Some(product),
+                None,
+                group.get("FLAVOR").map(|x| x.as_str()),
+                format!(
+                    "remove tag {} which points at undeclared version {}",
+                    tag,
+                    group.get("VERSION").map_or("", |x| x.as_str())
+                ),
+            ));
+        }
+
+        // Changes to the groups of versions, as (product, version, index, key, value)
+        let mut changes = vec![];
+        // Identities created for versions without one, shared by all flavors of a version
+        let mut created: FnvHashMap<(String, String), String> = FnvHashMap::default();
+        for (product, versions) in self.product_to_version_info.iter() {
+            for (version, groups) in versions.iter() {
+                for (index, group) in groups.iter().enumerate() {
+                    let flavor = group.get("FLAVOR");
+                    // Rewrite the product directory of the group and its table
+                    let rewritten = match (paths, group.get("PROD_DIR")) {
+                        (Some(style), Some(prod_dir)) => {
+                            super::rewrite_path(&base, prod_dir, style).map(|x| (prod_dir, x))
+                        }
+                        _ => None,
+                    };
+                    if let Some((prod_dir, rewritten)) = rewritten {
+                        repairs.push(Repair::new(
+                            Some(product),
+                            Some(version),
+                            flavor.map(|x| x.as_str()),
+                            format!("rewrite product directory {} as {}", prod_dir, rewritten),
+                        ));
+                        changes.push((
+                            product.clone(),
+                            version.clone(),
+                            index,
+                            "PROD_DIR",
+                            rewritten,
+                        ));
+                    }
+
+                    // Use the identity of another flavor of the version if there is one,
+                    // otherwise create one
+                    if group.contains_key("IDENT") {
+                        continue;
+                    }
+                    let existing = self
+                        .product_ident_version
+                        .as_ref()
+                        .and_then(|x| x.get(product))
+                        .and_then(|x| x.iter().find(|(_, y)| *y == version))
+                        .map(|(ident, _)| ident.clone());
+                    let ident = match existing {
+                        Some(ident) => ident,
+                        None => created
+                            .entry((product.clone(), version.clone()))
+                            .or_insert_with(|| super::hash_ident(version))
+                            .clone(),
+                    };
+                    repairs.push(Repair::new(
+                        Some(product),
+                        Some(version),
+                        flavor.map(|x| x.as_str()),
+                        format!("create identity {}", ident),
+                    ));
+                    changes.push((product.clone(), version.clone(), index, "IDENT", ident));
+                }
+            }
+        }
+        // Entries are paired up by position when loading, so writing the source back out drops
+        // any without a partner
+        if let Some((versions, tables)) = self.file_entry_counts() {
+            if tables > versions {
+                repairs.push(Repair::new(
+                    None,
+                    None,
+                    None,
+                    format!("drop {} Tables entries with no Versions", tables - versions),
+                ));
+            } else if versions > tables {
+                repairs.push(Repair::new(
+                    None,
+                    None,
+                    None,
+                    format!("drop {} Versions entries with no Tables", versions - tables),
+                ));
+            }
+        }
+
+        FixPlan {
+            repairs,
+            dangling,
+            created,
+            changes,
+        }
+    }
+
+    /// Makes the repairs fix finds to this source in memory, without writing them out, and
+    /// returns them. Sources which keep a json source in memory use this before writing it out
+    /// in their own form
+    pub(crate) fn fix_in_memory(&mut self, paths: Option<super::PathStyle>) -> Vec<super::Repair> {
+        let FixPlan {
+            repairs,
+            dangling,
+            created,
+            changes,
+        } = self.plan_fix_changes(paths);
+        for (tag, product, index) in dangling {
+            let products = self.tag_to_product_info.get_mut(&tag).unwrap();
+            let groups = products.get_mut(&product).unwrap();
+            groups.remove(index);
+            if groups.is_empty() {
+                products.remove(&product);
+                if let Some(tags) = self.product_to_tags.get_mut(&product) {
+                    tags.retain(|x| x != &tag);
+                }
+            }
+        }
+        for ((product, version), ident) in created {
+            self.product_ident_version
+                .as_mut()
+                .unwrap()
+                .entry(product.clone())
+                .or_default()
+                .insert(ident.clone(), version);
+            self.product_to_ident
+                .as_mut()
+                .unwrap()
+                .entry(product)
+                .or_default()
+                .push(ident);
+        }
+        for (product, version, index, key, value) in changes {
+            if key == "PROD_DIR" {
+                if let Some(table) = self
+                    .product_to_version_table
+                    .get_mut(&product)
+                    .and_then(|x| x.get_mut(&version))
+                    .and_then(|x| x.get_mut(index))
+                {
+                    table.product_dir = PathBuf::from(&value);
+                }
+            }
+            self.product_to_version_info
+                .get_mut(&product)
+                .unwrap()
+                .get_mut(&version)
+                .unwrap()[index]
+                .insert(key.to_string(), value);
+        }
+        repairs
+    }
 }
 
 // Deserialize trait, used to load an object from disk
@@ -130,10 +582,12 @@ impl<'de> Deserialize<'de> for JsonDBImpl {
         // do Versions first
         for (mut version_info, table_info) in helper.versions.drain(..).zip(helper.tables.drain(..))
         {
-            // pop off the product version, and ident from the hashmap, eliminates creating copies
+            // pop off the product and version from the hashmap, eliminates creating copies. The
+            // identity is kept, as each flavor of a version may have its own. A missing identity
+            // can be created with reups db fix
             let product = version_info.remove("PRODUCT").unwrap();
             let version = version_info.remove("VERSION").unwrap();
-            let ident = version_info.remove("IDENT").unwrap();
+            let ident = version_info.get("IDENT").cloned();
             let product_dir = PathBuf::from(version_info.get("PROD_DIR").as_ref().unwrap());
             // Create a new table object to populate
             /*
@@ -143,45 +597,40 @@ impl<'de> Deserialize<'de> for JsonDBImpl {
                     .1
                     .replace("${PRODUCT_DIR}", product_dir.to_str().unwrap());
             }*/
-            let new_table = super::Table {
-                name: product.clone(),
-                path: None,
-                product_dir,
-                exact: Some(super::table::Deps {
-                    required: table_info.exact.required,
-                    optional: table_info.exact.optional,
-                }),
-                inexact: Some(super::table::Deps {
-                    required: table_info.inexact.required,
-                    optional: table_info.inexact.optional,
-                }),
-                env_var: table_info.env,
-            };
+            let new_table = table_info.into_table(product.clone(), product_dir);
             // populate the various fields of the impl struct
-            new_dbimpl
+            let idents = new_dbimpl
                 .product_to_ident
                 .as_mut()
                 .unwrap()
                 .entry(product.clone())
-                .or_insert(vec![])
-                .push(ident.clone());
-            new_dbimpl
+                .or_insert(vec![]);
+            let ident_versions = new_dbimpl
                 .product_ident_version
                 .as_mut()
                 .unwrap()
                 .entry(product.clone())
-                .or_insert(FnvHashMap::default())
-                .insert(ident, version.clone());
+                .or_insert(FnvHashMap::default());
+            if let Some(ident) = ident {
+                if !idents.contains(&ident) {
+                    idents.push(ident.clone());
+                }
+                ident_versions.insert(ident, version.clone());
+            }
             let map = new_dbimpl
                 .product_to_version_info
                 .entry(product.clone())
                 .or_insert(FnvHashMap::default());
-            map.insert(version.clone(), version_info);
+            map.entry(version.clone())
+                .or_insert(vec![])
+                .push(version_info);
             new_dbimpl
                 .product_to_version_table
                 .entry(product)
                 .or_insert(FnvHashMap::default())
-                .insert(version, new_table);
+                .entry(version)
+                .or_insert(vec![])
+                .push(new_table);
         }
         // now take care of tags
         for mut tag_info in helper.tags.drain(..) {
@@ -193,12 +642,13 @@ impl<'de> Deserialize<'de> for JsonDBImpl {
                 .tag_to_product_info
                 .entry(tag.clone())
                 .or_insert(FnvHashMap::default())
-                .insert(product.clone(), tag_info);
-            new_dbimpl
-                .product_to_tags
-                .entry(product)
+                .entry(product.clone())
                 .or_insert(vec![])
-                .push(tag);
+                .push(tag_info);
+            let tags = new_dbimpl.product_to_tags.entry(product).or_insert(vec![]);
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
         }
         Ok(new_dbimpl)
     }
@@ -228,77 +678,47 @@ impl Serialize for JsonDBImpl {
                         .unwrap()
                         .contains_key(product)
                     {
-                        // Fetch the associated tag mapping
-                        let mut tag_info = self
-                            .tag_to_product_info
-                            .get(tag)
-                            .unwrap()
-                            .get(product)
-                            .unwrap()
-                            .clone();
-                        // insert product and tag info into the mapping so the info
-                        // will be available to use in deserializing
-                        tag_info.insert("PRODUCT".to_string(), product.clone());
-                        tag_info.insert("TAG".to_string(), tag.clone());
-                        tags.push(tag_info);
+                        // Fetch the associated tag mapping for each flavor
+                        for group in self.tag_to_product_info[tag][product].iter() {
+                            let mut tag_info = group.clone();
+                            // insert product and tag info into the mapping so the info
+                            // will be available to use in deserializing
+                            tag_info.insert("PRODUCT".to_string(), product.clone());
+                            tag_info.insert("TAG".to_string(), tag.clone());
+                            tags.push(tag_info);
+                        }
                     }
                 }
             }
             // now for versions
-            for (version, version_info) in version_map {
-                // look up the identity associated with this version
-                let ident_vec: Vec<(&String, &String)> =
-                    self.product_ident_version.as_ref().unwrap()[product]
-                        .iter()
-                        .filter(|(_, y)| y.as_str() == version.as_str())
-                        .collect();
-                let (ident, _) = ident_vec[0];
-
-                // Fetch the table corresponding to this product, version from the
-                // in memory table and convert it a struct for serialization
-                let in_memory_table = self.get_table(product, version).unwrap();
-                let mut new_table = TableInfoJson::new();
-                match in_memory_table.exact {
-                    Some(deps) => {
-                        new_table.exact = TableDepJson {
-                            required: deps.required.clone(),
-                            optional: deps.optional.clone(),
-                        };
-                    }
-                    None => {
-                        new_table.exact = TableDepJson::new();
-                    }
-                }
-                match in_memory_table.inexact {
-                    Some(deps) => {
-                        new_table.inexact = TableDepJson {
-                            required: deps.required.clone(),
-                            optional: deps.optional.clone(),
-                        };
-                    }
-                    None => {
-                        new_table.inexact = TableDepJson::new();
-                    }
-                }
-                let mut env_var_new = FnvHashMap::default();
-                for (k, (t, p)) in in_memory_table.env_var {
-                    let new_p = p.replace(
-                        in_memory_table.product_dir.to_str().unwrap(),
-                        "${PRODUCT_DIR}",
-                    );
-                    env_var_new.insert(k.clone(), (t.clone(), new_p));
-                }
-                new_table.env = env_var_new;
-                tables.push(new_table);
+            for (version, groups) in version_map {
+                for (index, version_info) in groups.iter().enumerate() {
+                    // look up the identity associated with this version, if the flavor does not
+                    // record its own, and create one if the version has none at all
+                    let ident = match version_info.get("IDENT") {
+                        Some(ident) => ident.clone(),
+                        None => self.product_ident_version.as_ref().unwrap()[product]
+                            .iter()
+                            .find(|(_, y)| y.as_str() == version.as_str())
+                            .map(|(x, _)| x.clone())
+                            .unwrap_or_else(|| super::hash_ident(version)),
+                    };
+
+                    // Fetch the table corresponding to this product, version from the
+                    // in memory table and convert it a struct for serialization
+                    let in_memory_table = self.get_group_table(product, version, index).unwrap();
+                    let new_table = TableInfoJson::from_table(in_memory_table);
+                    tables.push(new_table);
 
-                // Use the version info mapping and add product, version, identity
-                // as entries so they can be used in the deserialization process
-                let mut new_version_map = version_info.clone();
-                new_version_map.insert("PRODUCT".to_string(), product.clone());
-                new_version_map.insert("VERSION".to_string(), version.clone());
-                new_version_map.insert("IDENT".to_string(), ident.clone());
+                    // Use the version info mapping and add product, version, identity
+                    // as entries so they can be used in the deserialization process
+                    let mut new_version_map = version_info.clone();
+                    new_version_map.insert("PRODUCT".to_string(), product.clone());
+                    new_version_map.insert("VERSION".to_string(), version.clone());
+                    new_version_map.insert("IDENT".to_string(), ident);
 
-                versions.push(new_version_map);
+                    versions.push(new_version_map);
+                }
             }
         }
         // create the serialization struct, and serialize it
@@ -316,31 +736,16 @@ impl super::DBImpl for JsonDBImpl {
     // Add in pre-defined methods from the base instance
     make_db_source_default_methods!();
 
-    fn get_table(&self, product: &str, version: &str) -> Option<Table> {
-        let mut table = self
-            .product_to_version_table
-            .get(product)?
-            .get(version)?
-            .clone();
-        if table.product_dir.is_relative() {
-            table.product_dir = self
-                .location
-                .parent()
-                .expect("Problem finding json db location parent")
-                .join(table.product_dir)
-                .canonicalize()
-                .expect("Problem expanding json table location to abs path");
-        }
-        for (_, entry) in &mut table.env_var {
-            entry.1 = entry.1.replace(
-                "${PRODUCT_DIR}",
-                table
-                    .product_dir
-                    .to_str()
-                    .expect("convert table product_dir to stri"),
-            );
-        }
-        Some(table)
+    fn get_table(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<Table> {
+        let groups = self.product_to_version_info.get(product)?.get(version)?;
+        let index = super::find_group(groups, flavor, qualifiers)?;
+        self.get_group_table(product, version, index)
     }
 
     fn is_writable(&self) -> bool {
@@ -360,31 +765,129 @@ impl super::DBImpl for JsonDBImpl {
         }
     }
 
+    /// Checks that the Versions and Tables of the file pair up, that the product directories
+    /// recorded exist, that tables depend only on declared products, and that each tag points
+    /// at a declared version
+    fn check(&self, is_declared: &dyn Fn(&str) -> bool) -> Vec<super::CheckProblem> {
+        use super::{CheckProblem, Severity};
+        let base = self
+            .location
+            .parent()
+            .expect("Problem finding json db location parent");
+        let mut problems =
+            super::check_tags(&self.tag_to_product_info, &self.product_to_version_info);
+        // Entries are paired up by position when loading, so any without a partner are dropped
+        if let Some((versions, tables)) = self.file_entry_counts() {
+            if versions != tables {
+                problems.push(CheckProblem::new(
+                    Severity::Error,
+                    None,
+                    None,
+                    None,
+                    format!(
+                        "file has {} Versions but {} Tables, entries without a partner are ignored",
+                        versions, tables
+                    ),
+                ));
+            }
+        }
+        for (product, versions) in self.product_to_version_info.iter() {
+            for (version, groups) in versions.iter() {
+                for (index, group) in groups.iter().enumerate() {
+                    let flavor = group.get("FLAVOR").map(|x| x.as_str());
+                    let error = |message| {
+                        CheckProblem::new(
+                            Severity::Error,
+                            Some(product),
+                            Some(version),
+                            flavor,
+                            message,
+                        )
+                    };
+                    match group.get("PROD_DIR") {
+                        None => {
+                            problems.push(error("no product directory is recorded".to_string()));
+                            continue;
+                        }
+                        Some(x) if !super::resolve_path(base, x).exists() => {
+                            problems.push(error(format!(
+                                "product directory {} does not exist",
+                                super::resolve_path(base, x).to_str().unwrap()
+                            )));
+                            continue;
+                        }
+                        Some(_) => (),
+                    }
+                    match self.get_group_table(product, version, index) {
+                        Some(table) => problems.extend(super::check_dependencies(
+                            &table,
+                            version,
+                            flavor,
+                            is_declared,
+                        )),
+                        None => problems.push(error("no table is recorded".to_string())),
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /// Finds the groups of tags which point at versions that are not declared, the product
+    /// directories to rewrite in the requested style, the versions declared without an identity,
+    /// and the entries of the file which have no partner
+    fn plan_fix(&self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        Ok(self.plan_fix_changes(paths).repairs)
+    }
+
+    /// Removes the groups of tags which point at versions that are not declared, rewrites the
+    /// product directories in the requested style, creates identities for versions declared
+    /// without one, and drops the entries of the file which have no partner
+    fn fix(&mut self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        let repairs = self.fix_in_memory(paths);
+        if !repairs.is_empty() {
+            self.write_file()
+                .map_err(|e| format!("Problem writing {:?}: {}", self.location, e))?;
+        }
+        Ok(repairs)
+    }
+
     fn declare_in_memory_impl(&mut self, inputs: &Vec<super::DeclareInputs>) -> Result<(), String> {
         // This function takes the list of inputs to declare, insures the inputs are to already in
         // the database source and if not, adds the input information to the relevant fields of the
         // db source
 
+        // Inputs declared without an identity are given one
+        let idents: Vec<String> = inputs
+            .iter()
+            .map(|input| match input.ident {
+                Some(id) => id.to_string(),
+                None => self.generate_ident(input),
+            })
+            .collect();
+
         // verify that all inputs to be declared are not in the db already
-        for input in inputs.iter() {
-            if input.ident.is_none() {
-                return Err(format!(
-                    "Json database sources must be declared with an identity, none for input {}",
-                    input.product
-                ));
-            }
+        for (input, id) in inputs.iter().zip(idents.iter()) {
             let version = input.version;
+            let flav = input.flavor.unwrap_or("");
+            let qualifiers = input.qualifiers.unwrap_or("");
             // check that none of the supplied info is in the database, this must be done
             // not quite elegantly at the same time as insertion because we don't want to do
             // any insertions unless the database does not contain any of the info
 
-            // check that the version is not already in the database
-            if self.product_to_version_info.contains_key(input.product)
-                && self.product_to_version_info[input.product].contains_key(version)
+            // check that the version is not already in the database for this flavor, an
+            // existing version may be given a new tag
+            if input.tag.is_none()
+                && self
+                    .product_to_version_info
+                    .get(input.product)
+                    .and_then(|x| x.get(version))
+                    .and_then(|x| super::position_group(x, flav, qualifiers))
+                    .is_some()
             {
                 return Err(format!(
-                    "Database already contains product {} with version {}",
-                    input.product, version
+                    "Database already contains product {} with version {} for flavor {} and qualifiers \"{}\"",
+                    input.product, version, flav, qualifiers
                 ));
             }
 
@@ -392,8 +895,12 @@ impl super::DBImpl for JsonDBImpl {
             // This check assumes that tag keys were added to all data members
             // appropriately
             if let Some(tg) = input.tag {
-                if self.tag_to_product_info.contains_key(tg)
-                    && self.tag_to_product_info[tg].contains_key(input.product)
+                if self
+                    .tag_to_product_info
+                    .get(tg)
+                    .and_then(|x| x.get(input.product))
+                    .and_then(|x| super::position_group(x, flav, qualifiers))
+                    .is_some()
                 {
                     return Err(format!(
                         "Database already contains tag {} for product {} version {}",
@@ -403,30 +910,26 @@ impl super::DBImpl for JsonDBImpl {
             }
 
             // This check assumes that ident keys were added to all data members
-            // appropriately
-            if let Some(id) = input.ident {
-                if let Some(prod_map) = self.product_ident_version.as_ref() {
-                    if prod_map.contains_key(input.product)
-                        && prod_map[input.product].contains_key(id)
-                    {
-                        return Err(format!(
-                            "Database already contains id {} for product {} version {}",
-                            id, input.product, &version
-                        ));
-                    }
+            // appropriately, an identity may be shared by the flavors of a single version
+            if let Some(prod_map) = self.product_ident_version.as_ref() {
+                if prod_map
+                    .get(input.product)
+                    .and_then(|x| x.get(id.as_str()))
+                    .is_some_and(|x| x != version)
+                {
+                    return Err(format!(
+                        "Database already contains id {} for product {} version {}",
+                        id, input.product, &version
+                    ));
                 }
             }
         }
 
         // If the function has gotten this far, no products exist and all should be added
-        for input in inputs.iter() {
+        for (input, id) in inputs.iter().zip(idents.iter()) {
             let (user, date) = super::get_declare_info();
-            let flav = if let Some(flav) = input.flavor {
-                flav
-            } else {
-                ""
-            };
-            let ups_dir = "ups";
+            let flav = input.flavor.unwrap_or("");
+            let ups_dir = input.ups_dir.unwrap_or("ups");
             let mut version_map = FnvHashMap::<String, String>::default();
             let version = input.version.to_string();
             let product = input.product.to_string();
@@ -435,9 +938,14 @@ impl super::DBImpl for JsonDBImpl {
             version_map.insert("FLAVOR".to_string(), flav.to_string());
             version_map.insert("DECLARER".to_string(), user.clone());
             version_map.insert("DECLARED".to_string(), date.clone());
-            version_map.insert("QUALIFIERS".to_string(), "".to_string());
+            version_map.insert(
+                "QUALIFIERS".to_string(),
+                input.qualifiers.unwrap_or("").to_string(),
+            );
             let abs_prod_dir = if input.relative {
-                crate::warn!("Declaring product with relative path, assumed to be relative to db source path");
+                if input.prod_dir.is_relative() {
+                    crate::warn!("Declaring product with relative path, assumed to be relative to db source path");
+                }
                 input.prod_dir.clone()
             } else {
                 input
@@ -453,42 +961,70 @@ impl super::DBImpl for JsonDBImpl {
                     .to_string(),
             );
             version_map.insert("UPS_DIR".to_string(), ups_dir.to_string());
+            if let Some(table_file) = input.table_file {
+                version_map.insert("TABLE_FILE".to_string(), table_file.to_string());
+            }
+            version_map.insert("IDENT".to_string(), id.clone());
 
-            self.product_to_version_info
+            // A version which already has this group is only being tagged
+            let versions = self
+                .product_to_version_info
                 .entry(input.product.to_string())
                 .or_insert(FnvHashMap::default())
-                .insert(version.clone(), version_map);
-
-            let ups_dir = "ups";
-            let mut table_file = abs_prod_dir.clone();
-            table_file.push(ups_dir);
-            table_file.push(format!("{}{}", input.product, ".table"));
-
-            let table_result =
-                Table::from_file(input.product.to_string(), table_file, abs_prod_dir.clone());
-            let table = match table_result {
-                Ok(table) => table,
-                Err(e) => {
-                    return Err(e.to_string());
-                }
-            };
+                .entry(version.clone())
+                .or_insert(vec![]);
+            if super::position_group(versions, flav, input.qualifiers.unwrap_or("")).is_none() {
+                versions.push(version_map);
 
-            self.product_to_version_table
-                .entry(product)
-                .or_insert(FnvHashMap::default())
-                .insert(version.clone(), table);
+                // Use the table supplied with the declaration, otherwise read it from the
+                // product directory
+                let table = match input.table.as_ref() {
+                    Some(table) => table.clone(),
+                    None => {
+                        let mut table_file = abs_prod_dir.clone();
+                        table_file.push(ups_dir);
+                        table_file.push(format!("{}{}", input.product, ".table"));
+
+                        let table_result = Table::from_file(
+                            input.product.to_string(),
+                            table_file,
+                            abs_prod_dir.clone(),
+                        );
+                        match table_result {
+                            Ok(table) => table,
+                            Err(e) => {
+                                return Err(e.to_string());
+                            }
+                        }
+                    }
+                };
+
+                self.product_to_version_table
+                    .entry(product)
+                    .or_insert(FnvHashMap::default())
+                    .entry(version.clone())
+                    .or_insert(vec![])
+                    .push(table);
+            }
 
             if let Some(tg) = input.tag {
                 let mut tag_map = FnvHashMap::<String, String>::default();
                 tag_map.insert("VERSION".to_string(), version.clone());
                 tag_map.insert("DECLARER".to_string(), user);
                 tag_map.insert("DECLARED".to_string(), date);
+                tag_map.insert("FLAVOR".to_string(), flav.to_string());
+                tag_map.insert(
+                    "QUALIFIERS".to_string(),
+                    input.qualifiers.unwrap_or("").to_string(),
+                );
 
                 // insert the info about the product tags into the database
                 self.tag_to_product_info
                     .entry(tg.to_string())
                     .or_insert(FnvHashMap::default())
-                    .insert(input.product.to_string(), tag_map);
+                    .entry(input.product.to_string())
+                    .or_insert(vec![])
+                    .push(tag_map);
 
                 if self
                     .product_to_tags
@@ -505,29 +1041,27 @@ impl super::DBImpl for JsonDBImpl {
                 }
             }
 
-            if let Some(id) = input.ident {
-                if let Some(prod_map) = self.product_to_ident.as_mut() {
-                    if prod_map
-                        .entry(input.product.to_string())
-                        .or_insert(vec![])
-                        .iter()
-                        .position(|x| x == id)
-                        .is_none()
-                    {
-                        prod_map
-                            .get_mut(input.product)
-                            .unwrap()
-                            .push(id.to_string());
-                    }
-                }
-                if let Some(prod_map) = self.product_ident_version.as_mut() {
+            if let Some(prod_map) = self.product_to_ident.as_mut() {
+                if prod_map
+                    .entry(input.product.to_string())
+                    .or_insert(vec![])
+                    .iter()
+                    .position(|x| x == id)
+                    .is_none()
+                {
                     prod_map
-                        .entry(input.product.to_string())
-                        .or_insert(FnvHashMap::default())
-                        .entry(id.to_string())
-                        .or_insert(version);
+                        .get_mut(input.product)
+                        .unwrap()
+                        .push(id.to_string());
                 }
             }
+            if let Some(prod_map) = self.product_ident_version.as_mut() {
+                prod_map
+                    .entry(input.product.to_string())
+                    .or_insert(FnvHashMap::default())
+                    .entry(id.to_string())
+                    .or_insert(version);
+            }
         }
         Ok(())
     }
@@ -582,28 +1116,29 @@ impl super::DBImpl for JsonDBImpl {
         if self.product_to_tags.contains_key(product) {
             crate::debug!("Syncing tags for product {}", product);
             for tag in &self.product_to_tags[product] {
-                let product_map = &mut json_db
+                let new_groups = json_db
                     .tag_to_product_info
                     .entry(tag.clone())
-                    .or_insert(FnvHashMap::default());
-                if product_map.contains_key(product) {
-                    // extra verification can be done here
-                    continue;
+                    .or_insert(FnvHashMap::default())
+                    .entry(product.to_string())
+                    .or_insert(vec![]);
+                // Add any flavors or qualifiers of the tag not already on disk
+                let old_groups = &self.tag_to_product_info[tag][product];
+                for (index, group) in old_groups.iter().enumerate() {
+                    let (flavor, qualifiers) = super::group_key(old_groups, index);
+                    if super::position_group(new_groups, flavor, qualifiers).is_some() {
+                        // extra verification can be done here
+                        continue;
+                    }
+                    new_groups.push(group.clone());
                 }
-                product_map.insert(
-                    product.to_string(),
-                    self.tag_to_product_info
-                        .get(tag)
-                        .unwrap()
-                        .get(product)
-                        .unwrap()
-                        .clone(),
-                );
-                json_db
+                let new_tags = json_db
                     .product_to_tags
                     .entry(product.to_string())
-                    .or_insert(vec![])
-                    .push(tag.clone());
+                    .or_insert(vec![]);
+                if !new_tags.contains(tag) {
+                    new_tags.push(tag.clone());
+                }
             }
         }
         if self.product_to_version_info.contains_key(product) {
@@ -618,16 +1153,23 @@ impl super::DBImpl for JsonDBImpl {
                 .entry(product.to_string())
                 .or_insert(FnvHashMap::default());
             let old_table_map = self.product_to_version_table.get(product).unwrap();
-            for version in old_product_map.keys() {
-                if new_product_map.contains_key(version) {
-                    // extra verification can be done here
-                    continue;
+            for (version, old_groups) in old_product_map.iter() {
+                let new_groups = new_product_map.entry(version.clone()).or_insert(vec![]);
+                let new_tables = new_table_map.entry(version.clone()).or_insert(vec![]);
+                // Add any flavors or qualifiers of the version not already on disk
+                for (index, (group, table)) in old_groups
+                    .iter()
+                    .zip(old_table_map[version].iter())
+                    .enumerate()
+                {
+                    let (flavor, qualifiers) = super::group_key(old_groups, index);
+                    if super::position_group(new_groups, flavor, qualifiers).is_some() {
+                        // extra verification can be done here
+                        continue;
+                    }
+                    new_groups.push(group.clone());
+                    new_tables.push(table.clone());
                 }
-                new_product_map.insert(
-                    version.clone(),
-                    old_product_map.get(version).unwrap().clone(),
-                );
-                new_table_map.insert(version.clone(), old_table_map.get(version).unwrap().clone());
             }
 
             crate::debug!("Syncing identities for product {}", product);
@@ -0,0 +1,331 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/**
+ * memory_db_impl is a backend database source for the main DB class which only exists in memory.
+ * It is populated from code, either with the fluent MemoryDBBuilder or by declaring products into
+ * it, and is never read from or written to disk. This lets library consumers construct a DB, and
+ * test tooling built on reups, without any files.
+ *
+ * In memory it holds the same groups and tables as a json source, and it shares the json
+ * implementation for looking up and declaring products.
+ **/
+use super::table::{Deps, EnvActionType};
+use super::DBImpl;
+use super::FnvHashMap;
+use super::JsonDBImpl;
+use super::PathBuf;
+use super::Table;
+
+/// Database backend source that holds its products only in memory
+#[derive(Debug)]
+pub struct MemoryDBImpl {
+    inner: JsonDBImpl,
+}
+
+impl MemoryDBImpl {
+    /// Creates a new empty MemoryDBImpl, products may be declared into it
+    pub fn new() -> MemoryDBImpl {
+        MemoryDBImpl {
+            inner: JsonDBImpl::new(&PathBuf::from("memory")).unwrap(),
+        }
+    }
+
+    /// Returns a builder used to populate a new MemoryDBImpl
+    pub fn builder() -> MemoryDBBuilder {
+        MemoryDBBuilder::default()
+    }
+}
+
+impl Default for MemoryDBImpl {
+    fn default() -> MemoryDBImpl {
+        MemoryDBImpl::new()
+    }
+}
+
+/// One flavor of a product version added to a MemoryDBBuilder, along with its table and the tags
+/// which point at it
+#[derive(Debug)]
+struct MemoryVersion {
+    product: String,
+    version: String,
+    prod_dir: PathBuf,
+    flavor: String,
+    qualifiers: String,
+    ident: Option<String>,
+    tags: Vec<String>,
+    table: Table,
+}
+
+/// Builds a MemoryDBImpl from a chain of calls. Each call to version adds a product version, and
+/// the calls that follow it, such as flavor, tag, or requires, describe that version. For example
+///
+/// ```
+/// use reups_lib::table::EnvActionType;
+/// use reups_lib::MemoryDBImpl;
+/// let source = MemoryDBImpl::builder()
+///     .version("fooB", "v1", "/opt/fooB/v1")
+///     .tag("current")
+///     .version("fooA", "v2", "/opt/fooA/v2")
+///     .tag("current")
+///     .requires("fooB", "v1")
+///     .env("PATH", EnvActionType::Prepend, "${PRODUCT_DIR}/bin")
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Product directories should be absolute paths, they are not required to exist.
+#[derive(Debug, Default)]
+pub struct MemoryDBBuilder {
+    versions: Vec<MemoryVersion>,
+    error: Option<String>,
+}
+
+impl MemoryDBBuilder {
+    /// Adds a version of a product, located in prod_dir, with a generic flavor and no qualifiers
+    pub fn version(mut self, product: &str, version: &str, prod_dir: &str) -> MemoryDBBuilder {
+        let prod_dir = PathBuf::from(prod_dir);
+        let table = Table::empty(product.to_string(), prod_dir.clone());
+        self.versions.push(MemoryVersion {
+            product: product.to_string(),
+            version: version.to_string(),
+            prod_dir,
+            flavor: String::new(),
+            qualifiers: String::new(),
+            ident: None,
+            tags: vec![],
+            table,
+        });
+        self
+    }
+
+    /// Applies a change to the last version added, recording an error for build to return if no
+    /// version has been added yet
+    fn update<F>(mut self, call: &str, change: F) -> MemoryDBBuilder
+    where
+        F: FnOnce(&mut MemoryVersion),
+    {
+        match self.versions.last_mut() {
+            Some(last) => change(last),
+            None => {
+                if self.error.is_none() {
+                    self.error = Some(format!("{} was called before any version was added", call));
+                }
+            }
+        }
+        self
+    }
+
+    /// Sets the flavor of the last version added
+    pub fn flavor(self, flavor: &str) -> MemoryDBBuilder {
+        self.update("flavor", |x| x.flavor = flavor.to_string())
+    }
+
+    /// Sets the qualifiers of the last version added
+    pub fn qualifiers(self, qualifiers: &str) -> MemoryDBBuilder {
+        self.update("qualifiers", |x| x.qualifiers = qualifiers.to_string())
+    }
+
+    /// Sets the identity of the last version added. A version added without one is given an
+    /// identity made by hashing its version
+    pub fn ident(self, ident: &str) -> MemoryDBBuilder {
+        self.update("ident", |x| x.ident = Some(ident.to_string()))
+    }
+
+    /// Points the tag at the last version added, for its flavor and qualifiers
+    pub fn tag(self, tag: &str) -> MemoryDBBuilder {
+        self.update("tag", |x| x.tags.push(tag.to_string()))
+    }
+
+    /// Makes the last version added require a product. In exact mode the supplied version is
+    /// setup, otherwise the version is chosen by tag, as with the setupRequired lines of a table
+    pub fn requires(self, product: &str, version: &str) -> MemoryDBBuilder {
+        self.update("requires", |x| {
+            add_dependency(&mut x.table, product, version, |deps| &mut deps.required)
+        })
+    }
+
+    /// Makes the last version added optionally depend on a product, which is setup the same way
+    /// as a required product when it is declared, as with the setupOptional lines of a table
+    pub fn optional(self, product: &str, version: &str) -> MemoryDBBuilder {
+        self.update("optional", |x| {
+            add_dependency(&mut x.table, product, version, |deps| &mut deps.optional)
+        })
+    }
+
+    /// Adds an environment action to the table of the last version added. Any ${PRODUCT_DIR} in
+    /// the value is replaced with the product directory when the product is setup
+    pub fn env(self, var: &str, action: EnvActionType, value: &str) -> MemoryDBBuilder {
+        self.update("env", |x| {
+            x.table
+                .env_var
+                .insert(var.to_string(), (action, value.to_string()));
+        })
+    }
+
+    /// Creates the MemoryDBImpl, declaring each version and then each tag in the order they were
+    /// added. It is an error to add the same flavor of a version twice, or to point a tag at two
+    /// versions of a product for the same flavor
+    pub fn build(self) -> Result<MemoryDBImpl, String> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut source = MemoryDBImpl::new();
+        for entry in self.versions.iter() {
+            let ident = entry
+                .ident
+                .clone()
+                .unwrap_or_else(|| super::hash_ident(&entry.version));
+            let tags = entry.tags.iter().map(|x| Some(x.as_str()));
+            for tag in std::iter::once(None).chain(tags) {
+                let input = super::DeclareInputs {
+                    product: &entry.product,
+                    prod_dir: &entry.prod_dir,
+                    version: &entry.version,
+                    tag,
+                    ident: Some(&ident),
+                    flavor: Some(&entry.flavor),
+                    qualifiers: Some(&entry.qualifiers),
+                    table: Some(entry.table.clone()),
+                    // The product directory is kept as given, it may not exist on this system
+                    relative: true,
+                    ups_dir: None,
+                    table_file: None,
+                };
+                source.inner.declare_in_memory_impl(&vec![input])?;
+            }
+        }
+        Ok(source)
+    }
+}
+
+/// Adds a dependency to the exact and inexact dependencies of a table, chosen by select
+fn add_dependency<F>(table: &mut Table, product: &str, version: &str, select: F)
+where
+    F: Fn(&mut Deps) -> &mut FnvHashMap<String, String>,
+{
+    if let Some(deps) = table.exact.as_mut() {
+        select(deps).insert(product.to_string(), version.to_string());
+    }
+    if let Some(deps) = table.inexact.as_mut() {
+        select(deps).insert(product.to_string(), String::new());
+    }
+}
+
+// Implement the trait to make MemoryDBImpl a database source, everything is handled by the in
+// memory json representation, and there is nothing to write to disk
+impl DBImpl for MemoryDBImpl {
+    fn get_location(&self) -> &super::PathBuf {
+        self.inner.get_location()
+    }
+
+    fn get_table(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<Table> {
+        self.inner.get_table(product, version, flavor, qualifiers)
+    }
+
+    fn get_tags(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_tags(product)
+    }
+
+    fn get_versions(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_versions(product)
+    }
+
+    fn get_products(&self) -> Vec<&str> {
+        self.inner.get_products()
+    }
+
+    fn get_identities(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_identities(product)
+    }
+
+    fn get_flavors(&self, product: &str, version: &str) -> Option<Vec<&str>> {
+        self.inner.get_flavors(product, version)
+    }
+
+    fn get_groups(&self, product: &str, version: &str) -> Option<Vec<(&str, &str)>> {
+        self.inner.get_groups(product, version)
+    }
+
+    fn get_tag_groups(&self, product: &str, tag: &str) -> Option<Vec<(&str, &str, &str)>> {
+        self.inner.get_tag_groups(product, tag)
+    }
+
+    fn lookup_flavor_version(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.inner
+            .lookup_flavor_version(product, version, flavor, qualifiers)
+    }
+
+    fn lookup_version_tag(
+        &self,
+        product: &str,
+        tag: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.inner
+            .lookup_version_tag(product, tag, flavor, qualifiers)
+    }
+
+    fn lookup_version_ident(&self, product: &str, ident: &str) -> Option<&str> {
+        self.inner.lookup_version_ident(product, ident)
+    }
+
+    fn lookup_location_version(&self, product: &str, version: &str) -> Option<&PathBuf> {
+        self.inner.lookup_location_version(product, version)
+    }
+
+    fn has_identity(&self, product: &str, ident: &str) -> bool {
+        self.inner.has_identity(product, ident)
+    }
+
+    fn has_product(&self, product: &str) -> bool {
+        self.inner.has_product(product)
+    }
+
+    fn identities_populated(&self) -> bool {
+        self.inner.identities_populated()
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    /// Checks that the product directories recorded exist, that tables depend only on declared
+    /// products, and that each tag points at a declared version
+    fn check(&self, is_declared: &dyn Fn(&str) -> bool) -> Vec<super::CheckProblem> {
+        self.inner.check(is_declared)
+    }
+
+    fn plan_fix(&self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        self.inner.plan_fix(paths)
+    }
+
+    /// Repairs the source in memory, there is no file to write the repairs to
+    fn fix(&mut self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        Ok(self.inner.fix_in_memory(paths))
+    }
+
+    fn declare_in_memory_impl(&mut self, inputs: &Vec<super::DeclareInputs>) -> Result<(), String> {
+        self.inner.declare_in_memory_impl(inputs)
+    }
+
+    /// Products declared into a memory source are already in place, so syncing does nothing
+    fn sync(&self, _product: &str) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -5,16 +5,28 @@
 
 #[macro_use]
 mod base;
+pub mod http_db_impl;
+mod index_cache;
 pub mod json_db_impl;
+pub mod memory_db_impl;
+pub mod overlay_db_impl;
 pub mod posix_db_impl;
+pub mod sqlite_db_impl;
+pub use self::http_db_impl::*;
+pub use self::index_cache::clear_index_cache;
 pub use self::json_db_impl::*;
+pub use self::memory_db_impl::*;
+pub use self::overlay_db_impl::*;
 pub use self::posix_db_impl::*;
+pub use self::sqlite_db_impl::*;
 use super::table;
 use super::table::Table;
 use super::DBFile;
 use super::DBLoadControl;
 use super::FnvHashMap;
 use super::PathBuf;
+use serde_derive::Serialize;
+use std::path::Path;
 #[doc(hidden)]
 use time;
 #[doc(hidden)]
@@ -25,24 +37,705 @@ use users;
 /// implement this trait
 pub trait DBImpl {
     fn get_location(&self) -> &super::PathBuf;
-    fn get_table(&self, product: &str, version: &str) -> Option<Table>;
+    fn get_table(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<Table>;
     fn get_tags(&self, product: &str) -> Option<Vec<&str>>;
     fn get_versions(&self, product: &str) -> Option<Vec<&str>>;
     fn get_products(&self) -> Vec<&str>;
     fn get_identities(&self, product: &str) -> Option<Vec<&str>>;
-    fn lookup_flavor_version(&self, product: &str, version: &str) -> Option<&str>;
-    fn lookup_version_tag(&self, product: &str, tag: &str) -> Option<&str>;
+    fn get_flavors(&self, product: &str, version: &str) -> Option<Vec<&str>>;
+    fn get_groups(&self, product: &str, version: &str) -> Option<Vec<(&str, &str)>>;
+    fn get_tag_groups(&self, product: &str, tag: &str) -> Option<Vec<(&str, &str, &str)>>;
+    fn lookup_flavor_version(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str>;
+    fn lookup_version_tag(
+        &self,
+        product: &str,
+        tag: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str>;
     fn lookup_version_ident(&self, product: &str, ident: &str) -> Option<&str>;
     fn lookup_location_version(&self, product: &str, version: &str) -> Option<&PathBuf>;
     fn has_identity(&self, product: &str, ident: &str) -> bool;
     fn has_product(&self, product: &str) -> bool;
     fn identities_populated(&self) -> bool;
     fn is_writable(&self) -> bool;
+    fn check(&self, is_declared: &dyn Fn(&str) -> bool) -> Vec<CheckProblem>;
+    /// Returns the repairs fix would make, without changing the source
+    fn plan_fix(&self, paths: Option<PathStyle>) -> Result<Vec<Repair>, String>;
+    /// Makes the repairs found by plan_fix, and writes them out to the source
+    fn fix(&mut self, paths: Option<PathStyle>) -> Result<Vec<Repair>, String>;
 
     fn declare_in_memory_impl(&mut self, inputs: &Vec<DeclareInputs>) -> Result<(), String>;
     fn sync(&self, product: &str) -> std::io::Result<()>;
 }
 
+/// Storage for the information about a product version or tag. Each storage holds one or more
+/// groups of information, where each group describes one flavor of the product.
+pub trait GroupStorage {
+    fn group_count(&self) -> usize;
+    fn get_group(&self, index: usize, key: &str) -> Option<&str>;
+}
+
+impl GroupStorage for DBFile {
+    fn group_count(&self) -> usize {
+        DBFile::group_count(self)
+    }
+
+    fn get_group(&self, index: usize, key: &str) -> Option<&str> {
+        DBFile::get_group(self, index, key)
+    }
+}
+
+impl GroupStorage for Vec<FnvHashMap<String, String>> {
+    fn group_count(&self) -> usize {
+        self.len()
+    }
+
+    fn get_group(&self, index: usize, key: &str) -> Option<&str> {
+        Some(self.get(index)?.get(key)?.as_str())
+    }
+}
+
+/// Returns the qualifiers with any surrounding whitespace and quotes removed, posix sources store
+/// them quoted, with an empty pair of quotes meaning no qualifiers
+pub fn normalize_qualifiers(qualifiers: &str) -> &str {
+    qualifiers.trim().trim_matches('"')
+}
+
+/// Returns the flavor and qualifiers which distinguish the group at index from the others of a
+/// version or tag
+pub fn group_key<S: GroupStorage>(storage: &S, index: usize) -> (&str, &str) {
+    (
+        storage.get_group(index, "FLAVOR").unwrap_or(""),
+        normalize_qualifiers(storage.get_group(index, "QUALIFIERS").unwrap_or("")),
+    )
+}
+
+/// Returns the index of the group in storage declared with exactly the supplied flavor and
+/// qualifiers
+pub fn position_group<S: GroupStorage>(
+    storage: &S,
+    flavor: &str,
+    qualifiers: &str,
+) -> Option<usize> {
+    (0..storage.group_count()).find(|i| group_key(storage, *i) == (flavor, qualifiers))
+}
+
+/// Finds the index of the group in storage to use for the supplied flavor and qualifiers. A group
+/// with exactly the qualifiers is preferred over one with no qualifiers, and after that a group
+/// with exactly the flavor is preferred over a generic group. If either is None, groups are not
+/// restricted by it, and the first suitable group is used.
+pub fn find_group<S: GroupStorage>(
+    storage: &S,
+    flavor: Option<&str>,
+    qualifiers: Option<&str>,
+) -> Option<usize> {
+    // Rank how well a group matches, zero means it can not be used at all
+    let rank = |i| {
+        let group_flavor = storage.get_group(i, "FLAVOR").unwrap_or("");
+        let flavor_rank = match flavor {
+            Some(x) if x == group_flavor => 2,
+            Some(_) if crate::cogs::is_generic_flavor(group_flavor) => 1,
+            Some(_) => 0,
+            None => 2,
+        };
+        let group_qualifiers =
+            normalize_qualifiers(storage.get_group(i, "QUALIFIERS").unwrap_or(""));
+        let qualifiers_rank = match qualifiers.map(normalize_qualifiers) {
+            Some(x) if x == group_qualifiers => 2,
+            Some(_) if group_qualifiers.is_empty() => 1,
+            Some(_) => 0,
+            None => 2,
+        };
+        if flavor_rank == 0 || qualifiers_rank == 0 {
+            0
+        } else {
+            qualifiers_rank * 3 + flavor_rank
+        }
+    };
+    let mut best: Option<(usize, u32)> = None;
+    for i in 0..storage.group_count() {
+        let score = rank(i);
+        if score > 0 && best.is_none_or(|(_, x)| score > x) {
+            best = Some((i, score));
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+/// How serious a problem found when checking a database source is. Errors are entries which
+/// can not be used, warnings are entries which can be used but may not behave as expected
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found when checking the integrity of a database source, along with the product,
+/// version, and flavor it was found in where those apply
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct CheckProblem {
+    pub product: Option<String>,
+    pub version: Option<String>,
+    pub flavor: Option<String>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl CheckProblem {
+    pub fn new(
+        severity: Severity,
+        product: Option<&str>,
+        version: Option<&str>,
+        flavor: Option<&str>,
+        message: String,
+    ) -> CheckProblem {
+        CheckProblem {
+            product: product.map(|x| x.to_string()),
+            version: version.map(|x| x.to_string()),
+            flavor: flavor.map(|x| x.to_string()),
+            severity,
+            message,
+        }
+    }
+}
+
+/// The form fix rewrites the product directories recorded in a source to, either absolute, or
+/// relative to the directory containing the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathStyle {
+    Absolute,
+    Relative,
+}
+
+/// A change made, or planned in a dry run, when fixing a database source, along with the
+/// product, version, and flavor it applies to where those apply
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Repair {
+    pub product: Option<String>,
+    pub version: Option<String>,
+    pub flavor: Option<String>,
+    pub description: String,
+}
+
+impl Repair {
+    pub fn new(
+        product: Option<&str>,
+        version: Option<&str>,
+        flavor: Option<&str>,
+        description: String,
+    ) -> Repair {
+        Repair {
+            product: product.map(|x| x.to_string()),
+            version: version.map(|x| x.to_string()),
+            flavor: flavor.map(|x| x.to_string()),
+            description,
+        }
+    }
+}
+
+/// Returns the path a source records, which if relative is relative to base
+pub fn resolve_path(base: &Path, path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.is_absolute() {
+        path
+    } else {
+        base.join(path)
+    }
+}
+
+/// Returns the product directory recorded by a source rewritten in the supplied style, or None if
+/// it is already in that style or does not exist
+pub fn rewrite_path(base: &Path, path: &str, style: PathStyle) -> Option<String> {
+    if PathBuf::from(path).is_absolute() == (style == PathStyle::Absolute) {
+        return None;
+    }
+    let target = resolve_path(base, path).canonicalize().ok()?;
+    let rewritten = match style {
+        PathStyle::Absolute => target,
+        PathStyle::Relative => {
+            let base = base.canonicalize().ok()?;
+            let base: Vec<_> = base.components().collect();
+            let target: Vec<_> = target.components().collect();
+            let common = base
+                .iter()
+                .zip(target.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let mut relative = PathBuf::new();
+            for _ in common..base.len() {
+                relative.push("..");
+            }
+            relative.extend(target[common..].iter());
+            if relative.as_os_str().is_empty() {
+                relative.push(".");
+            }
+            relative
+        }
+    };
+    Some(rewritten.to_str()?.to_string())
+}
+
+/// Finds the groups of the tags in a source which do not point at a version of the product
+/// declared in that source, returning the tag, product and index of each group
+pub fn dangling_tags<S: GroupStorage>(
+    tag_to_product_info: &FnvHashMap<String, FnvHashMap<String, S>>,
+    product_to_version_info: &FnvHashMap<String, FnvHashMap<String, S>>,
+) -> Vec<(String, String, usize)> {
+    let mut dangling = vec![];
+    for (tag, products) in tag_to_product_info.iter() {
+        for (product, storage) in products.iter() {
+            for index in 0..storage.group_count() {
+                let declared = storage.get_group(index, "VERSION").is_some_and(|version| {
+                    product_to_version_info
+                        .get(product)
+                        .and_then(|x| x.get(version))
+                        .is_some()
+                });
+                if !declared {
+                    dangling.push((tag.clone(), product.clone(), index));
+                }
+            }
+        }
+    }
+    dangling
+}
+
+/// Checks that every tag in a source points at a version of the product declared in that source
+pub fn check_tags<S: GroupStorage>(
+    tag_to_product_info: &FnvHashMap<String, FnvHashMap<String, S>>,
+    product_to_version_info: &FnvHashMap<String, FnvHashMap<String, S>>,
+) -> Vec<CheckProblem> {
+    dangling_tags(tag_to_product_info, product_to_version_info)
+        .into_iter()
+        .map(|(tag, product, index)| {
+            let storage = &tag_to_product_info[&tag][&product];
+            let message = match storage.get_group(index, "VERSION") {
+                Some(version) => format!(
+                    "tag {} points at version {} which is not declared",
+                    tag, version
+                ),
+                None => format!("tag {} does not record a version", tag),
+            };
+            CheckProblem::new(
+                Severity::Error,
+                Some(&product),
+                None,
+                storage.get_group(index, "FLAVOR"),
+                message,
+            )
+        })
+        .collect()
+}
+
+/// Checks that the products a table depends on are declared, a missing required dependency is
+/// an error and a missing optional one a warning
+pub fn check_dependencies(
+    table: &Table,
+    version: &str,
+    flavor: Option<&str>,
+    is_declared: &dyn Fn(&str) -> bool,
+) -> Vec<CheckProblem> {
+    // The exact and inexact sections usually name the same products, report each one once
+    let mut missing = std::collections::BTreeMap::new();
+    for deps in table.exact.iter().chain(table.inexact.iter()) {
+        for (dependency, required) in deps
+            .required
+            .keys()
+            .map(|x| (x, true))
+            .chain(deps.optional.keys().map(|x| (x, false)))
+        {
+            if !is_declared(dependency) {
+                *missing.entry(dependency.as_str()).or_insert(false) |= required;
+            }
+        }
+    }
+    missing
+        .into_iter()
+        .map(|(dependency, required)| {
+            let (severity, kind) = if required {
+                (Severity::Error, "required")
+            } else {
+                (Severity::Warning, "optional")
+            };
+            CheckProblem::new(
+                severity,
+                Some(&table.name),
+                Some(version),
+                flavor,
+                format!("{} dependency {} is not declared", kind, dependency),
+            )
+        })
+        .collect()
+}
+
+/// The outcome of converting one database source into another
+#[derive(Debug, Default, Serialize)]
+pub struct Conversion {
+    /// The number of version groups declared in the destination
+    pub versions: usize,
+    /// The number of tag groups declared in the destination
+    pub tags: usize,
+    /// Entries which were not converted, because the destination already contains them or they
+    /// are incomplete in the source
+    pub conflicts: Vec<String>,
+}
+
+/// Declares every version and tag of the source in the destination, in memory, using the checks
+/// the destination makes for any declaration. Entries the destination already contains are
+/// reported as conflicts and left as they are. Identities are only declared if the destination
+/// keeps them, and a version without one is given one hashed from the version.
+pub fn convert(source: &dyn DBImpl, dest: &mut dyn DBImpl) -> Conversion {
+    let mut conversion = Conversion::default();
+    let keep_idents = dest.identities_populated();
+    let mut products = source.get_products();
+    products.sort_unstable();
+    for product in products {
+        let mut tags = source.get_tags(product).unwrap_or_default();
+        tags.sort_unstable();
+        // Each group of a tag is declared along with the version group it points at
+        let mut tag_groups: Vec<(&str, &str, &str, &str)> = vec![];
+        for tag in tags {
+            for (flavor, qualifiers, version) in
+                source.get_tag_groups(product, tag).unwrap_or_default()
+            {
+                tag_groups.push((tag, flavor, qualifiers, version));
+            }
+        }
+
+        let mut versions = source.get_versions(product).unwrap_or_default();
+        versions.sort_unstable();
+        for version in versions {
+            let ident = if keep_idents {
+                let existing = source
+                    .get_identities(product)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|id| source.lookup_version_ident(product, id) == Some(version));
+                Some(existing.map_or_else(|| hash_ident(version), |x| x.to_string()))
+            } else {
+                None
+            };
+            for (flavor, qualifiers) in source.get_groups(product, version).unwrap_or_default() {
+                let table = source.get_table(product, version, Some(flavor), Some(qualifiers));
+                let table = match table {
+                    Some(table) if table.product_dir.exists() => table,
+                    _ => {
+                        conversion.conflicts.push(format!(
+                            "Product {} version {} for flavor {} was not converted, its table or product directory could not be found",
+                            product, version, flavor
+                        ));
+                        continue;
+                    }
+                };
+                let prod_dir = table.product_dir.clone();
+                let input = DeclareInputs {
+                    product,
+                    prod_dir: &prod_dir,
+                    version,
+                    tag: None,
+                    ident: ident.as_deref(),
+                    flavor: Some(flavor),
+                    qualifiers: Some(qualifiers),
+                    table: Some(table),
+                    relative: false,
+                    ups_dir: None,
+                    table_file: None,
+                };
+                match dest.declare_in_memory_impl(&vec![input]) {
+                    Ok(_) => conversion.versions += 1,
+                    Err(msg) => conversion.conflicts.push(msg),
+                }
+
+                // Declaring a tag for a version the destination already has only adds the tag
+                for (tag, ..) in tag_groups
+                    .iter()
+                    .filter(|x| (x.1, x.2, x.3) == (flavor, qualifiers, version))
+                {
+                    let input = DeclareInputs {
+                        product,
+                        prod_dir: &prod_dir,
+                        version,
+                        tag: Some(tag),
+                        ident: ident.as_deref(),
+                        flavor: Some(flavor),
+                        qualifiers: Some(qualifiers),
+                        table: None,
+                        relative: false,
+                        ups_dir: None,
+                        table_file: None,
+                    };
+                    match dest.declare_in_memory_impl(&vec![input]) {
+                        Ok(_) => conversion.tags += 1,
+                        Err(msg) => conversion.conflicts.push(msg),
+                    }
+                }
+            }
+        }
+
+        for (tag, flavor, qualifiers, version) in tag_groups {
+            let declared = source
+                .get_groups(product, version)
+                .is_some_and(|x| x.contains(&(flavor, qualifiers)));
+            if !declared {
+                conversion.conflicts.push(format!(
+                    "Tag {} of product {} for flavor {} was not converted, it points at version {} which is not declared for that flavor",
+                    tag, product, flavor, version
+                ));
+            }
+        }
+    }
+    conversion
+}
+
+/// A difference found between two database sources, along with the product, version, and
+/// flavor it applies to where those apply
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Difference {
+    pub product: String,
+    pub version: Option<String>,
+    pub flavor: Option<String>,
+    pub description: String,
+}
+
+impl Difference {
+    pub fn new(
+        product: &str,
+        version: Option<&str>,
+        flavor: Option<String>,
+        description: String,
+    ) -> Difference {
+        Difference {
+            product: product.to_string(),
+            version: version.map(|x| x.to_string()),
+            flavor,
+            description,
+        }
+    }
+}
+
+/// Returns the label a group is reported with, its flavor followed by any qualifiers
+fn group_label(flavor: &str, qualifiers: &str) -> String {
+    if qualifiers.is_empty() {
+        flavor.to_string()
+    } else {
+        format!("{} {}", flavor, qualifiers)
+    }
+}
+
+/// Returns the sorted union of two optional lists
+fn union<'a>(a: Option<Vec<&'a str>>, b: Option<Vec<&'a str>>) -> Vec<&'a str> {
+    let mut all: Vec<&str> = a.unwrap_or_default();
+    all.extend(b.unwrap_or_default());
+    all.sort_unstable();
+    all.dedup();
+    all
+}
+
+/// Lists the differences between two database sources, which may be of different types. Products
+/// and versions which were added or removed are found, along with the flavors of versions in both,
+/// tags which were added, removed, or moved to another version, and tables whose dependencies or
+/// environment actions differ. Differences are described going from source a to source b.
+pub fn diff(a: &dyn DBImpl, b: &dyn DBImpl) -> Vec<Difference> {
+    let mut differences = vec![];
+    let products = union(Some(a.get_products()), Some(b.get_products()));
+    for product in products {
+        match (a.has_product(product), b.has_product(product)) {
+            (false, _) => {
+                differences.push(Difference::new(product, None, None, "product added".into()));
+                continue;
+            }
+            (_, false) => {
+                differences.push(Difference::new(
+                    product,
+                    None,
+                    None,
+                    "product removed".into(),
+                ));
+                continue;
+            }
+            _ => (),
+        }
+
+        for version in union(a.get_versions(product), b.get_versions(product)) {
+            let groups_a = a.get_groups(product, version);
+            let groups_b = b.get_groups(product, version);
+            let (groups_a, groups_b) = match (groups_a, groups_b) {
+                (None, _) => {
+                    differences.push(Difference::new(
+                        product,
+                        Some(version),
+                        None,
+                        "version added".into(),
+                    ));
+                    continue;
+                }
+                (_, None) => {
+                    differences.push(Difference::new(
+                        product,
+                        Some(version),
+                        None,
+                        "version removed".into(),
+                    ));
+                    continue;
+                }
+                (Some(x), Some(y)) => (x, y),
+            };
+            for (flavor, qualifiers) in groups_b.iter().filter(|x| !groups_a.contains(x)) {
+                differences.push(Difference::new(
+                    product,
+                    Some(version),
+                    Some(group_label(flavor, qualifiers)),
+                    "flavor added".into(),
+                ));
+            }
+            for (flavor, qualifiers) in groups_a.iter() {
+                let label = Some(group_label(flavor, qualifiers));
+                if !groups_b.contains(&(flavor, qualifiers)) {
+                    differences.push(Difference::new(
+                        product,
+                        Some(version),
+                        label,
+                        "flavor removed".into(),
+                    ));
+                    continue;
+                }
+                let table_a = a.get_table(product, version, Some(flavor), Some(qualifiers));
+                let table_b = b.get_table(product, version, Some(flavor), Some(qualifiers));
+                for description in diff_tables(table_a.as_ref(), table_b.as_ref()) {
+                    differences.push(Difference::new(
+                        product,
+                        Some(version),
+                        label.clone(),
+                        description,
+                    ));
+                }
+            }
+        }
+
+        for tag in union(a.get_tags(product), b.get_tags(product)) {
+            // Tags are compared for every flavor either source declares them for
+            let mut groups: Vec<(&str, &str)> = a
+                .get_tag_groups(product, tag)
+                .unwrap_or_default()
+                .into_iter()
+                .chain(b.get_tag_groups(product, tag).unwrap_or_default())
+                .map(|(flavor, qualifiers, _)| (flavor, qualifiers))
+                .collect();
+            groups.sort_unstable();
+            groups.dedup();
+            for (flavor, qualifiers) in groups {
+                let version_a = a.lookup_version_tag(product, tag, Some(flavor), Some(qualifiers));
+                let version_b = b.lookup_version_tag(product, tag, Some(flavor), Some(qualifiers));
+                let description = match (version_a, version_b) {
+                    (None, Some(to)) => format!("tag {} added at version {}", tag, to),
+                    (Some(from), None) => format!("tag {} removed from version {}", tag, from),
+                    (Some(from), Some(to)) if from != to => {
+                        format!("tag {} moved from version {} to {}", tag, from, to)
+                    }
+                    _ => continue,
+                };
+                differences.push(Difference::new(
+                    product,
+                    None,
+                    Some(group_label(flavor, qualifiers)),
+                    description,
+                ));
+            }
+        }
+    }
+    differences.sort();
+    differences
+}
+
+/// Describes the differences between the dependencies and environment actions of two tables
+fn diff_tables(a: Option<&Table>, b: Option<&Table>) -> Vec<String> {
+    let (a, b) = match (a, b) {
+        (None, Some(_)) => return vec!["table added".to_string()],
+        (Some(_), None) => return vec!["table removed".to_string()],
+        (Some(a), Some(b)) => (a, b),
+        (None, None) => return vec![],
+    };
+    let mut descriptions = vec![];
+    let deps = |table: &Table, exact: bool| {
+        let deps = if exact { &table.exact } else { &table.inexact };
+        let mut all: Vec<(&str, String, String)> = vec![];
+        if let Some(deps) = deps {
+            for (kind, map) in [("required", &deps.required), ("optional", &deps.optional)] {
+                all.extend(map.iter().map(|(k, v)| (kind, k.clone(), v.clone())));
+            }
+        }
+        all
+    };
+    for (exact, name) in [(true, "exact"), (false, "inexact")] {
+        let (deps_a, deps_b) = (deps(a, exact), deps(b, exact));
+        let find = |deps: &[(&str, String, String)], kind: &str, product: &str| {
+            deps.iter()
+                .find(|x| x.0 == kind && x.1 == product)
+                .map(|x| x.2.clone())
+        };
+        let mut keys: Vec<(&str, &String)> = deps_a
+            .iter()
+            .chain(deps_b.iter())
+            .map(|x| (x.0, &x.1))
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        for (kind, product) in keys {
+            let description = match (find(&deps_a, kind, product), find(&deps_b, kind, product)) {
+                (None, Some(_)) => format!("{} {} dependency {} added", name, kind, product),
+                (Some(_), None) => format!("{} {} dependency {} removed", name, kind, product),
+                (Some(from), Some(to)) if from != to => format!(
+                    "{} {} dependency {} changed from version {} to {}",
+                    name, kind, product, from, to
+                ),
+                _ => continue,
+            };
+            descriptions.push(description);
+        }
+    }
+
+    // Environment actions are compared with the product directory of each table replaced, so that
+    // tables of products which were moved are not different
+    let env = |table: &Table, var: &str| {
+        table.env_var.get(var).map(|(action, target)| {
+            let target = match table.product_dir.to_str() {
+                Some(dir) if !dir.is_empty() => target.replace(dir, "${PRODUCT_DIR}"),
+                _ => target.clone(),
+            };
+            format!("{:?} {}", action, target)
+        })
+    };
+    let mut vars: Vec<&String> = a.env_var.keys().chain(b.env_var.keys()).collect();
+    vars.sort_unstable();
+    vars.dedup();
+    for var in vars {
+        let description = match (env(a, var), env(b, var)) {
+            (None, Some(to)) => format!("env action on {} added: {}", var, to),
+            (Some(from), None) => format!("env action on {} removed: {}", var, from),
+            (Some(from), Some(to)) if from != to => {
+                format!("env action on {} changed from {} to {}", var, from, to)
+            }
+            _ => continue,
+        };
+        descriptions.push(description);
+    }
+    descriptions
+}
+
 pub trait DBImplDeclare: Sized {
     fn declare(self, inputs: &Vec<DeclareInputs>) -> Result<Self, (Self, String)>;
     fn declare_in_memory(self, inputs: &Vec<DeclareInputs>) -> Result<Self, (Self, String)>;
@@ -89,6 +782,7 @@ pub fn get_declare_info() -> (String, String) {
     (user, now)
 }
 
+#[derive(Clone)]
 pub struct DeclareInputs<'a> {
     pub product: &'a str,
     pub prod_dir: &'a PathBuf,
@@ -96,6 +790,13 @@ pub struct DeclareInputs<'a> {
     pub tag: Option<&'a str>,
     pub ident: Option<&'a str>,
     pub flavor: Option<&'a str>,
+    pub qualifiers: Option<&'a str>,
     pub table: Option<Table>,
     pub relative: bool,
+    /// Directory containing the table file, absolute or relative to the product directory, the
+    /// ups directory if None
+    pub ups_dir: Option<&'a str>,
+    /// Path of the table file, none for a product without a table, or the table named for the
+    /// product in the ups directory if None
+    pub table_file: Option<&'a str>,
 }
@@ -0,0 +1,151 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/**
+ * overlay_db_impl is a backend database source for the main DB class which holds products
+ * declared for local development on top of the shared stack. It is stored in a json file in the
+ * temporary directory, or wherever REUPS_OVERLAY points, and is only written to by
+ * `reups declare --overlay`, so it is never chosen as the source of an ordinary declare. It is
+ * looked up before every other source, and is discarded with `reups overlay clear`.
+ **/
+use super::JsonDBImpl;
+use super::PathBuf;
+use super::Table;
+
+/// Database backend source that holds products declared into the overlay
+#[derive(Debug)]
+pub struct OverlayDBImpl {
+    inner: JsonDBImpl,
+}
+
+impl OverlayDBImpl {
+    /// Creates a new OverlayDBImpl from the overlay file at the location provided, the overlay is
+    /// empty if the file does not exist yet
+    pub fn new(loc: &PathBuf) -> Result<OverlayDBImpl, String> {
+        let inner = if loc.exists() {
+            JsonDBImpl::from_file(loc)
+                .map_err(|e| format!("Problem reading overlay {:?}: {}", loc, e))?
+        } else {
+            JsonDBImpl::new(loc)?
+        };
+        Ok(OverlayDBImpl { inner })
+    }
+}
+
+// Implement the trait to make OverlayDBImpl a database source, everything is handled by the json
+// representation of the overlay file
+impl super::DBImpl for OverlayDBImpl {
+    fn get_location(&self) -> &super::PathBuf {
+        self.inner.get_location()
+    }
+
+    fn get_table(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<Table> {
+        self.inner.get_table(product, version, flavor, qualifiers)
+    }
+
+    fn get_tags(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_tags(product)
+    }
+
+    fn get_versions(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_versions(product)
+    }
+
+    fn get_products(&self) -> Vec<&str> {
+        self.inner.get_products()
+    }
+
+    fn get_identities(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_identities(product)
+    }
+
+    fn get_flavors(&self, product: &str, version: &str) -> Option<Vec<&str>> {
+        self.inner.get_flavors(product, version)
+    }
+
+    fn get_groups(&self, product: &str, version: &str) -> Option<Vec<(&str, &str)>> {
+        self.inner.get_groups(product, version)
+    }
+
+    fn get_tag_groups(&self, product: &str, tag: &str) -> Option<Vec<(&str, &str, &str)>> {
+        self.inner.get_tag_groups(product, tag)
+    }
+
+    fn lookup_flavor_version(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.inner
+            .lookup_flavor_version(product, version, flavor, qualifiers)
+    }
+
+    fn lookup_version_tag(
+        &self,
+        product: &str,
+        tag: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.inner
+            .lookup_version_tag(product, tag, flavor, qualifiers)
+    }
+
+    fn lookup_version_ident(&self, product: &str, ident: &str) -> Option<&str> {
+        self.inner.lookup_version_ident(product, ident)
+    }
+
+    fn lookup_location_version(&self, product: &str, version: &str) -> Option<&PathBuf> {
+        self.inner.lookup_location_version(product, version)
+    }
+
+    fn has_identity(&self, product: &str, ident: &str) -> bool {
+        self.inner.has_identity(product, ident)
+    }
+
+    fn has_product(&self, product: &str) -> bool {
+        self.inner.has_product(product)
+    }
+
+    fn identities_populated(&self) -> bool {
+        self.inner.identities_populated()
+    }
+
+    /// The overlay is only written to when declaring into it explicitly, so it is not reported
+    /// as writable
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    /// Checks that the product directories recorded exist, that tables depend only on declared
+    /// products, and that each tag points at a declared version
+    fn check(&self, is_declared: &dyn Fn(&str) -> bool) -> Vec<super::CheckProblem> {
+        self.inner.check(is_declared)
+    }
+
+    fn plan_fix(&self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        self.inner.plan_fix(paths)
+    }
+
+    fn fix(&mut self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        self.inner.fix(paths)
+    }
+
+    fn declare_in_memory_impl(&mut self, inputs: &Vec<super::DeclareInputs>) -> Result<(), String> {
+        self.inner.declare_in_memory_impl(inputs)
+    }
+
+    fn sync(&self, product: &str) -> std::io::Result<()> {
+        self.inner.sync(product)
+    }
+}
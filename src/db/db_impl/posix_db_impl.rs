@@ -2,43 +2,51 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/.
  * Copyright Nate Lust 2019*/
+use super::index_cache::{self, IndexCache};
 use super::DBFile;
-use super::DBImpl;
 use super::DBLoadControl;
 use super::FnvHashMap;
 use super::PathBuf;
 use super::Table;
 use crate::regex;
+use crossbeam_deque::{Injector, Stealer, Worker};
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
-use std::cell::RefCell;
+use fnv::FnvHashSet;
+use std::cell::{OnceCell, RefCell};
 use std::fs;
 use std::path;
-use std::sync::mpsc;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
 use std::thread;
 
-static TABLE_STR: &str = "FILE = version
+// Chain and version files are made up of a header, followed by one group per flavor and set of
+// qualifiers
+static CHAIN_HEADER_STR: &str = "FILE = version
 PRODUCT = {product} 
 CHAIN = {tag}
 #***************************************
+";
 
+static CHAIN_GROUP_STR: &str = "
 #Group:
    FLAVOR = {flavor}
    VERSION = {version}
-   QUALIFIERS = \"\"
+   QUALIFIERS = \"{qualifiers}\"
    DECLARER = {user}
    DECLARED = {date}
 #End:
 ";
 
-static VERSION_STR: &str = "FILE = version
+static VERSION_HEADER_STR: &str = "FILE = version
 PRODUCT = {product}
 VERSION = {version}
 #***************************************
+";
 
+static VERSION_GROUP_STR: &str = "
 Group:
    FLAVOR = {flavor}
-   QUALIFIERS = \"\"
+   QUALIFIERS = \"{qualifiers}\"
    DECLARER = {user}
    DECLARED = {date}
    PROD_DIR = {prod_dir}
@@ -47,11 +55,70 @@ Group:
 End:
 ";
 
+// Mappings between the fields of the templates and the keys of a DBFile
+static VERSION_FIELDS: [(&str, &str); 9] = [
+    ("product", "PRODUCT"),
+    ("version", "VERSION"),
+    ("flavor", "FLAVOR"),
+    ("qualifiers", "QUALIFIERS"),
+    ("user", "DECLARER"),
+    ("date", "DECLARED"),
+    ("prod_dir", "PROD_DIR"),
+    ("ups_dir", "UPS_DIR"),
+    ("table_file", "TABLE_FILE"),
+];
+
+static CHAIN_FIELDS: [(&str, &str); 7] = [
+    ("product", "PRODUCT"),
+    ("tag", "CHAIN"),
+    ("flavor", "FLAVOR"),
+    ("version", "VERSION"),
+    ("qualifiers", "QUALIFIERS"),
+    ("user", "DECLARER"),
+    ("date", "DECLARED"),
+];
+
+/// Returns the number of threads used to read posix sources when none is configured, which is
+/// the parallelism available to reups
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map_or(1, |x| x.get())
+}
+
+/// Controls how a posix source is read off disk
+#[derive(Debug, Clone)]
+pub struct PosixReadOptions {
+    /// Directory of the index caches the source is read through, None reads it without a cache
+    pub cache_dir: Option<PathBuf>,
+    /// Number of threads reading the directories and files of the source
+    pub workers: usize,
+}
+
+impl Default for PosixReadOptions {
+    fn default() -> PosixReadOptions {
+        PosixReadOptions {
+            cache_dir: None,
+            workers: default_workers(),
+        }
+    }
+}
+
+/// The repairs fix makes to a posix source, and the changes which make them
+struct FixPlan {
+    repairs: Vec<super::Repair>,
+    // Chain files left without any groups, as (tag, product)
+    removed_tags: Vec<(String, String)>,
+    // Chain files with groups to remove, as (tag, product, group indices from last to first)
+    changed_tags: Vec<(String, String, Vec<usize>)>,
+    // Product directories to rewrite, as (product, version, group index, product directory)
+    rewrites: Vec<(String, String, usize, String)>,
+}
+
 // Database back end source that uses a posix file system to store information
 make_db_source_struct!(
     PosixDBImpl,
     DBFile,
-    table_cache: RefCell<FnvHashMap<(String, String), Table>>
+    table_cache: RefCell<FnvHashMap<(String, String, usize), Table>>,
+    index_cache: Option<PathBuf>
 );
 
 impl PosixDBImpl {
@@ -64,7 +131,45 @@ impl PosixDBImpl {
         preload: Option<&DBLoadControl>,
         ident_regex: Option<regex::Regex>,
     ) -> Result<PosixDBImpl, String> {
-        let (location, product_to_info, tags_to_info, product_to_tags) = build_db(path, preload)?;
+        PosixDBImpl::new_with_options(path, preload, ident_regex, &PosixReadOptions::default())
+    }
+
+    /// Creates a new Posix database source in the same way as new, reading it with the supplied
+    /// options. When the options have a cache directory, the source is read through the index
+    /// cache kept in it. Products whose directories have not changed since they were cached are
+    /// not read off disk, and the cache is updated with those which have. All files are loaded at
+    /// creation when using a cache, so that they can be cached.
+    pub fn new_with_options(
+        path: PathBuf,
+        preload: Option<&DBLoadControl>,
+        ident_regex: Option<regex::Regex>,
+        options: &PosixReadOptions,
+    ) -> Result<PosixDBImpl, String> {
+        let files = build_db(path, preload, options, None)?;
+        Ok(PosixDBImpl::from_files(files, ident_regex, options))
+    }
+
+    /// Creates a Posix database source holding only one product of the source at path, by
+    /// reading the directory of that product. The source is empty if the product does not exist
+    fn new_product(
+        path: PathBuf,
+        product: &str,
+        preload: Option<&DBLoadControl>,
+        ident_regex: Option<regex::Regex>,
+        options: &PosixReadOptions,
+    ) -> Result<PosixDBImpl, String> {
+        let files = build_db(path, preload, options, Some(product))?;
+        Ok(PosixDBImpl::from_files(files, ident_regex, options))
+    }
+
+    /// Creates a Posix database source from the files read by build_db, extracting identities
+    /// from the versions if there is a regular expression to do so
+    fn from_files(
+        files: SourceFiles,
+        ident_regex: Option<regex::Regex>,
+        options: &PosixReadOptions,
+    ) -> PosixDBImpl {
+        let (location, product_to_info, tags_to_info, product_to_tags) = files;
         let (product_to_ident, product_ident_version) = if ident_regex.is_some() {
             let mut product_to_ident = FnvHashMap::<String, Vec<String>>::default();
             let mut product_ident_version =
@@ -72,14 +177,8 @@ impl PosixDBImpl {
             product_to_info.iter().for_each(|(product, version_map)| {
                 let mut idents = vec![];
                 let mut ident_versions = FnvHashMap::<String, String>::default();
-                for (version, dbfile) in version_map.iter() {
-                    let found = ident_regex
-                        .as_ref()
-                        .unwrap()
-                        .find(dbfile.get("VERSION").unwrap());
-                    if found.is_some() {
-                        let ident =
-                            version[found.unwrap().start()..(found.unwrap().end() + 1)].to_string();
+                for version in version_map.keys() {
+                    if let Some(ident) = extract_ident(ident_regex.as_ref().unwrap(), version) {
                         idents.push(ident.clone());
                         ident_versions.insert(ident, version.clone());
                     }
@@ -91,7 +190,7 @@ impl PosixDBImpl {
         } else {
             (None, None)
         };
-        Ok(PosixDBImpl {
+        PosixDBImpl {
             location,
             tag_to_product_info: tags_to_info,
             product_to_version_info: product_to_info,
@@ -99,7 +198,20 @@ impl PosixDBImpl {
             product_to_ident,
             product_ident_version,
             table_cache: RefCell::new(FnvHashMap::default()),
-        })
+            // Writes must remove the cache even when the source was not read through it
+            index_cache: options
+                .cache_dir
+                .clone()
+                .or_else(crate::cogs::get_index_cache_dir),
+        }
+    }
+
+    /// Removes the index cache of this source, so that files it writes are read again even if
+    /// they are written too quickly for their modification times to change
+    fn remove_index_cache(&self) {
+        if let Some(cache_dir) = self.index_cache.as_ref() {
+            index_cache::remove_index(cache_dir, &self.location);
+        }
     }
 
     /// Formats a given string, replacing specified fields with corresponding values from map, this
@@ -127,66 +239,155 @@ impl PosixDBImpl {
         formatted_string
     }
 
-    /// Formats the templated format string with supplied information
+    /// Formats the templated format string with supplied information, creating a file with a
+    /// single group
     fn format_version_file(&self, map: &FnvHashMap<&str, &str>) -> String {
+        let mut contents =
+            self.format_template_file(VERSION_HEADER_STR, vec!["product", "version"], map);
+        contents.push_str(&self.format_version_group(map));
+        contents
+    }
+
+    /// Formats a single group of a version file with the supplied information
+    fn format_version_group(&self, map: &FnvHashMap<&str, &str>) -> String {
         let fields: Vec<&str> = vec![
-            "product",
-            "version",
             "flavor",
+            "qualifiers",
             "user",
             "date",
             "prod_dir",
             "ups_dir",
             "table_file",
         ];
-        self.format_template_file(VERSION_STR, fields, map)
+        self.format_template_file(VERSION_GROUP_STR, fields, map)
     }
 
     /// Formats the templated version string into a format that is expected when a function
     /// accesses the data through a DBFile interface. This mainly means just reformatting the keys
-    /// used in the mapping
+    /// used in the mapping. Each group in the DBFile is written out
     fn format_version_dbfile(&self, dbfile: &DBFile) -> String {
         crate::info!("Formatting dbfile into version string");
-        let mut translate = FnvHashMap::default();
-        translate.insert("product", "PRODUCT");
-        translate.insert("version", "VERSION");
-        translate.insert("flavor", "FLAVOR");
-        translate.insert("user", "DECLARER");
-        translate.insert("date", "DECLARED");
-        translate.insert("prod_dir", "PROD_DIR");
-        translate.insert("ups_dir", "UPS_DIR");
-        translate.insert("table_file", "TABLE_FILE");
-        let mut new_map = FnvHashMap::<&str, &str>::default();
-        for (k, v) in translate.iter() {
-            crate::debug!("inserting key value: {}, {}", k, v);
-            new_map.insert(k, dbfile.get(v).unwrap());
-        }
-        self.format_version_file(&new_map)
-    }
-
-    /// Formats the templated tag string with supplied information
+        let mut contents = self.format_template_file(
+            VERSION_HEADER_STR,
+            vec!["product", "version"],
+            &translate_dbfile(dbfile, 0, &VERSION_FIELDS),
+        );
+        for index in 0..dbfile.group_count() {
+            contents.push_str(&self.format_version_group(&translate_dbfile(
+                dbfile,
+                index,
+                &VERSION_FIELDS,
+            )));
+        }
+        contents
+    }
+
+    /// Formats the templated tag string with supplied information, creating a file with a single
+    /// group
     fn format_tag_file(&self, map: &FnvHashMap<&str, &str>) -> String {
-        let fields: Vec<&str> = vec!["product", "tag", "flavor", "version", "user", "date"];
-        self.format_template_file(TABLE_STR, fields, map)
+        let mut contents = self.format_template_file(CHAIN_HEADER_STR, vec!["product", "tag"], map);
+        contents.push_str(&self.format_tag_group(map));
+        contents
+    }
+
+    /// Formats a single group of a tag file with the supplied information
+    fn format_tag_group(&self, map: &FnvHashMap<&str, &str>) -> String {
+        let fields: Vec<&str> = vec!["flavor", "version", "qualifiers", "user", "date"];
+        self.format_template_file(CHAIN_GROUP_STR, fields, map)
     }
 
     /// Formats the templated tag string into a format that is expected when a function
     /// accesses the data through a DBFile interface. This mainly means just reformatting the keys
-    /// used in the mapping
+    /// used in the mapping. Each group in the DBFile is written out
     fn format_tag_dbfile(&self, dbfile: &DBFile) -> String {
         crate::info!("Formatting dbfile into tag string");
-        let mut translate = FnvHashMap::default();
-        translate.insert("product", "PRODUCT");
-        translate.insert("tag", "CHAIN");
-        translate.insert("flavor", "FLAVOR");
-        translate.insert("version", "VERSION");
-        translate.insert("user", "DECLARER");
-        translate.insert("date", "DECLARED");
-        let mut new_map = FnvHashMap::<&str, &str>::default();
-        for (k, v) in translate.iter() {
-            new_map.insert(k, dbfile.get(v).unwrap());
+        let mut contents = self.format_template_file(
+            CHAIN_HEADER_STR,
+            vec!["product", "tag"],
+            &translate_dbfile(dbfile, 0, &CHAIN_FIELDS),
+        );
+        for index in 0..dbfile.group_count() {
+            contents.push_str(&self.format_tag_group(&translate_dbfile(
+                dbfile,
+                index,
+                &CHAIN_FIELDS,
+            )));
         }
-        self.format_tag_file(&new_map)
+        contents
+    }
+
+    /// Returns the product directory recorded in the group at the given index of a product
+    /// version, and the path of its table file, which is None if it was declared without one.
+    /// Relative product directories are relative to the directory containing this source, and
+    /// relative ups directories to the product directory. A table file recorded as an absolute
+    /// path is used in place of the table in the ups directory
+    fn group_table_paths(
+        &self,
+        product: &str,
+        version: &str,
+        index: usize,
+    ) -> Option<(PathBuf, Option<PathBuf>)> {
+        let db_file = self.product_to_version_info.get(product)?.get(version)?;
+        let prod_dir = db_file.get_group(index, "PROD_DIR")?;
+        let mut ups_dir = db_file.get_group(index, "UPS_DIR")?;
+        let table_file = db_file.get_group(index, "TABLE_FILE");
+        let prod_dir_path = super::PathBuf::from(prod_dir);
+        let mut complete = if prod_dir_path.is_absolute() {
+            prod_dir_path
+        } else {
+            // Relative product directories are resolved against the directory containing the
+            // ups_db directory, a source without one has nowhere to resolve them
+            let base = self.location.parent().map(path::Path::to_path_buf)?;
+            base.join(prod_dir_path)
+        };
+
+        let mut product_table_name = product.to_string();
+        product_table_name.push_str(".table");
+
+        let complete_only_path = complete.clone();
+        match table_file {
+            Some("none") => return Some((complete_only_path, None)),
+            Some(file) if path::Path::new(file).is_absolute() => {
+                return Some((complete_only_path, Some(PathBuf::from(file))));
+            }
+            _ => (),
+        }
+        if ups_dir == "none" {
+            ups_dir = "ups";
+        }
+
+        complete.push(ups_dir);
+        complete.push(product_table_name);
+        Some((complete_only_path, Some(complete)))
+    }
+
+    /// Returns the table for the group at the given index of a product version, reading it from
+    /// the product directory recorded in that group
+    fn get_group_table(&self, product: &str, version: &str, index: usize) -> Option<Table> {
+        let cache_key = (product.to_string(), version.to_string(), index);
+        {
+            if let Some(table) = self.table_cache.borrow().get(&cache_key) {
+                return Some(table.clone());
+            }
+        }
+        let (complete_only_path, complete) =
+            match self.group_table_paths(product, version, index)? {
+                (product_dir, Some(table_file)) => (product_dir, table_file),
+                (product_dir, None) => return Some(Table::empty(product.to_owned(), product_dir)),
+            };
+        crate::debug!(
+            "Making table for product {}, on path {}, with name {}",
+            product,
+            complete_only_path.to_str().unwrap(),
+            complete.to_str().unwrap()
+        );
+        let table = Table::from_file(product.to_owned(), complete, complete_only_path).ok();
+        if table.is_some() {
+            self.table_cache
+                .borrow_mut()
+                .insert(cache_key, table.as_ref().unwrap().clone());
+        }
+        table
     }
 
     /// Converts Posix database backend into a Json based database backend source
@@ -194,16 +395,16 @@ impl PosixDBImpl {
         // Create container objects
         let mut tag_to_product_info: FnvHashMap<
             String,
-            FnvHashMap<String, FnvHashMap<String, String>>,
+            FnvHashMap<String, Vec<FnvHashMap<String, String>>>,
         > = FnvHashMap::default();
         let mut product_to_version_info: FnvHashMap<
             String,
-            FnvHashMap<String, FnvHashMap<String, String>>,
+            FnvHashMap<String, Vec<FnvHashMap<String, String>>>,
         > = FnvHashMap::default();
         let mut product_to_ident: FnvHashMap<String, Vec<String>> = FnvHashMap::default();
         let mut product_ident_version: FnvHashMap<String, FnvHashMap<String, String>> =
             FnvHashMap::default();
-        let mut product_to_version_table: FnvHashMap<String, FnvHashMap<String, Table>> =
+        let mut product_to_version_table: FnvHashMap<String, FnvHashMap<String, Vec<Table>>> =
             FnvHashMap::default();
 
         // populate the tags to product map with maps created from the corresponding field in self
@@ -212,7 +413,12 @@ impl PosixDBImpl {
                 tag_to_product_info
                     .entry(tag.clone())
                     .or_insert(FnvHashMap::default())
-                    .insert(product.clone(), info.to_map());
+                    .insert(
+                        product.clone(),
+                        (0..info.group_count())
+                            .map(|i| info.group_to_map(i))
+                            .collect(),
+                    );
             }
         }
 
@@ -220,29 +426,31 @@ impl PosixDBImpl {
         // use as an identity. This is needed because JSON database sources require an identity to be
         // specified, as they are more strict than posix in this case
         let ident_empty = self.product_to_ident.is_none() && self.product_ident_version.is_none();
-        let mut hasher = Sha1::new();
         for (product, map) in self.product_to_version_info.iter() {
             for (version, info) in map.iter() {
-                // reset the hasher to an empty state to be reused
-                hasher.reset();
                 // insert a created map into the data structure form a corresponding data structure
                 // in self
                 product_to_version_info
                     .entry(product.clone())
                     .or_insert(FnvHashMap::default())
-                    .insert(version.clone(), info.to_map());
+                    .insert(
+                        version.clone(),
+                        (0..info.group_count())
+                            .map(|i| info.group_to_map(i))
+                            .collect(),
+                    );
                 // if there is no identity, hash the version to use as an identity. Insert in
                 // data structure
                 if ident_empty {
-                    hasher.input_str(version);
+                    let ident = hash_ident(version);
                     product_to_ident
                         .entry(product.clone())
                         .or_insert(vec![])
-                        .push(hasher.result_str());
+                        .push(ident.clone());
                     product_ident_version
                         .entry(product.clone())
                         .or_insert(FnvHashMap::default())
-                        .insert(hasher.result_str(), version.clone());
+                        .insert(ident, version.clone());
                 }
                 // Fetch tables and insert them into data structure. This is because a JSON
                 // database source keeps declared tables in the database structure instead of
@@ -250,7 +458,12 @@ impl PosixDBImpl {
                 product_to_version_table
                     .entry(product.clone())
                     .or_insert(FnvHashMap::default())
-                    .insert(version.clone(), self.get_table(product, version).unwrap());
+                    .insert(
+                        version.clone(),
+                        (0..info.group_count())
+                            .map(|i| self.get_group_table(product, version, i).unwrap())
+                            .collect(),
+                    );
             }
         }
         // if there are identities defined, copy those to new data structure
@@ -269,81 +482,273 @@ impl PosixDBImpl {
             product_to_version_table,
         }
     }
-}
 
-impl super::DBImpl for PosixDBImpl {
-    // copy methods defined in base into Posix impl
-    make_db_source_default_methods!();
+    /// Finds the repairs fix makes to this source, along with the changes which make them
+    fn plan_fix_changes(&self, paths: Option<super::PathStyle>) -> FixPlan {
+        use super::Repair;
+        let base = self.location.parent().unwrap().to_path_buf();
+        let mut repairs = vec![];
 
-    /// Returns a table corresponding to a given product and version
-    fn get_table(&self, product: &str, version: &str) -> Option<Table> {
-        let prod_string = product.to_string();
-        let vers_string = version.to_string();
+        // Group the dangling groups by the chain file they are in, and remove them from the end
+        // so the indices stay valid
+        let mut dangling: FnvHashMap<(String, String), Vec<usize>> = FnvHashMap::default();
+        for (tag, product, index) in
+            super::dangling_tags(&self.tag_to_product_info, &self.product_to_version_info)
         {
-            if self
-                .table_cache
-                .borrow()
-                .contains_key(&(prod_string.clone(), vers_string.clone()))
-            {
-                return self
-                    .table_cache
-                    .borrow()
-                    .get(&(prod_string, vers_string))
-                    .cloned();
+            dangling.entry((tag, product)).or_default().push(index);
+        }
+        let mut changed_tags = vec![];
+        let mut removed_tags = vec![];
+        for ((tag, product), mut indices) in dangling {
+            indices.sort_unstable_by(|a, b| b.cmp(a));
+            let tag_file = &self.tag_to_product_info[&tag][&product];
+            for index in indices.iter() {
+                repairs.push(Repair::new(
+                    Some(&product),
+                    None,
+                    tag_file.get_group(*index, "FLAVOR"),
+                    format!(
+                        "remove tag {} which points at undeclared version {}",
+                        tag,
+                        tag_file.get_group(*index, "VERSION").unwrap_or("")
+                    ),
+                ));
+            }
+            if indices.len() == tag_file.group_count() {
+                removed_tags.push((tag, product));
+            } else {
+                changed_tags.push((tag, product, indices));
             }
         }
-        let db_file = self.product_to_version_info.get(product)?.get(version)?;
-        let prod_dir = db_file.get(&"PROD_DIR")?;
-        let mut ups_dir = db_file.get(&"UPS_DIR")?;
-        let prod_dir_path = super::PathBuf::from(prod_dir);
-        let mut complete = if prod_dir_path.is_absolute() {
-            prod_dir_path
-        } else {
-            let base = self.location.parent().unwrap().clone();
-            base.join(prod_dir_path)
-        };
 
-        let mut product_table_name = product.to_string();
-        product_table_name.push_str(".table");
-
-        let complete_only_path = complete.clone();
-        if ups_dir == "none" {
-            ups_dir = &"ups";
+        // Find the rewritten product directories of each version file
+        let mut rewrites = vec![];
+        if let Some(style) = paths {
+            for (product, versions) in self.product_to_version_info.iter() {
+                for (version, db_file) in versions.iter() {
+                    for index in 0..db_file.group_count() {
+                        let prod_dir = match db_file.get_group(index, "PROD_DIR") {
+                            Some(x) => x,
+                            None => continue,
+                        };
+                        let rewritten = match super::rewrite_path(&base, prod_dir, style) {
+                            Some(x) => x,
+                            None => continue,
+                        };
+                        repairs.push(Repair::new(
+                            Some(product),
+                            Some(version),
+                            db_file.get_group(index, "FLAVOR"),
+                            format!("rewrite product directory {} as {}", prod_dir, rewritten),
+                        ));
+                        rewrites.push((product.clone(), version.clone(), index, rewritten));
+                    }
+                }
+            }
         }
 
-        complete.push(ups_dir);
-        complete.push(product_table_name);
-        crate::debug!(
-            "Making table for product {}, on path {}, with name {}",
-            product,
-            complete_only_path.to_str().unwrap(),
-            complete.to_str().unwrap()
-        );
-        let table = Table::from_file(product.to_owned(), complete, complete_only_path).ok();
-        if table.is_some() {
-            self.table_cache
-                .borrow_mut()
-                .insert((prod_string, vers_string), table.as_ref().unwrap().clone());
+        FixPlan {
+            repairs,
+            removed_tags,
+            changed_tags,
+            rewrites,
         }
-        table
+    }
+}
+
+impl super::DBImpl for PosixDBImpl {
+    // copy methods defined in base into Posix impl
+    make_db_source_default_methods!();
+
+    /// Returns a table corresponding to a given product and version, if it was declared for the
+    /// supplied flavor and qualifiers
+    fn get_table(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<Table> {
+        let index = super::find_group(
+            self.product_to_version_info.get(product)?.get(version)?,
+            flavor,
+            qualifiers,
+        )?;
+        self.get_group_table(product, version, index)
     }
 
     /// Returns if this database can be written to
     fn is_writable(&self) -> bool {
-        let mut test = self.get_location().clone();
-        test.push("readonly_test_file.txt");
-
-        let per = std::fs::OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&test);
-        match per {
-            Ok(_) => {
-                std::fs::remove_file(test).expect("Problem cleaning up readonly test file");
-                true
+        location_is_writable(&self.location)
+    }
+
+    /// Checks that the product directories and table files recorded in each version file exist,
+    /// that the tables can be parsed and depend only on declared products, and that each chain
+    /// file points at a version file
+    fn check(&self, is_declared: &dyn Fn(&str) -> bool) -> Vec<super::CheckProblem> {
+        use super::{CheckProblem, Severity};
+        let base = self.location.parent().unwrap();
+        let mut problems =
+            super::check_tags(&self.tag_to_product_info, &self.product_to_version_info);
+        for (product, versions) in self.product_to_version_info.iter() {
+            for (version, db_file) in versions.iter() {
+                for index in 0..db_file.group_count() {
+                    let flavor = db_file.get_group(index, "FLAVOR");
+                    let error = |message| {
+                        CheckProblem::new(
+                            Severity::Error,
+                            Some(product),
+                            Some(version),
+                            flavor,
+                            message,
+                        )
+                    };
+                    let prod_dir = match db_file.get_group(index, "PROD_DIR") {
+                        Some(x) => super::resolve_path(base, x),
+                        None => {
+                            problems.push(error("no product directory is recorded".to_string()));
+                            continue;
+                        }
+                    };
+                    if !prod_dir.exists() {
+                        problems.push(error(format!(
+                            "product directory {} does not exist",
+                            prod_dir.to_str().unwrap()
+                        )));
+                        continue;
+                    }
+                    let table_file = db_file.get_group(index, "TABLE_FILE");
+                    if table_file == Some("none") {
+                        continue;
+                    }
+                    let ups_dir = match db_file.get_group(index, "UPS_DIR") {
+                        Some("none") | None => "ups",
+                        Some(x) => x,
+                    };
+                    // The table file recorded may be relative to the source or the ups directory,
+                    // but the table reups reads is always the one in the ups directory
+                    let mut missing = vec![];
+                    if let Some(table_file) = table_file {
+                        let recorded = super::resolve_path(base, table_file);
+                        let in_ups_dir = prod_dir.join(ups_dir).join(table_file);
+                        if !recorded.exists() && !in_ups_dir.exists() {
+                            // A bare file name is a name in the ups directory
+                            if table_file.contains('/') {
+                                missing.push(recorded);
+                            } else {
+                                missing.push(in_ups_dir);
+                            }
+                        }
+                    }
+                    let table_path = match table_file.map(path::Path::new) {
+                        Some(file) if file.is_absolute() => file.to_path_buf(),
+                        _ => prod_dir.join(ups_dir).join(format!("{}.table", product)),
+                    };
+                    if !table_path.exists() && !missing.contains(&table_path) {
+                        missing.push(table_path.clone());
+                    }
+                    for path in missing.iter() {
+                        problems.push(error(format!(
+                            "table file {} does not exist",
+                            path.to_str().unwrap()
+                        )));
+                    }
+                    if !table_path.exists() {
+                        continue;
+                    }
+                    if let Err(msg) = Table::check_file(&table_path) {
+                        problems.push(error(format!(
+                            "table file {} could not be parsed: {}",
+                            table_path.to_str().unwrap(),
+                            msg
+                        )));
+                        continue;
+                    }
+                    if let Ok(table) = Table::from_file(product.clone(), table_path, prod_dir) {
+                        problems.extend(super::check_dependencies(
+                            &table,
+                            version,
+                            flavor,
+                            is_declared,
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /// Finds the groups of chain files which point at versions that are not declared, and the
+    /// product directories of version files to rewrite in the requested style. Identities are not
+    /// stored in posix sources, so there are none to create
+    fn plan_fix(&self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        Ok(self.plan_fix_changes(paths).repairs)
+    }
+
+    /// Removes the groups of chain files which point at versions that are not declared, deleting
+    /// chain files left without any, and rewrites the product directories of version files in the
+    /// requested style
+    fn fix(&mut self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        let FixPlan {
+            repairs,
+            removed_tags,
+            changed_tags,
+            rewrites,
+        } = self.plan_fix_changes(paths);
+        for (tag, product) in removed_tags.iter() {
+            self.tag_to_product_info
+                .get_mut(tag)
+                .unwrap()
+                .remove(product);
+            if let Some(tags) = self.product_to_tags.get_mut(product) {
+                tags.retain(|x| x != tag);
+            }
+        }
+        for (tag, product, indices) in changed_tags.iter() {
+            let tag_file = self
+                .tag_to_product_info
+                .get_mut(tag)
+                .and_then(|x| x.get_mut(product))
+                .unwrap();
+            for index in indices {
+                tag_file.remove_group(*index);
+            }
+        }
+        let mut changed_versions = vec![];
+        for (product, version, index, rewritten) in rewrites {
+            let db_file = self
+                .product_to_version_info
+                .get_mut(&product)
+                .and_then(|x| x.get_mut(&version))
+                .unwrap();
+            db_file.set_group(index, "PROD_DIR", &rewritten);
+            if !changed_versions.contains(&(product.clone(), version.clone())) {
+                changed_versions.push((product, version));
             }
-            Err(_) => false,
         }
+
+        self.remove_index_cache();
+        let write_error =
+            |path: &PathBuf, e: std::io::Error| format!("Problem writing {:?}: {}", path, e);
+        for (tag, product) in removed_tags {
+            let path = self.location.join(&product).join(format!("{}.chain", tag));
+            fs::remove_file(&path).map_err(|e| write_error(&path, e))?;
+        }
+        for (tag, product, _) in changed_tags {
+            let path = self.location.join(&product).join(format!("{}.chain", tag));
+            let contents = self.format_tag_dbfile(&self.tag_to_product_info[&tag][&product]);
+            fs::write(&path, contents).map_err(|e| write_error(&path, e))?;
+        }
+        for (product, version) in changed_versions {
+            let path = self
+                .location
+                .join(&product)
+                .join(format!("{}.version", version));
+            let contents =
+                self.format_version_dbfile(&self.product_to_version_info[&product][&version]);
+            fs::write(&path, contents).map_err(|e| write_error(&path, e))?;
+        }
+        Ok(repairs)
     }
 
     /// Declare inputs to the database in memory only
@@ -361,17 +766,26 @@ impl super::DBImpl for PosixDBImpl {
         // verify that all inputs to be declared are not in the db already
         for input in inputs.iter() {
             let version = check_version_name(input);
+            let flav = input.flavor.unwrap_or("");
+            let qualifiers = input.qualifiers.unwrap_or("");
             // check that none of the supplied info is in the database, this must be done
             // not quite elegantly at the same time as insertion because we don't want to do
             // any insertions unless the database does not contain any of the info
 
-            // check that the version is not already in the database
-            if self.product_to_version_info.contains_key(input.product)
-                && self.product_to_version_info[input.product].contains_key(&version)
+            // check that the version is not already in the database for this flavor and
+            // qualifiers, other groups may be added to an existing version, and an existing
+            // version may be given a new tag
+            if input.tag.is_none()
+                && self
+                    .product_to_version_info
+                    .get(input.product)
+                    .and_then(|x| x.get(input.version))
+                    .and_then(|x| super::position_group(x, flav, qualifiers))
+                    .is_some()
             {
                 return Err(format!(
-                    "Database already contains product {} with version {}",
-                    input.product, version
+                    "Database already contains product {} with version {} for flavor {} and qualifiers \"{}\"",
+                    input.product, version, flav, qualifiers
                 ));
             }
 
@@ -379,8 +793,12 @@ impl super::DBImpl for PosixDBImpl {
             // This check assumes that tag keys were added to all data members
             // appropriately
             if let Some(tg) = input.tag {
-                if self.tag_to_product_info.contains_key(tg)
-                    && self.tag_to_product_info[tg].contains_key(input.product)
+                if self
+                    .tag_to_product_info
+                    .get(tg)
+                    .and_then(|x| x.get(input.product))
+                    .and_then(|x| super::position_group(x, flav, qualifiers))
+                    .is_some()
                 {
                     return Err(format!(
                         "Database already contains tag {} for product {} version {}",
@@ -390,11 +808,13 @@ impl super::DBImpl for PosixDBImpl {
             }
 
             // This check assumes that ident keys were added to all data members
-            // appropriately
+            // appropriately, an identity may be shared by the groups of a single version
             if let Some(id) = input.ident {
                 if let Some(prod_map) = self.product_ident_version.as_ref() {
-                    if prod_map.contains_key(input.product)
-                        && prod_map[input.product].contains_key(id)
+                    if prod_map
+                        .get(input.product)
+                        .and_then(|x| x.get(id))
+                        .is_some_and(|x| x != input.version)
                     {
                         return Err(format!(
                             "Database already contains id {} for product {} version {}",
@@ -410,15 +830,15 @@ impl super::DBImpl for PosixDBImpl {
             local_base_dir.push(input.product);
 
             let (user, date) = super::get_declare_info();
-            let flav = if let Some(flav) = input.flavor {
-                flav
-            } else {
-                ""
+            let flav = input.flavor.unwrap_or("");
+            let ups_dir = input.ups_dir.unwrap_or("ups");
+            let table_file = match input.table_file {
+                Some(table_file) => PathBuf::from(table_file),
+                None => input
+                    .prod_dir
+                    .join(ups_dir)
+                    .join(format!("{}{}", input.product, ".table")),
             };
-            let ups_dir = "ups";
-            let mut table_file = input.prod_dir.clone();
-            table_file.push(ups_dir);
-            table_file.push(format!("{}{}", input.product, ".table"));
             let mut version_map = FnvHashMap::default();
             let version_dbfile = if let Some(id) = input.ident {
                 format!("{}-{}", input.version, id)
@@ -429,6 +849,7 @@ impl super::DBImpl for PosixDBImpl {
             version_map.insert("product", input.product);
             version_map.insert("version", version_dbfile.as_str());
             version_map.insert("flavor", flav);
+            version_map.insert("qualifiers", input.qualifiers.unwrap_or(""));
             version_map.insert("user", user.as_str());
             version_map.insert("date", date.as_str());
             let abs_prod_dir = if input.relative {
@@ -458,16 +879,33 @@ impl super::DBImpl for PosixDBImpl {
             let mut version_dir = local_base_dir.clone();
             version_dir.push(format!("{}.version", version));
 
-            self.product_to_version_info
+            let new_version = DBFile::new_with_contents(version_dir, version_contents);
+            let versions = self
+                .product_to_version_info
                 .entry(input.product.to_string())
-                .or_insert(FnvHashMap::default())
-                .entry(version.clone())
-                .or_insert(DBFile::new_with_contents(version_dir, version_contents));
-
-            if let Some(tbl) = &input.table {
-                self.table_cache
-                    .borrow_mut()
-                    .insert((input.product.to_string(), version.clone()), tbl.clone());
+                .or_insert(FnvHashMap::default());
+            // Add a group to the version if it already exists, a version which already has this
+            // group is only being tagged
+            let qualifiers = input.qualifiers.unwrap_or("");
+            let (index, added) = match versions.get_mut(&version) {
+                Some(existing) => match super::position_group(existing, flav, qualifiers) {
+                    Some(index) => (index, false),
+                    None => {
+                        existing.push_group(new_version.group_to_map(0));
+                        (existing.group_count() - 1, true)
+                    }
+                },
+                None => {
+                    versions.insert(version.clone(), new_version);
+                    (0, true)
+                }
+            };
+
+            if let (true, Some(tbl)) = (added, &input.table) {
+                self.table_cache.borrow_mut().insert(
+                    (input.product.to_string(), version.clone(), index),
+                    tbl.clone(),
+                );
             }
 
             if let Some(tg) = input.tag {
@@ -477,12 +915,19 @@ impl super::DBImpl for PosixDBImpl {
                 let mut tag_dir = local_base_dir.clone();
                 tag_dir.push(format!("{}.chain", tg));
 
-                // insert the info about the product tags into the database
-                self.tag_to_product_info
+                // insert the info about the product tags into the database, adding a group if
+                // the tag already exists for another flavor or qualifiers
+                let new_tag = DBFile::new_with_contents(tag_dir, tag_contents);
+                let tags = self
+                    .tag_to_product_info
                     .entry(tg.to_string())
-                    .or_insert(FnvHashMap::default())
-                    .entry(input.product.to_string())
-                    .or_insert(DBFile::new_with_contents(tag_dir, tag_contents));
+                    .or_insert(FnvHashMap::default());
+                match tags.get_mut(input.product) {
+                    Some(existing) => existing.push_group(new_tag.group_to_map(0)),
+                    None => {
+                        tags.insert(input.product.to_string(), new_tag);
+                    }
+                }
 
                 if self
                     .product_to_tags
@@ -529,6 +974,7 @@ impl super::DBImpl for PosixDBImpl {
     /// Sync a given product to the database source storage backend
     fn sync(&self, product: &str) -> std::io::Result<()> {
         crate::info!("Running sync in posix_db_impl for product {}", product);
+        self.remove_index_cache();
         // Get a string representation of the file contents
         // Make sure product directory exists
         let mut product_dir = self.location.clone();
@@ -546,7 +992,12 @@ impl super::DBImpl for PosixDBImpl {
                         let mut table_dir = product_dir.clone();
                         table_dir.push(format!("{}.chain", tag));
                         if table_dir.exists() {
-                            continue;
+                            // Only rewrite an existing file if there are groups to add to it
+                            if let Some(merged) = merge_groups(&table_dir, tag_file) {
+                                let tag_contents = self.format_tag_dbfile(&merged);
+                                crate::info!("Adding groups to tag {} file for {}", tag, product);
+                                fs::write(table_dir, tag_contents)?;
+                            }
                         } else {
                             let tag_contents = self.format_tag_dbfile(tag_file);
                             crate::info!("Syncing tag {} file for {} to disk", tag, product);
@@ -576,47 +1027,65 @@ impl super::DBImpl for PosixDBImpl {
                 let mut version_dir = product_dir.clone();
                 version_dir.push(format!("{}.version", k));
                 if version_dir.exists() {
-                    crate::debug!(
-                        "Product {} with version {} already exists, skipping",
-                        product,
-                        k
-                    );
-                    continue;
+                    // Only rewrite an existing file if there are groups to add to it
+                    match merge_groups(&version_dir, v) {
+                        Some(merged) => {
+                            let version_contents = self.format_version_dbfile(&merged);
+                            crate::debug!("Adding groups to version {} file for {}", k, product);
+                            fs::write(version_dir, version_contents)?;
+                        }
+                        None => {
+                            crate::debug!(
+                                "Product {} with version {} already exists, skipping",
+                                product,
+                                k
+                            );
+                            continue;
+                        }
+                    }
                 } else {
                     let version_contents = self.format_version_dbfile(v);
                     crate::debug!("Syncing version {} file for {} to disk", k, product);
                     fs::write(version_dir, version_contents)?;
                 }
-                if let Some(tbl) = self
-                    .table_cache
-                    .borrow()
-                    .get(&(product.to_string(), k.to_string()))
-                {
+                for index in 0..v.group_count() {
+                    let table_cache = self.table_cache.borrow();
+                    let tbl = match table_cache.get(&(product.to_string(), k.to_string(), index)) {
+                        Some(tbl) => tbl,
+                        None => continue,
+                    };
                     crate::debug!(
                         "Table for {} version {} exists in cache, checking if it needs saved",
                         product,
                         k
                     );
-                    let product_dir = PathBuf::from(v.get("PROD_DIR").unwrap());
-                    let mut table_dir = product_dir.clone();
-                    table_dir.push("ups");
-                    table_dir.push(format!("{}.table", product));
-                    let on_disk_table = super::Table::from_file(
-                        product.to_string(),
-                        table_dir.clone(),
-                        product_dir,
-                    );
-                    if &on_disk_table? != tbl {
+                    // Products declared without a table have no table file to write
+                    let (product_dir, table_dir) = match self.group_table_paths(product, k, index) {
+                        Some((product_dir, Some(table_dir))) => (product_dir, table_dir),
+                        _ => continue,
+                    };
+                    if table_dir.exists() {
+                        let on_disk_table = super::Table::from_file(
+                            product.to_string(),
+                            table_dir.clone(),
+                            product_dir,
+                        );
+                        if &on_disk_table? == tbl {
+                            continue;
+                        }
                         crate::debug!(
                             "In memory table is different than on disk, saving table to disk"
                         );
-                        tbl.to_file(table_dir.to_str().unwrap()).or_else(|e| {
-                            return Err(std::io::Error::new(
-                                std::io::ErrorKind::Other,
-                                format!("{}", e),
-                            ));
-                        })?;
+                    } else {
+                        crate::debug!("Table is not on disk, saving table to disk");
+                        fs::create_dir_all(table_dir.parent().unwrap())?;
                     }
+                    tbl.to_file(table_dir.to_str().unwrap()).or_else(|e| {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            format!("{}", e),
+                        ));
+                    })?;
                 }
             }
         } else {
@@ -626,33 +1095,595 @@ impl super::DBImpl for PosixDBImpl {
     }
 }
 
+/// Returns if a file can be created in the directory at location
+fn location_is_writable(location: &path::Path) -> bool {
+    let test = location.join("readonly_test_file.txt");
+
+    let per = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&test);
+    match per {
+        Ok(_) => {
+            std::fs::remove_file(test).expect("Problem cleaning up readonly test file");
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Posix database source which reads each product off disk the first time it is used. Creating
+/// the source only lists the product directories in the source, so commands which resolve the
+/// dependencies of a few products do not stat or read the directories of any others. Each
+/// product is held in its own PosixDBImpl once it is read. Methods which span the whole source,
+/// such as get_products and check, read every product.
+#[derive(Debug)]
+pub struct LazyPosixDBImpl {
+    location: PathBuf,
+    products: FnvHashMap<String, OnceCell<Option<PosixDBImpl>>>,
+    ident_regex: Option<regex::Regex>,
+    options: PosixReadOptions,
+}
+
+impl LazyPosixDBImpl {
+    /// Creates a new lazily read Posix database source given a filesystem location, a regular
+    /// expression that may be used to parse versions to extract identity strings, and the options
+    /// products are read with. Products are never read through the index cache, as the cache
+    /// holds the whole source.
+    pub fn new(
+        path: PathBuf,
+        ident_regex: Option<regex::Regex>,
+        options: &PosixReadOptions,
+    ) -> Result<LazyPosixDBImpl, String> {
+        let entries = fs::read_dir(&path).map_err(|_| {
+            format!(
+                "Problem reading database at {}",
+                path.to_str().unwrap_or_default()
+            )
+        })?;
+        let mut products = FnvHashMap::default();
+        for entry in entries.flatten() {
+            // The type of an entry usually comes from the directory listing itself, so this does
+            // not stat the product directories
+            let is_product = entry
+                .file_type()
+                .is_ok_and(|x| x.is_dir() || x.is_symlink());
+            if let (true, Some(name)) = (is_product, entry.file_name().to_str()) {
+                products.insert(name.to_string(), OnceCell::new());
+            }
+        }
+        Ok(LazyPosixDBImpl {
+            location: path,
+            products,
+            ident_regex,
+            options: options.clone(),
+        })
+    }
+
+    /// Returns the number of products which have been read off disk
+    pub fn loaded_count(&self) -> usize {
+        self.products.values().filter(|x| x.get().is_some()).count()
+    }
+
+    /// Returns the source holding a product, reading it off disk if this is the first time it is
+    /// used. None is returned if the product is not in the source
+    fn product(&self, product: &str) -> Option<&PosixDBImpl> {
+        self.products
+            .get(product)?
+            .get_or_init(|| {
+                crate::debug!(
+                    "Lazily reading product {} from {:?}",
+                    product,
+                    self.location
+                );
+                match PosixDBImpl::new_product(
+                    self.location.clone(),
+                    product,
+                    Some(&DBLoadControl::All),
+                    self.ident_regex.clone(),
+                    &self.options,
+                ) {
+                    Ok(x) => Some(x),
+                    Err(e) => {
+                        crate::warn!("Problem reading product {}: {}", product, e);
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+
+    /// Returns the source holding a product mutably, reading it off disk if needed. A product
+    /// which is not in the source yet is added empty, so that it may be declared
+    fn product_mut(&mut self, product: &str) -> Option<&mut PosixDBImpl> {
+        self.products.entry(product.to_string()).or_default();
+        self.product(product);
+        self.products.get_mut(product)?.get_mut()?.as_mut()
+    }
+
+    /// Returns the sources of all the products, reading any which have not been used yet
+    fn all_products(&self) -> impl Iterator<Item = &PosixDBImpl> {
+        self.products.keys().filter_map(move |x| self.product(x))
+    }
+}
+
+impl super::DBImpl for LazyPosixDBImpl {
+    fn get_location(&self) -> &super::PathBuf {
+        &self.location
+    }
+
+    fn get_table(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<Table> {
+        self.product(product)?
+            .get_table(product, version, flavor, qualifiers)
+    }
+
+    fn get_tags(&self, product: &str) -> Option<Vec<&str>> {
+        self.product(product)?.get_tags(product)
+    }
+
+    fn get_versions(&self, product: &str) -> Option<Vec<&str>> {
+        self.product(product)?.get_versions(product)
+    }
+
+    fn get_products(&self) -> Vec<&str> {
+        self.all_products().flat_map(|x| x.get_products()).collect()
+    }
+
+    fn get_identities(&self, product: &str) -> Option<Vec<&str>> {
+        self.product(product)?.get_identities(product)
+    }
+
+    fn get_flavors(&self, product: &str, version: &str) -> Option<Vec<&str>> {
+        self.product(product)?.get_flavors(product, version)
+    }
+
+    fn get_groups(&self, product: &str, version: &str) -> Option<Vec<(&str, &str)>> {
+        self.product(product)?.get_groups(product, version)
+    }
+
+    fn get_tag_groups(&self, product: &str, tag: &str) -> Option<Vec<(&str, &str, &str)>> {
+        self.product(product)?.get_tag_groups(product, tag)
+    }
+
+    fn lookup_flavor_version(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.product(product)?
+            .lookup_flavor_version(product, version, flavor, qualifiers)
+    }
+
+    fn lookup_version_tag(
+        &self,
+        product: &str,
+        tag: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.product(product)?
+            .lookup_version_tag(product, tag, flavor, qualifiers)
+    }
+
+    fn lookup_version_ident(&self, product: &str, ident: &str) -> Option<&str> {
+        self.product(product)?.lookup_version_ident(product, ident)
+    }
+
+    fn lookup_location_version(&self, product: &str, version: &str) -> Option<&PathBuf> {
+        self.product(product)?
+            .lookup_location_version(product, version)
+    }
+
+    fn has_identity(&self, product: &str, ident: &str) -> bool {
+        self.product(product)
+            .is_some_and(|x| x.has_identity(product, ident))
+    }
+
+    fn has_product(&self, product: &str) -> bool {
+        self.product(product)
+            .is_some_and(|x| x.has_product(product))
+    }
+
+    fn identities_populated(&self) -> bool {
+        self.ident_regex.is_some()
+    }
+
+    fn is_writable(&self) -> bool {
+        location_is_writable(&self.location)
+    }
+
+    fn check(&self, is_declared: &dyn Fn(&str) -> bool) -> Vec<super::CheckProblem> {
+        self.all_products()
+            .flat_map(|x| x.check(is_declared))
+            .collect()
+    }
+
+    fn plan_fix(&self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        let mut repairs = vec![];
+        for source in self.all_products() {
+            repairs.extend(source.plan_fix(paths)?);
+        }
+        Ok(repairs)
+    }
+
+    fn fix(&mut self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        let products: Vec<String> = self.products.keys().cloned().collect();
+        let mut repairs = vec![];
+        for product in products {
+            if let Some(source) = self.product_mut(&product) {
+                repairs.extend(source.fix(paths)?);
+            }
+        }
+        Ok(repairs)
+    }
+
+    /// Declares the inputs in memory, in the source of the product of each
+    fn declare_in_memory_impl(&mut self, inputs: &Vec<super::DeclareInputs>) -> Result<(), String> {
+        let mut product_inputs: FnvHashMap<&str, Vec<super::DeclareInputs>> = FnvHashMap::default();
+        for input in inputs.iter() {
+            product_inputs
+                .entry(input.product)
+                .or_default()
+                .push(input.clone());
+        }
+        for (product, inputs) in product_inputs {
+            let location = self.location.clone();
+            self.product_mut(product)
+                .ok_or_else(|| format!("Problem reading product {} from {:?}", product, location))?
+                .declare_in_memory_impl(&inputs)?;
+        }
+        Ok(())
+    }
+
+    fn sync(&self, product: &str) -> std::io::Result<()> {
+        match self.product(product) {
+            Some(source) => source.sync(product),
+            None => Err(std::io::Error::other(format!(
+                "Problem looking up product {} to sync",
+                product
+            ))),
+        }
+    }
+}
+
+/// Reads the database file at path, and adds any groups of in_memory which it does not already
+/// contain. The merged file is returned if there were groups added.
+fn merge_groups(path: &path::Path, in_memory: &DBFile) -> Option<DBFile> {
+    let mut on_disk = DBFile::new(path.to_path_buf(), true);
+    let mut changed = false;
+    for index in 0..in_memory.group_count() {
+        let (flavor, qualifiers) = super::group_key(in_memory, index);
+        if super::position_group(&on_disk, flavor, qualifiers).is_none() {
+            on_disk.push_group(in_memory.group_to_map(index));
+            changed = true;
+        }
+    }
+    if changed {
+        Some(on_disk)
+    } else {
+        None
+    }
+}
+
+/// Builds a map from the template fields to the values of a group in a DBFile. Qualifiers are
+/// stored quoted, so the quotes are removed as the templates add their own. Any value missing
+/// from the file is left empty
+fn translate_dbfile<'a>(
+    dbfile: &'a DBFile,
+    index: usize,
+    fields: &[(&'static str, &str)],
+) -> FnvHashMap<&'static str, &'a str> {
+    let mut map = FnvHashMap::default();
+    for (field, key) in fields.iter() {
+        crate::debug!("inserting key value: {}, {}", field, key);
+        let value = dbfile.get_group(index, key).unwrap_or("");
+        if *field == "qualifiers" {
+            map.insert(*field, super::normalize_qualifiers(value));
+        } else {
+            map.insert(*field, value);
+        }
+    }
+    map
+}
+
+/// Creates an identity for a version which was declared without one, by hashing the version
+pub fn hash_ident(version: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input_str(version);
+    hasher.result_str()
+}
+
+/// Creates an identity for a version declared without one from what is being declared, by
+/// hashing the product, version, product directory and the contents of the table file if there is
+/// one. Declaring the same build again gives the same identity
+pub fn content_ident(
+    product: &str,
+    version: &str,
+    prod_dir: &path::Path,
+    table_file: Option<&path::Path>,
+) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input_str(product);
+    hasher.input_str(version);
+    hasher.input_str(prod_dir.to_str().unwrap_or(""));
+    if let Some(contents) = table_file.and_then(|x| fs::read(x).ok()) {
+        hasher.input(&contents);
+    }
+    hasher.result_str()
+}
+
+/// Extracts an identity from a version string with the supplied regular expression. If the
+/// expression contains a capture group named ident, the text matched by that group is the
+/// identity, otherwise the text matched by the whole expression is used.
+pub fn extract_ident(ident_regex: &regex::Regex, version: &str) -> Option<String> {
+    let captures = ident_regex.captures(version)?;
+    let found = match captures.name("ident") {
+        Some(x) => x,
+        None => captures.get(0)?,
+    };
+    if found.as_str().is_empty() {
+        return None;
+    }
+    Some(found.as_str().to_string())
+}
+
+/// Work done by the threads reading a posix source, either listing the directory of a product,
+/// or reading one of the version or chain files found in it
+enum Job {
+    Directory(PathBuf),
+    Version(String, PathBuf),
+    Tag(String, PathBuf),
+}
+
+/// Files read by a thread, as (product, version or tag, file, modification time of the file)
+type ReadFiles = Vec<(String, String, DBFile, Option<index_cache::Modified>)>;
+
+/// Finds the next job for a thread, first from its own queue, then from the shared queue, and
+/// finally by stealing from the queues of the other threads
+fn find_job(
+    local: &Worker<Job>,
+    injector: &Injector<Job>,
+    stealers: &[Stealer<Job>],
+) -> Option<Job> {
+    local.pop().or_else(|| {
+        std::iter::repeat_with(|| {
+            injector
+                .steal_batch_and_pop(local)
+                .or_else(|| stealers.iter().map(|s| s.steal()).collect())
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success())
+    })
+}
+
+/// Returns the name of a product directory
+fn file_name(path: &path::Path) -> String {
+    path.file_name().unwrap().to_str().unwrap().to_string()
+}
+
+/// Returns the name of a database file with its extension removed
+fn file_stem(path: &path::Path) -> String {
+    path.file_stem().unwrap().to_str().unwrap().to_string()
+}
+
+/// Progress of the threads reading a posix source
+struct ReadState {
+    // Jobs which are queued or running, the threads are done when there are none left
+    pending: usize,
+    // The first error hit by any of the threads, which stops all of them
+    error: Option<String>,
+}
+
+/// State shared by the threads reading a posix source. Jobs are only queued while the state is
+/// locked, so a thread which finds no job while holding the lock can wait on the condition
+/// variable without missing any.
+struct ReadProgress {
+    state: Mutex<ReadState>,
+    changed: Condvar,
+}
+
+impl ReadProgress {
+    fn lock(&self) -> MutexGuard<'_, ReadState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds jobs to the queue of a thread, waking any idle threads to steal them
+    fn queue(&self, local: &Worker<Job>, jobs: Vec<Job>) {
+        if jobs.is_empty() {
+            return;
+        }
+        let mut state = self.lock();
+        state.pending += jobs.len();
+        for job in jobs {
+            local.push(job);
+        }
+        self.changed.notify_all();
+    }
+
+    /// Waits until a job can be found, returning None once there are no jobs left or one of
+    /// the threads failed
+    fn wait_for_job(&self, find: impl Fn() -> Option<Job>) -> Option<Job> {
+        let mut state = self.lock();
+        loop {
+            if state.pending == 0 || state.error.is_some() {
+                return None;
+            }
+            if let Some(job) = find() {
+                return Some(job);
+            }
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Marks a job as done, recording the error if it failed, and returns true if the thread
+    /// should stop because one of the threads failed
+    fn finish(&self, result: Result<(), String>) -> bool {
+        let mut state = self.lock();
+        state.pending -= 1;
+        if let Err(msg) = result {
+            state.error.get_or_insert(msg);
+        }
+        if state.pending == 0 || state.error.is_some() {
+            self.changed.notify_all();
+        }
+        state.error.is_some()
+    }
+}
+
+/// Records a failure if a thread reading a posix source panics, so the other threads stop
+/// rather than waiting on a job which will never finish
+struct PanicGuard<'a>(&'a ReadProgress);
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let mut state = self.0.lock();
+            state
+                .error
+                .get_or_insert_with(|| "A thread reading a posix source panicked".to_string());
+            self.0.changed.notify_all();
+        }
+    }
+}
+
+/// Lists the directory of a product, returning a job for each of its version and chain files
+fn list_directory(directory: &path::Path) -> Result<Vec<Job>, String> {
+    let product = file_name(directory);
+    let contents = fs::read_dir(directory)
+        .map_err(|e| format!("Problem reading directory {:?}: {}", directory, e))?;
+    let mut jobs = vec![];
+    for file in contents {
+        let file = file
+            .map_err(|e| format!("Problem reading directory {:?}: {}", directory, e))?
+            .path();
+        match file.extension().and_then(|x| x.to_str()) {
+            Some("version") => jobs.push(Job::Version(product.clone(), file)),
+            Some("chain") => jobs.push(Job::Tag(product.clone(), file)),
+            _ => (),
+        }
+    }
+    Ok(jobs)
+}
+
+/// Reads the product directories with a pool of threads sharing a work stealing queue. Each
+/// directory listed adds a job for each of its files to the queue of the thread which listed it,
+/// which idle threads steal from, so the work stays balanced however the files are spread between
+/// products. The version and tag files read are returned, or the first error any thread hit. If
+/// stat is set, the modification time of each file is taken before it is read.
+fn read_directories(
+    directories: Vec<PathBuf>,
+    workers: usize,
+    load_version: bool,
+    load_tag: bool,
+    stat: bool,
+) -> Result<(ReadFiles, ReadFiles), String> {
+    let injector = Injector::new();
+    let progress = ReadProgress {
+        state: Mutex::new(ReadState {
+            pending: directories.len(),
+            error: None,
+        }),
+        changed: Condvar::new(),
+    };
+    for directory in directories {
+        injector.push(Job::Directory(directory));
+    }
+    let queues: Vec<Worker<Job>> = (0..workers.max(1)).map(|_| Worker::new_fifo()).collect();
+    let stealers: Vec<Stealer<Job>> = queues.iter().map(|x| x.stealer()).collect();
+
+    thread::scope(|scope| {
+        let threads: Vec<_> = queues
+            .into_iter()
+            .map(|local| {
+                let (injector, stealers, progress) = (&injector, &stealers, &progress);
+                let stat_file = |file: &path::Path| stat.then(|| index_cache::modified(file))?;
+                scope.spawn(move || {
+                    let _guard = PanicGuard(progress);
+                    let (mut versions, mut tags) = (vec![], vec![]);
+                    loop {
+                        let job = match find_job(&local, injector, stealers) {
+                            Some(job) => job,
+                            None => {
+                                match progress.wait_for_job(|| find_job(&local, injector, stealers))
+                                {
+                                    Some(job) => job,
+                                    None => break,
+                                }
+                            }
+                        };
+                        let result = match job {
+                            Job::Directory(directory) => {
+                                list_directory(&directory).map(|jobs| progress.queue(&local, jobs))
+                            }
+                            Job::Version(product, file) => {
+                                let version = file_stem(&file);
+                                let modified = stat_file(&file);
+                                let file = DBFile::new(file, load_version);
+                                versions.push((product, version, file, modified));
+                                Ok(())
+                            }
+                            Job::Tag(product, file) => {
+                                let tag = file_stem(&file);
+                                let modified = stat_file(&file);
+                                tags.push((product, tag, DBFile::new(file, load_tag), modified));
+                                Ok(())
+                            }
+                        };
+                        if progress.finish(result) {
+                            break;
+                        }
+                    }
+                    (versions, tags)
+                })
+            })
+            .collect();
+        // A thread which panicked has recorded an error, which is returned below
+        let results: Vec<_> = threads.into_iter().map(|x| x.join()).collect();
+        if let Some(msg) = progress.lock().error.take() {
+            return Err(msg);
+        }
+        let (mut versions, mut tags) = (vec![], vec![]);
+        for (thread_versions, thread_tags) in results.into_iter().flatten() {
+            versions.extend(thread_versions);
+            tags.extend(thread_tags);
+        }
+        Ok((versions, tags))
+    })
+}
+
+/// The location of a source, a mapping of product to version to version file, a mapping of tag to
+/// product to chain file, and the tags of each product
+type SourceFiles = (
+    path::PathBuf,
+    FnvHashMap<String, FnvHashMap<String, DBFile>>,
+    FnvHashMap<String, FnvHashMap<String, DBFile>>,
+    FnvHashMap<String, Vec<String>>,
+);
+
 /// This function builds all the components which go into the creation of a database.
 /// The functionality was sufficiently complex that it was factored out of new for the
-/// sake of readability. The function makes heavy use of system threads to create worker
-/// pools to speed up the process of reading all the database information off disk, as
-/// io is inherently an asynchronous process.
+/// sake of readability. The directories of the products are read by a pool of threads, as io is
+/// inherently an asynchronous process, whose size is set in the options. If the options have a
+/// cache directory, the directories of products which are current in the index cache are not
+/// walked. If a product is supplied, only the directory of that product is read, without the
+/// cache.
 fn build_db(
     eups_path: PathBuf,
     load_options: Option<&DBLoadControl>,
-) -> Result<
-    (
-        path::PathBuf,
-        FnvHashMap<String, FnvHashMap<String, DBFile>>,
-        FnvHashMap<String, FnvHashMap<String, DBFile>>,
-        FnvHashMap<String, Vec<String>>,
-    ),
-    String,
-> {
-    // Create channels that each of the threads will communicate over
-    let (name_tx, name_rx) = mpsc::channel::<(String, path::PathBuf)>();
-    let (tag_tx, tag_rx) = mpsc::channel::<(String, path::PathBuf)>();
-    let (worker1_tx, worker1_rx) = mpsc::channel::<path::PathBuf>();
-    let (worker2_tx, worker2_rx) = mpsc::channel::<path::PathBuf>();
-
-    // bundle the woker communication end points so that they can be looped over
-    let worker_tx_vec = vec![worker1_tx, worker2_tx];
-    let worker_rx_vec = vec![worker1_rx, worker2_rx];
-
+    options: &PosixReadOptions,
+    product: Option<&str>,
+) -> Result<SourceFiles, String> {
     let (mut load_version, mut load_tag) = (false, false);
     match load_options {
         Some(DBLoadControl::Versions) => {
@@ -665,176 +1696,114 @@ fn build_db(
             load_version = true;
             load_tag = true;
         }
-        None => (),
+        Some(DBLoadControl::Lazy) | None => (),
+    }
+    let cache_dir = options.cache_dir.as_deref().filter(|_| product.is_none());
+    let cache = cache_dir.map(|x| IndexCache::load(x, &eups_path));
+    if cache.is_some() {
+        // Files which are read are stored in the cache, so they must be loaded now
+        load_version = true;
+        load_tag = true;
     }
 
-    let names_thread = thread::spawn(move || {
-        // #product -> #version -> struct(path, info)
-        let mut product_hash: FnvHashMap<String, FnvHashMap<String, DBFile>> =
-            FnvHashMap::default();
+    // Products whose directories are unchanged since they were cached, and the modification
+    // times of the directories which are walked
+    let mut current = FnvHashSet::<String>::default();
+    let mut walked = FnvHashMap::<String, index_cache::Modified>::default();
 
-        // create a pool of workers to make dbfiles
-        let mut tx_vec = vec![];
-        let mut threads_vec = vec![];
-        for _ in 0..2 {
-            let (tx, rx) = mpsc::channel::<(String, String, path::PathBuf, bool)>();
-            tx_vec.push(tx);
-            threads_vec.push(thread::spawn(move || {
-                let mut dbfiles = vec![];
-                for (version, product, path, preload) in rx {
-                    dbfiles.push((version, product, DBFile::new(path, preload)));
-                }
-                dbfiles
-            }));
-        }
-        // block to ensure chained iterator goes out of scope
-        {
-            let mut tx_vec_cycle = tx_vec.iter().cycle();
-            for (product, file) in name_rx {
-                let version;
-                // The code below is scoped so that the borrow of file goes out scope and
-                // the file can be moved into the DBFile constructor
-                {
-                    let version_file_name = file.file_name().unwrap().to_str().unwrap();
-                    let version_str: Vec<&str> = version_file_name.split(".version").collect();
-                    version = String::from(version_str[0]);
-                }
-                tx_vec_cycle
-                    .next()
-                    .unwrap()
-                    .send((version, product, file, load_version))
-                    .unwrap();
+    let mut directories = vec![];
+    let directory_iterator = match (product, fs::read_dir(&eups_path)) {
+        (Some(product), _) => {
+            let product_dir = eups_path.join(product);
+            if product_dir.is_dir() {
+                directories.push(product_dir);
             }
+            None
         }
-        // work is done collect from threads
-        drop(tx_vec);
-        for thread in threads_vec {
-            let result = thread.join().unwrap();
-            for (version, product, dbfile) in result {
-                let version_hash = product_hash.entry(product).or_insert(FnvHashMap::default());
-                version_hash.insert(version, dbfile);
-            }
+        (None, Ok(x)) => Some(x),
+        (None, Err(_)) => {
+            return Err(format!(
+                "Problem reading database at {}",
+                eups_path.to_str().unwrap()
+            ));
         }
-        product_hash
-    });
-
-    let tags_thread = thread::spawn(move || {
-        // #tag -> #product -> (path, info)
-        let mut tags_hash: FnvHashMap<String, FnvHashMap<String, DBFile>> = FnvHashMap::default();
-        let mut product_to_tags: FnvHashMap<String, Vec<String>> = FnvHashMap::default();
-        //
-        // create a pool of workers to make dbfiles
-        let mut tx_vec = vec![];
-        let mut threads_vec = vec![];
-        for _ in 0..2 {
-            let (tx, rx) = mpsc::channel::<(String, String, path::PathBuf, bool)>();
-            tx_vec.push(tx);
-            threads_vec.push(thread::spawn(move || {
-                let mut dbfiles = vec![];
-                for (product, tag, path, preload) in rx {
-                    dbfiles.push((product, tag, DBFile::new(path, preload)));
-                }
-                dbfiles
-            }));
+    };
+    for entry in directory_iterator.into_iter().flatten() {
+        let entry_path = entry.unwrap().path();
+        if !entry_path.is_dir() {
+            continue;
         }
-        {
-            let mut tx_vec_cycle = tx_vec.iter().cycle();
-
-            for (product, file) in tag_rx {
-                let tag;
-                // The code below is scoped so that the borrow of file goes out scope and
-                // the file can be moved into the DBFile constructor
-                {
-                    let tag_file_name = file.file_name().unwrap().to_str().unwrap();
-                    let tag_str: Vec<&str> = tag_file_name.split(".chain").collect();
-                    tag = String::from(tag_str[0]);
-                }
-                let tags_vec = product_to_tags.entry(product.clone()).or_insert(Vec::new());
-                tags_vec.push(tag.clone());
-                tx_vec_cycle
-                    .next()
-                    .unwrap()
-                    .send((product, tag, file, load_tag))
-                    .unwrap();
+        if let Some(cache) = cache.as_ref() {
+            let name = file_name(&entry_path);
+            // The time is taken before the directory is walked, so that changes made while
+            // walking it are found the next time
+            let modified = index_cache::modified(&entry_path);
+            if cache.is_current(&entry_path, &name, modified) {
+                current.insert(name);
+                continue;
             }
-        }
-        // work is done, collect from threads
-        drop(tx_vec);
-        for thread in threads_vec {
-            let result = thread.join().unwrap();
-            for (product, tag, dbfile) in result {
-                let product_hash = tags_hash.entry(tag).or_insert(FnvHashMap::default());
-                product_hash.insert(product, dbfile);
+            if let Some(modified) = modified {
+                walked.insert(name, modified);
             }
         }
-        (tags_hash, product_to_tags)
-    });
+        directories.push(entry_path);
+    }
 
-    // Create a worker "pool" to list and sort directories passed to them, passing off files
-    // by type to other threads which accumulate
-    let mut worker_threads = vec![];
-    for reciver in worker_rx_vec {
-        let name_tx_clone = mpsc::Sender::clone(&name_tx);
-        let tag_tx_clone = mpsc::Sender::clone(&tag_tx);
+    let (versions, tags) = read_directories(
+        directories,
+        options.workers,
+        load_version,
+        load_tag,
+        cache.is_some(),
+    )?;
 
-        worker_threads.push(thread::spawn(move || {
-            for entry in reciver {
-                if !entry.is_dir() {
-                    continue;
-                }
-                let entry_name = String::from(entry.file_name().unwrap().to_str().unwrap());
-                let contents = fs::read_dir(entry).expect("problem in worker thread read_dir");
-                for file in contents {
-                    let obj = file.unwrap();
-                    let obj_name = obj.file_name().to_str().unwrap().to_string();
-                    let message = (entry_name.clone(), obj.path().clone());
-                    if obj_name.ends_with(".version") {
-                        name_tx_clone.send(message).unwrap();
-                    } else if obj_name.ends_with(".chain") {
-                        tag_tx_clone.send(message).unwrap();
-                    }
-                }
-            }
-        }));
-    }
+    // The modification times of the files read, which are cached with them
+    let mut file_times = index_cache::FileTimes::default();
 
-    // run this in a scope block so the iterator gets cleaned up afterwards
-    {
-        // create an iterator that cycles between the worker transmitter such
-        // that the work will be distributed to each worker in sequence
-        let mut worker_iter = worker_tx_vec.iter().cycle();
-        let directory_iterator = fs::read_dir(eups_path.clone());
-        if !directory_iterator.is_ok() {
-            return Err(format!(
-                "Problem reading database at {}",
-                eups_path.to_str().unwrap()
-            )
-            .to_string());
+    // #product -> #version -> struct(path, info)
+    let mut product_to_info: FnvHashMap<String, FnvHashMap<String, DBFile>> = FnvHashMap::default();
+    for (product, version, dbfile, modified) in versions {
+        if let Some(modified) = modified {
+            file_times.insert((product.clone(), format!("{}.version", version)), modified);
         }
-        for entry in fs::read_dir(eups_path.clone()).expect("issue in main list") {
-            worker_iter
-                .next()
-                .unwrap()
-                .send(entry.unwrap().path())
-                .unwrap();
+        product_to_info
+            .entry(product)
+            .or_default()
+            .insert(version, dbfile);
+    }
+    // #tag -> #product -> (path, info)
+    let mut tags_to_info: FnvHashMap<String, FnvHashMap<String, DBFile>> = FnvHashMap::default();
+    let mut product_to_tags: FnvHashMap<String, Vec<String>> = FnvHashMap::default();
+    for (product, tag, dbfile, modified) in tags {
+        if let Some(modified) = modified {
+            file_times.insert((product.clone(), format!("{}.chain", tag)), modified);
         }
+        product_to_tags
+            .entry(product.clone())
+            .or_default()
+            .push(tag.clone());
+        tags_to_info.entry(tag).or_default().insert(product, dbfile);
     }
 
-    // drop the worker transmitters so that the worker threads get closed
-    drop(worker_tx_vec);
-
-    // Join the worker threads to make sure they cleanly end
-    for thread in worker_threads {
-        thread.join().unwrap();
+    if let (Some(mut cache), Some(cache_dir)) = (cache, cache_dir) {
+        let cached_count = cache.product_count();
+        let mut modified = cache.restore(
+            &eups_path,
+            &current,
+            &mut product_to_info,
+            &mut tags_to_info,
+            &mut product_to_tags,
+            &mut file_times,
+        );
+        // Only write the cache if products were walked or removed
+        if !walked.is_empty() || cached_count != current.len() {
+            modified.extend(walked);
+            cache.fill(modified, &file_times, &product_to_info, &tags_to_info);
+            if let Err(e) = cache.save(cache_dir) {
+                crate::warn!("{}", e);
+            }
+        }
     }
 
-    // Drop the version and tag db accumulators so the threads close
-    drop(name_tx);
-    drop(tag_tx);
-
-    // collect the results of the accumulators
-    let product_to_info = names_thread.join().unwrap();
-    let (tags_to_info, product_to_tags) = tags_thread.join().unwrap();
-
     Ok((eups_path, product_to_info, tags_to_info, product_to_tags))
 }
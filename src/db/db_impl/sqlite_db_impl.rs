@@ -0,0 +1,404 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/**
+ * sqlite_db_impl is a backend database source for the main DB class. It stores all of the
+ * information about products in a single SQLite file, with one row per flavor of each version and
+ * tag. Unlike a json source, syncing a product only writes the rows of that product, inside a
+ * transaction, so the cost of a declare does not grow with the size of the source. The file is
+ * kept in write ahead log mode, so it may be read while another process is writing to it.
+ *
+ * In memory a SQLite source holds the same groups and tables as a json source, and it shares the
+ * json implementation for looking up and declaring products.
+ **/
+use super::json_db_impl::TableInfoJson;
+use super::FnvHashMap;
+use super::JsonDBImpl;
+use super::PathBuf;
+use super::Table;
+use rusqlite::{params, Connection, OpenFlags, Transaction};
+use std::time::Duration;
+
+/// Layout of the tables in the file. Each primary key starts with the product, so the rows of a
+/// product are found through the index of the key
+static SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS versions (
+    product TEXT NOT NULL,
+    version TEXT NOT NULL,
+    flavor TEXT NOT NULL,
+    qualifiers TEXT NOT NULL,
+    info TEXT NOT NULL,
+    table_info TEXT NOT NULL,
+    PRIMARY KEY (product, version, flavor, qualifiers)
+);
+CREATE TABLE IF NOT EXISTS tags (
+    product TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    flavor TEXT NOT NULL,
+    qualifiers TEXT NOT NULL,
+    version TEXT NOT NULL,
+    info TEXT NOT NULL,
+    PRIMARY KEY (product, tag, flavor, qualifiers)
+);
+CREATE INDEX IF NOT EXISTS tags_by_tag ON tags (tag);
+CREATE TABLE IF NOT EXISTS idents (
+    product TEXT NOT NULL,
+    ident TEXT NOT NULL,
+    version TEXT NOT NULL,
+    PRIMARY KEY (product, ident)
+);
+CREATE INDEX IF NOT EXISTS idents_by_version ON idents (product, version);
+";
+
+/// How long to wait for another process writing to the file before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The information for one flavor of a product version or tag
+type GroupInfo = FnvHashMap<String, String>;
+
+/// Database backend source that stores data in a single SQLite file
+#[derive(Debug)]
+pub struct SqliteDBImpl {
+    inner: JsonDBImpl,
+}
+
+/// Converts an error from SQLite into an io error, as returned by sync
+fn io_error(e: rusqlite::Error) -> std::io::Error {
+    std::io::Error::other(e.to_string())
+}
+
+impl SqliteDBImpl {
+    /// Creates a new empty SqliteDBImpl instance, which will be stored at the location provided
+    /// if written to disk.
+    pub fn new(loc: &PathBuf) -> Result<SqliteDBImpl, String> {
+        Ok(SqliteDBImpl {
+            inner: JsonDBImpl::new(loc)?,
+        })
+    }
+
+    /// Creates a new SqliteDBImpl from the SQLite file located at the path provided. The file is
+    /// only opened for reading, so sources on read only file systems can be used.
+    pub fn from_file(loc: &PathBuf) -> Result<SqliteDBImpl, String> {
+        let read_error =
+            |e: rusqlite::Error| format!("Problem reading sqlite source {:?}: {}", loc, e);
+        let connection = Connection::open_with_flags(loc, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(read_error)?;
+        connection.busy_timeout(BUSY_TIMEOUT).map_err(read_error)?;
+        let mut source = SqliteDBImpl::new(loc)?;
+        source.load(&connection).map_err(read_error)?;
+        Ok(source)
+    }
+
+    /// Reads all the rows of the file into memory, in the order they were written so the groups
+    /// of each version and tag keep their order
+    fn load(&mut self, connection: &Connection) -> rusqlite::Result<()> {
+        let db = &mut self.inner;
+        let to_sql_error =
+            |e: serde_json::Error| rusqlite::Error::ToSqlConversionFailure(Box::new(e));
+
+        let mut statement = connection
+            .prepare("SELECT product, version, info, table_info FROM versions ORDER BY rowid")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let product: String = row.get(0)?;
+            let version: String = row.get(1)?;
+            let info: GroupInfo =
+                serde_json::from_str(&row.get::<_, String>(2)?).map_err(to_sql_error)?;
+            let table_info: TableInfoJson =
+                serde_json::from_str(&row.get::<_, String>(3)?).map_err(to_sql_error)?;
+            let product_dir = PathBuf::from(info.get("PROD_DIR").cloned().unwrap_or_default());
+            db.product_to_version_table
+                .entry(product.clone())
+                .or_default()
+                .entry(version.clone())
+                .or_default()
+                .push(table_info.into_table(product.clone(), product_dir));
+            db.product_to_version_info
+                .entry(product)
+                .or_default()
+                .entry(version)
+                .or_default()
+                .push(info);
+        }
+
+        let mut statement =
+            connection.prepare("SELECT product, tag, info FROM tags ORDER BY rowid")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let product: String = row.get(0)?;
+            let tag: String = row.get(1)?;
+            let info: GroupInfo =
+                serde_json::from_str(&row.get::<_, String>(2)?).map_err(to_sql_error)?;
+            let tags = db.product_to_tags.entry(product.clone()).or_default();
+            if !tags.contains(&tag) {
+                tags.push(tag.clone());
+            }
+            db.tag_to_product_info
+                .entry(tag)
+                .or_default()
+                .entry(product)
+                .or_default()
+                .push(info);
+        }
+
+        let mut statement =
+            connection.prepare("SELECT product, ident, version FROM idents ORDER BY rowid")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let product: String = row.get(0)?;
+            let ident: String = row.get(1)?;
+            db.product_to_ident
+                .as_mut()
+                .unwrap()
+                .entry(product.clone())
+                .or_default()
+                .push(ident.clone());
+            db.product_ident_version
+                .as_mut()
+                .unwrap()
+                .entry(product)
+                .or_default()
+                .insert(ident, row.get(2)?);
+        }
+        Ok(())
+    }
+
+    /// Opens the file for writing, creating it and its tables if they do not exist
+    fn connect(&self) -> rusqlite::Result<Connection> {
+        let connection = Connection::open(&self.inner.location)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(connection)
+    }
+
+    /// Writes the versions, tags, and identities of a product which are not already in the file.
+    /// Rows already in the file are left as they are, so that the work of other processes since
+    /// this source was read is not lost
+    fn write_product(&self, transaction: &Transaction, product: &str) -> rusqlite::Result<()> {
+        let db = &self.inner;
+        let to_sql_error =
+            |e: serde_json::Error| rusqlite::Error::ToSqlConversionFailure(Box::new(e));
+
+        if let Some(versions) = db.product_to_version_info.get(product) {
+            let mut statement = transaction.prepare(
+                "INSERT OR IGNORE INTO versions
+                 (product, version, flavor, qualifiers, info, table_info)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for (version, groups) in versions.iter() {
+                for index in 0..groups.len() {
+                    let (flavor, qualifiers) = super::group_key(groups, index);
+                    let table = match db.get_group_table(product, version, index) {
+                        Some(table) => TableInfoJson::from_table(table),
+                        None => continue,
+                    };
+                    statement.execute(params![
+                        product,
+                        version,
+                        flavor,
+                        qualifiers,
+                        serde_json::to_string(&groups[index]).map_err(to_sql_error)?,
+                        serde_json::to_string(&table).map_err(to_sql_error)?,
+                    ])?;
+                }
+            }
+        }
+
+        if let Some(tags) = db.product_to_tags.get(product) {
+            let mut statement = transaction.prepare(
+                "INSERT OR IGNORE INTO tags (product, tag, flavor, qualifiers, version, info)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for tag in tags.iter() {
+                let groups = match db.tag_to_product_info.get(tag).and_then(|x| x.get(product)) {
+                    Some(groups) => groups,
+                    None => continue,
+                };
+                for (index, group) in groups.iter().enumerate() {
+                    let (flavor, qualifiers) = super::group_key(groups, index);
+                    statement.execute(params![
+                        product,
+                        tag,
+                        flavor,
+                        qualifiers,
+                        group.get("VERSION").map_or("", |x| x.as_str()),
+                        serde_json::to_string(group).map_err(to_sql_error)?,
+                    ])?;
+                }
+            }
+        }
+
+        if let Some(idents) = db
+            .product_ident_version
+            .as_ref()
+            .and_then(|x| x.get(product))
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR IGNORE INTO idents (product, ident, version) VALUES (?1, ?2, ?3)",
+            )?;
+            for (ident, version) in idents.iter() {
+                statement.execute(params![product, ident, version])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the whole contents of the file with this source, in a single transaction
+    fn write_all(&self) -> rusqlite::Result<()> {
+        let mut connection = self.connect()?;
+        let transaction = connection.transaction()?;
+        transaction.execute_batch("DELETE FROM versions; DELETE FROM tags; DELETE FROM idents;")?;
+        let mut products: Vec<&String> = self.inner.product_to_version_info.keys().collect();
+        products.extend(self.inner.product_to_tags.keys());
+        products.sort();
+        products.dedup();
+        for product in products {
+            self.write_product(&transaction, product)?;
+        }
+        transaction.commit()
+    }
+}
+
+// Implement the trait to make SqliteDBImpl a database source, lookups use the in memory json
+// representation
+impl super::DBImpl for SqliteDBImpl {
+    fn get_location(&self) -> &super::PathBuf {
+        self.inner.get_location()
+    }
+
+    fn get_table(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<Table> {
+        self.inner.get_table(product, version, flavor, qualifiers)
+    }
+
+    fn get_tags(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_tags(product)
+    }
+
+    fn get_versions(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_versions(product)
+    }
+
+    fn get_products(&self) -> Vec<&str> {
+        self.inner.get_products()
+    }
+
+    fn get_identities(&self, product: &str) -> Option<Vec<&str>> {
+        self.inner.get_identities(product)
+    }
+
+    fn get_flavors(&self, product: &str, version: &str) -> Option<Vec<&str>> {
+        self.inner.get_flavors(product, version)
+    }
+
+    fn get_groups(&self, product: &str, version: &str) -> Option<Vec<(&str, &str)>> {
+        self.inner.get_groups(product, version)
+    }
+
+    fn get_tag_groups(&self, product: &str, tag: &str) -> Option<Vec<(&str, &str, &str)>> {
+        self.inner.get_tag_groups(product, tag)
+    }
+
+    fn lookup_flavor_version(
+        &self,
+        product: &str,
+        version: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.inner
+            .lookup_flavor_version(product, version, flavor, qualifiers)
+    }
+
+    fn lookup_version_tag(
+        &self,
+        product: &str,
+        tag: &str,
+        flavor: Option<&str>,
+        qualifiers: Option<&str>,
+    ) -> Option<&str> {
+        self.inner
+            .lookup_version_tag(product, tag, flavor, qualifiers)
+    }
+
+    fn lookup_version_ident(&self, product: &str, ident: &str) -> Option<&str> {
+        self.inner.lookup_version_ident(product, ident)
+    }
+
+    fn lookup_location_version(&self, product: &str, version: &str) -> Option<&PathBuf> {
+        self.inner.lookup_location_version(product, version)
+    }
+
+    fn has_identity(&self, product: &str, ident: &str) -> bool {
+        self.inner.has_identity(product, ident)
+    }
+
+    fn has_product(&self, product: &str) -> bool {
+        self.inner.has_product(product)
+    }
+
+    fn identities_populated(&self) -> bool {
+        self.inner.identities_populated()
+    }
+
+    fn is_writable(&self) -> bool {
+        // A file which does not exist yet is created when the source is first synced
+        let location = self.get_location();
+        if !location.exists() {
+            return true;
+        }
+        std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .open(location)
+            .is_ok()
+    }
+
+    /// Checks that the product directories recorded exist, that tables depend only on declared
+    /// products, and that each tag points at a declared version
+    fn check(&self, is_declared: &dyn Fn(&str) -> bool) -> Vec<super::CheckProblem> {
+        self.inner.check(is_declared)
+    }
+
+    fn plan_fix(&self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        self.inner.plan_fix(paths)
+    }
+
+    /// Removes the groups of tags which point at versions that are not declared, rewrites the
+    /// product directories in the requested style, and creates identities for versions declared
+    /// without one. The repairs are made in memory, and the file is then rewritten in a single
+    /// transaction
+    fn fix(&mut self, paths: Option<super::PathStyle>) -> Result<Vec<super::Repair>, String> {
+        let repairs = self.inner.fix_in_memory(paths);
+        if !repairs.is_empty() {
+            self.write_all()
+                .map_err(|e| format!("Problem writing {:?}: {}", self.get_location(), e))?;
+        }
+        Ok(repairs)
+    }
+
+    fn declare_in_memory_impl(&mut self, inputs: &Vec<super::DeclareInputs>) -> Result<(), String> {
+        self.inner.declare_in_memory_impl(inputs)
+    }
+
+    /// Syncs a product to disk, adding the rows of the product that are not in the file in a
+    /// single transaction. The file is created if it does not exist
+    fn sync(&self, product: &str) -> std::io::Result<()> {
+        crate::info!("Running sync in sqlite_db_impl for product {}", product);
+        let mut connection = self.connect().map_err(io_error)?;
+        let transaction = connection.transaction().map_err(io_error)?;
+        self.write_product(&transaction, product)
+            .map_err(io_error)?;
+        transaction.commit().map_err(io_error)?;
+        crate::debug!("Done syncing out the database");
+        Ok(())
+    }
+}
@@ -14,21 +14,41 @@
  makes more sense to get the io out of the way ans so there is a preload
  boolean in the new function that determines if the file should be read at the
  creation time of the object.
+
+ A file may contain several `Group:` blocks, each describing the product for one
+ flavor and set of qualifiers. The keys outside of any group (such as PRODUCT) are
+ shared by all the groups, and the groups are kept in the order they appear in the
+ file.
 */
 
 use fnv::FnvHashMap;
+use serde_derive::{Deserialize, Serialize};
 use std::io;
 use std::path;
 
 use std::cell::RefCell;
 use std::fs;
 
+/// The parsed contents of a database file, the keys shared by the whole file and those
+/// of each group
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Contents {
+    header: FnvHashMap<String, String>,
+    groups: Vec<FnvHashMap<String, String>>,
+}
+
+impl Contents {
+    fn is_empty(&self) -> bool {
+        self.header.is_empty() && self.groups.is_empty()
+    }
+}
+
 #[derive(Debug)]
 pub struct DBFile {
     path: path::PathBuf,
     // Contents are a ReffCell so that there can be a mutable hashmap in an immutable
     // DBFile
-    contents: RefCell<FnvHashMap<String, String>>,
+    contents: RefCell<Contents>,
 }
 
 impl DBFile {
@@ -38,7 +58,7 @@ impl DBFile {
     pub fn new(path: path::PathBuf, preload: bool) -> DBFile {
         let db_file = DBFile {
             path: path.clone(),
-            contents: RefCell::new(FnvHashMap::default()),
+            contents: RefCell::new(Contents::default()),
         };
 
         if preload {
@@ -57,32 +77,29 @@ impl DBFile {
     pub fn new_with_contents(path: path::PathBuf, file_contents: String) -> DBFile {
         let db_file = DBFile {
             path: path,
-            contents: RefCell::new(FnvHashMap::default()),
+            contents: RefCell::new(Contents::default()),
         };
         db_file.parse_string(file_contents);
         db_file
     }
 
-    /// Copies the contents of the dbfile into a HashMap of String to String,
-    /// returning ownership of new HashMap
-    pub fn to_map(&self) -> FnvHashMap<String, String> {
-        let db_is_empty: bool;
-        {
-            db_is_empty = self.contents.borrow().is_empty();
-        }
-        if db_is_empty {
-            self.load_file().unwrap_or_else(|_e| {
-                exit_with_message!(format!(
-                    "Problem accessing {}, could not create database",
-                    self.path.to_str().unwrap()
-                ));
-            });
+    /// Create a DBFile object from contents which were parsed previously, such as those kept in
+    /// an index cache
+    pub(crate) fn new_with_parsed(path: path::PathBuf, contents: Contents) -> DBFile {
+        DBFile {
+            path,
+            contents: RefCell::new(contents),
         }
+    }
+
+    /// Returns a copy of the parsed contents of the file, reading them off disk if needed
+    pub(crate) fn parsed(&self) -> Contents {
+        self.ensure_loaded();
         self.contents.borrow().clone()
     }
 
-    /// Retrieves the value of the DBFile corresponding to the supplied key
-    pub fn get(&self, key: &str) -> Option<&str> {
+    /// Reads the contents of the file off disk if that has not already been done
+    fn ensure_loaded(&self) {
         let db_is_empty: bool;
         {
             db_is_empty = self.contents.borrow().is_empty();
@@ -95,6 +112,61 @@ impl DBFile {
                 ));
             });
         }
+    }
+
+    /// Copies the keys shared by the whole file, and those of the group at the
+    /// supplied index into a new HashMap
+    pub fn group_to_map(&self, index: usize) -> FnvHashMap<String, String> {
+        self.ensure_loaded();
+        let contents = self.contents.borrow();
+        let mut map = contents.header.clone();
+        if let Some(group) = contents.groups.get(index) {
+            map.extend(group.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        map
+    }
+
+    /// Returns the number of groups in the file, a file without any group blocks
+    /// is treated as having a single group
+    pub fn group_count(&self) -> usize {
+        self.ensure_loaded();
+        std::cmp::max(self.contents.borrow().groups.len(), 1)
+    }
+
+    /// Adds a new group to the end of the file, the keys in group which are shared by
+    /// the whole file are not added
+    pub fn push_group(&mut self, mut group: FnvHashMap<String, String>) {
+        self.ensure_loaded();
+        let contents = self.contents.get_mut();
+        group.retain(|k, _| !contents.header.contains_key(k));
+        contents.groups.push(group);
+    }
+
+    /// Removes the group at the supplied index from the file
+    pub fn remove_group(&mut self, index: usize) {
+        self.ensure_loaded();
+        let contents = self.contents.get_mut();
+        if index < contents.groups.len() {
+            contents.groups.remove(index);
+        }
+    }
+
+    /// Sets the value of a key in the group at the supplied index, a file without
+    /// any group blocks keeps the value with the keys shared by the whole file
+    pub fn set_group(&mut self, index: usize, key: &str, value: &str) {
+        self.ensure_loaded();
+        let contents = self.contents.get_mut();
+        let map = match contents.groups.get_mut(index) {
+            Some(group) => group,
+            None => &mut contents.header,
+        };
+        map.insert(key.to_string(), value.to_string());
+    }
+
+    /// Retrieves the value corresponding to the supplied key from the group at the
+    /// supplied index, or from the keys shared by the whole file
+    pub fn get_group(&self, index: usize, key: &str) -> Option<&str> {
+        self.ensure_loaded();
         // This unsafe block exists because rust will not allow a reference to be
         // taken from inside a refcell. A refcell gets a reference to the underlying
         // type each time you borrow(), and taking the reference on the borrowed
@@ -117,8 +189,11 @@ impl DBFile {
         // this is true, rust will not let the reference be used after the object
         // is dropped
         let r = unsafe {
-            let ptr = self.contents.as_ptr();
-            (*ptr).get(key)?
+            let contents = &*self.contents.as_ptr();
+            match contents.groups.get(index).and_then(|group| group.get(key)) {
+                Some(value) => value,
+                None => contents.header.get(key)?,
+            }
         };
         crate::debug!("Found key, value {}, {}  in DBFile", key, r);
         Some(r)
@@ -127,7 +202,8 @@ impl DBFile {
     /// Loads the file associated with this DBFile object off disk, and then
     /// parses the file line by line. Any line that has an equals in it is
     /// split with the left side of the equals being the key, and the right
-    /// becomes the value
+    /// becomes the value. Lines between a Group: and End: marker belong to
+    /// that group
     fn load_file(&self) -> Result<(), io::Error> {
         crate::debug!(
             "Populating DBFile with {} from disk",
@@ -142,14 +218,31 @@ impl DBFile {
     /// Parse a string corresponding to the contents of a file, and store them
     /// in self.
     fn parse_string(&self, contents: String) {
+        let mut parsed = self.contents.borrow_mut();
+        let mut in_group = false;
         for line in contents.lines() {
+            // Group markers may be commented out, as they are in chain files
+            match line.trim().trim_start_matches('#').trim() {
+                "Group:" => {
+                    parsed.groups.push(FnvHashMap::default());
+                    in_group = true;
+                    continue;
+                }
+                "End:" => {
+                    in_group = false;
+                    continue;
+                }
+                _ => (),
+            }
             for (i, char) in line.char_indices() {
                 if char == '=' {
-                    let key = line[0..i].trim();
-                    let value = line[i + 1..].trim();
-                    self.contents
-                        .borrow_mut()
-                        .insert(key.to_string(), value.to_string());
+                    let key = line[0..i].trim().to_string();
+                    let value = line[i + 1..].trim().to_string();
+                    if in_group {
+                        parsed.groups.last_mut().unwrap().insert(key, value);
+                    } else {
+                        parsed.header.insert(key, value);
+                    }
                     break;
                 }
             }
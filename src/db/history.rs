@@ -0,0 +1,192 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ The history module keeps the audit log of each database source. The log is an append only file
+ of JSON lines next to the source, recording each change reups makes to the source along with
+ who made it, when, and from which host. A tag can not be moved once it is declared, so the log
+ only records the version each tag was first pointed at.
+*/
+use super::db_impl::{self, DBImpl, DeclareInputs};
+use fs2::FileExt;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Name of the audit log kept inside the ups_db directory of posix sources
+pub const HISTORY_FILE_NAME: &str = ".reups_log.jsonl";
+
+/// The kinds of change recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// A version of a product was declared
+    Declare,
+    /// A tag was pointed at a version of a product
+    Tag,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Declare => write!(f, "declare"),
+            Operation::Tag => write!(f, "tag"),
+        }
+    }
+}
+
+/// One change to a database source recorded in its audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub operation: Operation,
+    pub product: String,
+    pub version: String,
+    pub tag: Option<String>,
+    pub flavor: String,
+    pub qualifiers: String,
+    pub user: String,
+    pub time: String,
+    pub host: String,
+}
+
+/// The audit log of a database source
+#[derive(Debug, Serialize)]
+pub struct SourceHistory {
+    pub source: String,
+    pub location: PathBuf,
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// Returns the path of the audit log of the source at the given location. Posix sources keep it
+/// inside their ups_db directory, and sources stored in a single file keep it beside the file
+pub fn history_location(source: &Path) -> PathBuf {
+    if source.is_dir() {
+        return source.join(HISTORY_FILE_NAME);
+    }
+    let name = source
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    source.with_file_name(format!(".{}{}", name, HISTORY_FILE_NAME))
+}
+
+/// Returns the name of the host reups is running on, or unknown if it can not be found
+pub fn get_host() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|x| fs::read_to_string(x).ok())
+        .map(|x| x.trim().to_string())
+        .chain(std::env::var("HOSTNAME").ok())
+        .find(|x| !x.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Builds the entries recording the declaration of the inputs into the source. This must be
+/// called before declaring, so that the versions already declared are known. A version which is
+/// already declared for the flavor and qualifiers of an input is only being tagged, so only its
+/// tag is recorded
+pub fn history_entries(source: &dyn DBImpl, inputs: &[DeclareInputs]) -> Vec<HistoryEntry> {
+    let (user, time) = db_impl::get_declare_info();
+    let host = get_host();
+    let mut entries = vec![];
+    for input in inputs.iter() {
+        let flavor = input.flavor.unwrap_or("");
+        let qualifiers = input.qualifiers.unwrap_or("");
+        let matches =
+            |f: &str, q: &str| f == flavor && db_impl::normalize_qualifiers(q) == qualifiers;
+        let entry = |operation, tag: Option<&str>| HistoryEntry {
+            operation,
+            product: input.product.to_string(),
+            version: input.version.to_string(),
+            tag: tag.map(|x| x.to_string()),
+            flavor: flavor.to_string(),
+            qualifiers: qualifiers.to_string(),
+            user: user.clone(),
+            time: time.clone(),
+            host: host.clone(),
+        };
+        let declared = source
+            .get_groups(input.product, input.version)
+            .is_some_and(|groups| groups.iter().any(|(f, q)| matches(f, q)));
+        if !declared {
+            entries.push(entry(Operation::Declare, None));
+        }
+        if let Some(tag) = input.tag {
+            entries.push(entry(Operation::Tag, Some(tag)));
+        }
+    }
+    entries
+}
+
+/// Appends the entries to the audit log of the source at the given location. Sources which are
+/// not stored on disk, such as those held in memory, keep no log
+pub fn record_history(source: &Path, entries: &[HistoryEntry]) -> Result<(), String> {
+    if entries.is_empty() || !source.exists() {
+        return Ok(());
+    }
+    let location = history_location(source);
+    let mut lines = String::new();
+    for entry in entries.iter() {
+        lines.push_str(&serde_json::to_string(entry).map_err(|e| format!("{}", e))?);
+        lines.push('\n');
+    }
+    let problem = |e: std::io::Error| {
+        format!(
+            "Problem writing history {}: {}",
+            location.to_str().unwrap_or_default(),
+            e
+        )
+    };
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&location)
+        .map_err(problem)?;
+    // Several users may write to a shared source at once, so the entries are written together
+    // while holding the lock
+    file.lock_exclusive().map_err(problem)?;
+    let result = file.write_all(lines.as_bytes()).map_err(problem);
+    let _ = file.unlock();
+    result
+}
+
+/// Reads the audit log of the source at the given location, oldest entry first, keeping only the
+/// entries of the product if one is given. A source which has never been changed has no log,
+/// and so has no history
+pub fn read_history(source: &Path, product: Option<&str>) -> Result<Vec<HistoryEntry>, String> {
+    let location = history_location(source);
+    if !location.exists() {
+        return Ok(vec![]);
+    }
+    let contents = fs::read_to_string(&location).map_err(|e| {
+        format!(
+            "Problem reading history {}: {}",
+            location.to_str().unwrap_or_default(),
+            e
+        )
+    })?;
+    let mut entries = vec![];
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<HistoryEntry>(line) {
+            Ok(entry) => {
+                if product.is_none_or(|x| x == entry.product) {
+                    entries.push(entry);
+                }
+            }
+            Err(e) => crate::warn!(
+                "Skipping line {} of history {} which could not be read: {}",
+                number + 1,
+                location.to_str().unwrap_or_default(),
+                e
+            ),
+        }
+    }
+    Ok(entries)
+}
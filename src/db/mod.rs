@@ -13,18 +13,48 @@ use fnv::FnvHashMap;
 mod db_impl;
 mod dbfile;
 pub mod graph;
+mod history;
+mod registry;
 pub mod table;
 
 use self::dbfile::DBFile;
 use crate::argparse;
 use crate::cogs;
+use crate::config;
+use crate::regex;
 
 use self::db_impl::DBImplDeclare;
 pub use self::db_impl::DeclareInputs;
 pub use self::db_impl::*;
+pub use self::history::*;
+pub use self::registry::*;
+use serde_derive::Serialize;
 use std::cell::RefCell;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Name of the source holding the products declared into the overlay
+pub const OVERLAY_NAME: &str = "overlay";
+
+/// Default priority of the sources added with -Z, or with add_path
+pub const EXTRA_PRIORITY: i32 = 100;
+
+/// Default priority of the user sources, posix_user and json_user
+pub const USER_PRIORITY: i32 = -100;
+
+/// Default priority of the sources found from EUPS_PATH and REUPS_PATH
+pub const SYSTEM_PRIORITY: i32 = -200;
+
+/// The ways a source may be added to a DB. Sources with equal priority are ordered by layer,
+/// highest first, and only sources within the same layer are ordered by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SourceLayer {
+    System,
+    User,
+    Named,
+    Extra,
+    Overlay,
+}
 
 /// Data structure to hold state related to iterating over a db object.
 /// This iteration is used to loop over all the instance of DBImpls
@@ -63,12 +93,14 @@ impl<'a> Iterator for DBIter<'a> {
     }
 }
 
-/// Enum to describe what types of `DBFile`s should be loaded at DB creation time.
+/// Enum to describe what types of `DBFile`s should be loaded at DB creation time. Lazy loads
+/// nothing at creation, and reads each product of a posix source the first time it is used.
 #[derive(Clone)]
 pub enum DBLoadControl {
     Versions,
     Tags,
     All,
+    Lazy,
 }
 
 /// Creates a new DB object. Optionally takes the path to a system database, a user database,
@@ -81,9 +113,18 @@ pub struct DBBuilder {
     reups_env: bool,
     reups_user: bool,
     db_sources: FnvHashMap<String, PathBuf>,
+    built_sources: Vec<(String, Box<dyn db_impl::DBImpl>)>,
+    db_priorities: FnvHashMap<String, i32>,
+    db_layers: FnvHashMap<String, SourceLayer>,
+    ident_regex: Option<regex::Regex>,
+    source_ident_regexes: FnvHashMap<String, regex::Regex>,
+    flavor: Option<String>,
+    qualifiers: String,
     extra_id: u32,
     load_control: Option<DBLoadControl>,
     allow_empty: bool,
+    read_options: PosixReadOptions,
+    overlay: Option<PathBuf>,
 }
 
 type BuildBundle = Result<DBBuilder, String>;
@@ -96,17 +137,76 @@ impl DBBuilder {
             reups_env: true,
             reups_user: true,
             db_sources: FnvHashMap::default(),
+            built_sources: vec![],
+            db_priorities: FnvHashMap::default(),
+            db_layers: FnvHashMap::default(),
+            ident_regex: None,
+            source_ident_regexes: FnvHashMap::default(),
+            flavor: None,
+            qualifiers: String::new(),
             extra_id: 0,
             load_control: Some(DBLoadControl::All),
             allow_empty: true,
+            read_options: PosixReadOptions {
+                cache_dir: cogs::get_index_cache_dir(),
+                workers: db_impl::default_workers(),
+            },
+            overlay: Some(cogs::get_overlay_path()),
         })
     }
 
+    /// Creates a builder configured from the reups configuration files, and then the supplied
+    /// command line arguments
     pub fn from_args(args: &argparse::ArgMatches) -> BuildBundle {
         let mut db = DBBuilder::new();
+        let config = config::get_config()?;
+        if config.user == Some(false) {
+            db = db.add_eups_user(false);
+            db = db.add_reups_user(false);
+        }
+        if config.system == Some(false) {
+            db = db.add_eups_env(false);
+            db = db.add_reups_env(false);
+        }
+        for source in config.source.iter() {
+            db = match source.name.as_ref() {
+                Some(name) => {
+                    let mut path_vec = cogs::path_string_to_vec(&source.path)?;
+                    if path_vec.len() != 1 {
+                        return Err(format!("Configured source {} must be a single path", name));
+                    }
+                    db = db.add_named_path(name, path_vec.remove(0), source.priority);
+                    match source.ident_regex.as_ref() {
+                        Some(ident_regex) => db.set_source_ident_regex(name, ident_regex),
+                        None => db,
+                    }
+                }
+                None if source.ident_regex.is_some() => {
+                    return Err(format!(
+                        "Configured source {} must be named to use an ident regex",
+                        source.path
+                    ));
+                }
+                None => db.add_path_str_priority(&source.path, source.priority),
+            };
+        }
+        for (name, priority) in config.priority.iter() {
+            db = db.set_priority(name, *priority);
+        }
+        if let Some(ident_regex) = config.ident_regex.as_ref() {
+            db = db.set_ident_regex(ident_regex);
+        }
+        if let Some(workers) = config.workers {
+            db = db.set_workers(workers);
+        }
+        db = db.set_flavor(args.value_of("flavor").unwrap_or_else(|| config.flavor()));
+        if let Some(qualifiers) = args.value_of("qualifiers") {
+            db = db.set_qualifiers(qualifiers);
+        }
         if args.is_present("nouser") {
             db = db.add_eups_user(false);
             db = db.add_reups_user(false);
+            db = db.set_overlay(None);
         }
         if args.is_present("nosys") {
             db = db.add_eups_env(false);
@@ -115,8 +215,73 @@ impl DBBuilder {
         if args.is_present("database") {
             db = db.add_path_str(args.value_of("database").unwrap());
         }
+        if args.is_present("no-cache") {
+            db = db.set_cache_dir(None);
+        }
         db
     }
+
+    /// Returns the regular expression used to extract identities for the named source, falling
+    /// back to the one used for all sources
+    fn get_ident_regex(&self, name: &str) -> Option<regex::Regex> {
+        self.source_ident_regexes
+            .get(name)
+            .or(self.ident_regex.as_ref())
+            .cloned()
+    }
+
+    /// Records the layer of a source, and gives it the supplied priority unless one was set
+    fn add_to_layer(&mut self, name: &str, layer: SourceLayer, priority: i32) {
+        self.db_layers.insert(name.to_string(), layer);
+        self.db_priorities.entry(name.to_string()).or_insert(priority);
+    }
+
+    /// Returns true if a source has already been added under the supplied name
+    fn has_source(&self, name: &str) -> bool {
+        self.db_sources.contains_key(name) || self.built_sources.iter().any(|(x, _)| x == name)
+    }
+}
+
+/// Compiles a regular expression used to extract identities from versions
+fn compile_ident_regex(ident_regex: &str) -> Result<regex::Regex, String> {
+    regex::Regex::new(ident_regex)
+        .map_err(|e| format!("Problem parsing ident regex {}: {}", ident_regex, e))
+}
+
+/// Opens the database source at the supplied path with the constructor registered for its url
+/// scheme or extension, such as a remote source for an http url or a json source for a json file.
+/// Paths without a registered constructor are posix sources, read with the supplied options
+pub fn open_source(
+    pth: &Path,
+    load_control: Option<&DBLoadControl>,
+    ident_regex: Option<regex::Regex>,
+    options: &PosixReadOptions,
+) -> Result<Box<dyn db_impl::DBImpl>, String> {
+    match registry::registered_constructor(pth) {
+        Some(constructor) => constructor(pth),
+        None => open_posix_source(pth.to_path_buf(), load_control, ident_regex, options),
+    }
+}
+
+/// Opens the posix database source at the supplied path, which is read lazily when the load
+/// control is Lazy, and at creation otherwise
+fn open_posix_source(
+    pth: PathBuf,
+    load_control: Option<&DBLoadControl>,
+    ident_regex: Option<regex::Regex>,
+    options: &PosixReadOptions,
+) -> Result<Box<dyn db_impl::DBImpl>, String> {
+    Ok(match load_control {
+        Some(DBLoadControl::Lazy) => {
+            Box::new(db_impl::LazyPosixDBImpl::new(pth, ident_regex, options)?)
+        }
+        _ => Box::new(db_impl::PosixDBImpl::new_with_options(
+            pth,
+            load_control,
+            ident_regex,
+            options,
+        )?),
+    })
 }
 
 pub trait DBBuilderTrait {
@@ -127,8 +292,20 @@ pub trait DBBuilderTrait {
     fn add_path_str(self, path_str: &str) -> BuildBundle;
     fn add_path_vec(self, path_vec: Vec<PathBuf>) -> BuildBundle;
     fn add_path(self, pth: PathBuf) -> BuildBundle;
+    fn add_path_str_priority(self, path_str: &str, priority: i32) -> BuildBundle;
+    fn add_named_path(self, name: &str, pth: PathBuf, priority: i32) -> BuildBundle;
+    fn add_source(self, name: &str, source: Box<dyn db_impl::DBImpl>, priority: i32)
+        -> BuildBundle;
+    fn set_priority(self, name: &str, priority: i32) -> BuildBundle;
+    fn set_ident_regex(self, ident_regex: &str) -> BuildBundle;
+    fn set_source_ident_regex(self, name: &str, ident_regex: &str) -> BuildBundle;
+    fn set_flavor(self, flavor: &str) -> BuildBundle;
+    fn set_qualifiers(self, qualifiers: &str) -> BuildBundle;
     fn set_load_control(self, mode: DBLoadControl) -> BuildBundle;
     fn allow_empty(self, x: bool) -> BuildBundle;
+    fn set_cache_dir(self, cache_dir: Option<PathBuf>) -> BuildBundle;
+    fn set_workers(self, workers: usize) -> BuildBundle;
+    fn set_overlay(self, overlay: Option<PathBuf>) -> BuildBundle;
     fn build(self) -> Result<DB, String>;
 }
 
@@ -174,11 +351,83 @@ impl DBBuilderTrait for BuildBundle {
 
     fn add_path(self, pth: PathBuf) -> BuildBundle {
         let mut me = self?;
-        me.db_sources.insert(format!("Extra_{}", me.extra_id), pth);
+        let name = format!("Extra_{}", me.extra_id);
+        me.add_to_layer(&name, SourceLayer::Extra, EXTRA_PRIORITY);
+        me.db_sources.insert(name, pth);
         me.extra_id += 1;
         Ok(me)
     }
 
+    fn add_path_str_priority(self, path_str: &str, priority: i32) -> BuildBundle {
+        let mut me = self?;
+        for pth in cogs::path_string_to_vec(path_str)? {
+            let name = format!("Extra_{}", me.extra_id);
+            me = Ok(me).add_named_path(&name, pth, priority)?;
+            me.extra_id += 1;
+        }
+        Ok(me)
+    }
+
+    fn add_named_path(self, name: &str, pth: PathBuf, priority: i32) -> BuildBundle {
+        let mut me = self?;
+        if me.has_source(name) {
+            return Err(format!("A source named {} was added more than once", name));
+        }
+        me.db_sources.insert(name.to_string(), pth);
+        me.db_priorities.insert(name.to_string(), priority);
+        me.db_layers.insert(name.to_string(), SourceLayer::Named);
+        Ok(me)
+    }
+
+    /// Adds a source which has already been opened, such as one using a DBImpl defined outside
+    /// of reups, under the supplied name
+    fn add_source(
+        self,
+        name: &str,
+        source: Box<dyn db_impl::DBImpl>,
+        priority: i32,
+    ) -> BuildBundle {
+        let mut me = self?;
+        if me.has_source(name) {
+            return Err(format!("A source named {} was added more than once", name));
+        }
+        me.built_sources.push((name.to_string(), source));
+        me.db_priorities.insert(name.to_string(), priority);
+        me.db_layers.insert(name.to_string(), SourceLayer::Named);
+        Ok(me)
+    }
+
+    fn set_priority(self, name: &str, priority: i32) -> BuildBundle {
+        let mut me = self?;
+        me.db_priorities.insert(name.to_string(), priority);
+        Ok(me)
+    }
+
+    fn set_ident_regex(self, ident_regex: &str) -> BuildBundle {
+        let mut me = self?;
+        me.ident_regex = Some(compile_ident_regex(ident_regex)?);
+        Ok(me)
+    }
+
+    fn set_source_ident_regex(self, name: &str, ident_regex: &str) -> BuildBundle {
+        let mut me = self?;
+        me.source_ident_regexes
+            .insert(name.to_string(), compile_ident_regex(ident_regex)?);
+        Ok(me)
+    }
+
+    fn set_flavor(self, flavor: &str) -> BuildBundle {
+        let mut me = self?;
+        me.flavor = Some(flavor.to_string());
+        Ok(me)
+    }
+
+    fn set_qualifiers(self, qualifiers: &str) -> BuildBundle {
+        let mut me = self?;
+        me.qualifiers = qualifiers.to_string();
+        Ok(me)
+    }
+
     fn set_load_control(self, mode: DBLoadControl) -> BuildBundle {
         let mut me = self?;
         me.load_control = Some(mode);
@@ -191,9 +440,35 @@ impl DBBuilderTrait for BuildBundle {
         Ok(me)
     }
 
+    /// Sets the directory holding the index caches posix sources are read through, None reads
+    /// them without a cache
+    fn set_cache_dir(self, cache_dir: Option<PathBuf>) -> BuildBundle {
+        let mut me = self?;
+        me.read_options.cache_dir = cache_dir;
+        Ok(me)
+    }
+
+    /// Sets the number of threads used to read each posix source
+    fn set_workers(self, workers: usize) -> BuildBundle {
+        let mut me = self?;
+        if workers == 0 {
+            return Err("The number of workers must be at least 1".to_string());
+        }
+        me.read_options.workers = workers;
+        Ok(me)
+    }
+
+    /// Sets the overlay file, whose products are looked up before those of every other source.
+    /// None builds the database without an overlay
+    fn set_overlay(self, overlay: Option<PathBuf>) -> BuildBundle {
+        let mut me = self?;
+        me.overlay = overlay;
+        Ok(me)
+    }
+
     fn build(self) -> Result<DB, String> {
         let mut db_dict = FnvHashMap::<String, Box<db_impl::DBImpl>>::default();
-        let me = self?;
+        let mut me = self?;
         if me.eups_env {
             let eups_env_path_result = cogs::get_eups_path_from_env();
             let eups_env_path = match eups_env_path_result {
@@ -211,11 +486,6 @@ impl DBBuilderTrait for BuildBundle {
                     "Adding {} to databases",
                     pth.to_str().expect("Malformed database string")
                 );
-                let temp_db =
-                    match db_impl::PosixDBImpl::new(pth.clone(), me.load_control.as_ref(), None) {
-                        Ok(x) => x,
-                        Err(msg) => return Err(msg),
-                    };
                 // expect should be safe here, as we pushed a directory on previously
                 // Format the database map name in a deterministic way with the last bit of the path
                 let db_name = format!(
@@ -227,7 +497,14 @@ impl DBBuilderTrait for BuildBundle {
                         .to_str()
                         .expect("Problem turning directory osString to str")
                 );
-                db_dict.insert(db_name.clone(), Box::new(temp_db));
+                let temp_db = open_source(
+                    pth,
+                    me.load_control.as_ref(),
+                    me.get_ident_regex(&db_name),
+                    &me.read_options,
+                )?;
+                me.add_to_layer(&db_name, SourceLayer::System, SYSTEM_PRIORITY);
+                db_dict.insert(db_name.clone(), temp_db);
             }
         };
         // Handle the user paths
@@ -239,12 +516,15 @@ impl DBBuilderTrait for BuildBundle {
                     "Adding {} to databases",
                     pth.clone().to_str().expect("Malformed database string")
                 );
-                let user_db = match db_impl::PosixDBImpl::new(pth, me.load_control.as_ref(), None) {
-                    Ok(x) => x,
-                    Err(msg) => return Err(msg),
-                };
                 let database_name = String::from("posix_user");
-                db_dict.insert(database_name.clone(), Box::new(user_db));
+                let user_db = open_posix_source(
+                    pth,
+                    me.load_control.as_ref(),
+                    me.get_ident_regex(&database_name),
+                    &me.read_options,
+                )?;
+                me.add_to_layer(&database_name, SourceLayer::User, USER_PRIORITY);
+                db_dict.insert(database_name.clone(), user_db);
             }
         };
         if me.reups_env {
@@ -264,10 +544,6 @@ impl DBBuilderTrait for BuildBundle {
                     "Adding {} to databases",
                     pth.to_str().expect("Malformed database string")
                 );
-                let temp_db = match db_impl::JsonDBImpl::new(&pth) {
-                    Ok(x) => x,
-                    Err(msg) => return Err(msg),
-                };
                 // expect should be safe here, as we pushed a directory on previously
                 // Format the database map name in a deterministic way with the last bit of the path
                 let db_name = format!(
@@ -279,7 +555,14 @@ impl DBBuilderTrait for BuildBundle {
                         .to_str()
                         .expect("Problem turning directory osString to str")
                 );
-                db_dict.insert(db_name.clone(), Box::new(temp_db));
+                let temp_db = open_source(
+                    pth,
+                    me.load_control.as_ref(),
+                    me.get_ident_regex(&db_name),
+                    &me.read_options,
+                )?;
+                me.add_to_layer(&db_name, SourceLayer::System, SYSTEM_PRIORITY);
+                db_dict.insert(db_name.clone(), temp_db);
             }
         }
         if me.reups_user {
@@ -295,42 +578,48 @@ impl DBBuilderTrait for BuildBundle {
                     Err(msg) => return Err(format!("{}", msg)),
                 };
                 let database_name = String::from("json_user");
+                me.add_to_layer(&database_name, SourceLayer::User, USER_PRIORITY);
                 db_dict.insert(database_name, Box::new(user_db));
             }
         }
         // Handle any other paths that were added
         for (name, pth) in me.db_sources.iter() {
-            let extension = pth.extension();
-            let extra_db: Box<db_impl::DBImpl> =
-                if extension.is_some() && extension.unwrap() == "json" {
-                    if !pth.exists() {
-                        println!("in not exists");
-                        crate::warn!(
-                        "The backend {} does not exist on disk, creating empty source in memory",
-                        pth.to_str().unwrap()
-                    );
-                        match db_impl::JsonDBImpl::new(pth) {
-                            Ok(x) => Box::new(x),
-                            Err(_) => return Err("Problem creating new json source\n".to_string()),
-                        }
-                    } else {
-                        match db_impl::JsonDBImpl::from_file(pth) {
-                            Ok(x) => Box::new(x),
-                            Err(e) => return Err(format!("{}\n", e.to_string())),
-                        }
-                    }
-                } else {
-                    match db_impl::PosixDBImpl::new(pth.clone(), me.load_control.as_ref(), None) {
-                        Ok(x) => Box::new(x),
-                        Err(msg) => return Err(msg),
-                    }
-                };
+            let extra_db = open_source(
+                pth,
+                me.load_control.as_ref(),
+                me.get_ident_regex(name),
+                &me.read_options,
+            )?;
             db_dict.insert(name.clone(), extra_db);
         }
-        let db_names: Vec<String> = db_dict.keys().map(|x| x.clone()).collect();
+        // Add the sources that were opened before being added
+        for (name, source) in me.built_sources.drain(..) {
+            db_dict.insert(name, source);
+        }
+        // The overlay is only used once something has been declared into it, and is looked up
+        // first unless given a priority
+        if let Some(pth) = me.overlay.as_ref().filter(|x| x.exists()) {
+            let overlay = db_impl::OverlayDBImpl::new(pth)?;
+            db_dict.insert(OVERLAY_NAME.to_string(), Box::new(overlay));
+            me.add_to_layer(OVERLAY_NAME, SourceLayer::Overlay, i32::MAX);
+        }
+        // Order the sources by priority, highest first, sources with equal priority are ordered by
+        // layer, and then by name so the order is deterministic
+        let mut db_names: Vec<String> = db_dict.keys().map(|x| x.clone()).collect();
+        let priority = |name: &String| me.db_priorities.get(name).cloned().unwrap_or(0);
+        let layer = |name: &String| me.db_layers.get(name).cloned();
+        db_names.sort_by(|a, b| {
+            priority(b)
+                .cmp(&priority(a))
+                .then_with(|| layer(b).cmp(&layer(a)))
+                .then_with(|| a.cmp(b))
+        });
         Ok(DB {
             database_map: db_dict,
             database_names: db_names,
+            overlay: me.overlay,
+            flavor: me.flavor.unwrap_or_else(|| cogs::SYSTEM_OS.to_string()),
+            qualifiers: me.qualifiers,
             cache: RefCell::new(FnvHashMap::default()),
         })
     }
@@ -342,6 +631,9 @@ impl DBBuilderTrait for BuildBundle {
 pub struct DB {
     database_map: FnvHashMap<String, Box<dyn db_impl::DBImpl>>,
     database_names: Vec<String>,
+    overlay: Option<PathBuf>,
+    flavor: String,
+    qualifiers: String,
     cache: RefCell<FnvHashMap<(String, String), table::Table>>,
 }
 
@@ -403,16 +695,53 @@ impl DB {
         product_tags
     }
 
-    pub fn get_table_from_identity(&self, product: &str, id: &str) -> Option<table::Table> {
+    /// Looks up the version of a product corresponding to the specified identity
+    pub fn get_version_from_identity(&self, product: &str, id: &str) -> Option<&str> {
         for (_, db) in self.iter() {
             if db.has_identity(product, id) {
-                let version = db.lookup_version_ident(product, id)?;
-                return self.get_table_from_version(product, version);
+                return db.lookup_version_ident(product, id);
             }
         }
         None
     }
 
+    /// Looks up the identity of the specified version of a product, if it was declared with one
+    pub fn get_identity_from_version(&self, product: &str, version: &str) -> Option<&str> {
+        for (_, db) in self.iter() {
+            let found = db.get_identities(product).and_then(|ids| {
+                ids.into_iter()
+                    .find(|id| db.lookup_version_ident(product, id) == Some(version))
+            });
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    /// Returns the name of the first source, in the order sources are searched, which declares
+    /// the product, or the given version of it
+    pub fn get_source_of_product(&self, product: &str, version: Option<&str>) -> Option<&str> {
+        for (name, db) in self.iter() {
+            let found = match version {
+                Some(version) => db
+                    .get_versions(product)
+                    .is_some_and(|versions| versions.contains(&version)),
+                None => db.has_product(product),
+            };
+            if found {
+                return Some(name);
+            }
+        }
+        None
+    }
+
+    /// Looks up the table corresponding to the product, identity combination specified.
+    pub fn get_table_from_identity(&self, product: &str, id: &str) -> Option<table::Table> {
+        let version = self.get_version_from_identity(product, id)?;
+        self.get_table_from_version(product, version)
+    }
+
     /// Looks up the table corresponding to the product, version combination specified.
     pub fn get_table_from_version(&self, product: &str, version: &str) -> Option<table::Table> {
         crate::debug!("Getting table from version {}", version);
@@ -430,7 +759,9 @@ impl DB {
         let mut tables_vec: Vec<(Option<table::Table>, &str)> = vec![];
 
         for (name, db) in self.iter() {
-            if let Some(product_table) = db.get_table(product, version) {
+            if let Some(product_table) =
+                db.get_table(product, version, Some(&self.flavor), Some(&self.qualifiers))
+            {
                 tables_vec.push((Some(product_table), name));
             }
         }
@@ -466,17 +797,39 @@ impl DB {
         }
     }
 
-    /// Lists the flavors of a product corresponding to a specified product and version
+    /// Returns the flavor products are looked up with
+    pub fn get_flavor(&self) -> &str {
+        &self.flavor
+    }
+
+    /// Returns the qualifiers products are looked up with
+    pub fn get_qualifiers(&self) -> &str {
+        &self.qualifiers
+    }
+
+    /// Lists all the flavors a product is declared with for a specified version
     pub fn get_flavors_from_version(&self, product: &str, version: &str) -> Vec<&str> {
         let mut flavors = Vec::new();
         for (_, db) in self.iter() {
-            if let Some(flavor) = db.lookup_flavor_version(product, version) {
-                flavors.push(flavor);
+            if let Some(db_flavors) = db.get_flavors(product, version) {
+                for flavor in db_flavors {
+                    if !flavors.contains(&flavor) {
+                        flavors.push(flavor);
+                    }
+                }
             }
         }
         flavors
     }
 
+    /// Returns the flavor of a product version that would be used on this system, preferring
+    /// an exact match to the flavor of the database over a generic one
+    pub fn get_flavor_from_version(&self, product: &str, version: &str) -> Option<&str> {
+        self.iter().find_map(|(_, db)| {
+            db.lookup_flavor_version(product, version, Some(&self.flavor), Some(&self.qualifiers))
+        })
+    }
+
     /// Looks up all the versions which correspond to specified prodcut and tag
     pub fn get_versions_from_tag(&self, product: &str, tags: &Vec<&str>) -> Vec<&str> {
         crate::debug!("Looking up all versions for tagged product");
@@ -491,7 +844,9 @@ impl DB {
                     t,
                     name
                 );
-                if let Some(version) = db.lookup_version_tag(product, t) {
+                if let Some(version) =
+                    db.lookup_version_tag(product, t, Some(&self.flavor), Some(&self.qualifiers))
+                {
                     crate::debug!("Found version {}", version);
                     versions_vec.push(version);
                 }
@@ -538,31 +893,28 @@ impl DB {
             .clone()
     }
 
-    /// Looks up a table file given a product and tag
-    pub fn get_table_from_tag(&self, product: &str, tag: &Vec<&str>) -> Option<table::Table> {
+    /// Looks up the version of a product with the first of the tags, and the table of that
+    /// version. Tags are tried in the order given, and each tag is looked up in the sources in
+    /// priority order, highest first. The table is that of the version in the highest priority
+    /// source declaring it
+    pub fn get_version_table_from_tag(
+        &self,
+        product: &str,
+        tag: &Vec<&str>,
+    ) -> Option<(&str, table::Table)> {
         crate::debug!("Looking up table from tag");
         let versions_vec = self.get_versions_from_tag(product, tag);
         crate::debug!("Found versions {:?}", versions_vec);
-        // use the last element, as this will select the user tag if one is present else
-        // it will return the result from the main tag
-        // it is safe to unwrap here, as there must be at least one db to construct the
-        // object. The real Option to worry about is the one that is contained in the vec
-
-        match versions_vec.len() {
-            x if x > 0 => {
-                let mut res: Option<table::Table> = None;
-                for ver in versions_vec.iter().rev() {
-                    res = self.get_table_from_version(product, ver);
-                    // if we found the product in a given database, then bail out, no need
-                    // to search further
-                    if res.is_some() {
-                        break;
-                    }
-                }
-                res
-            }
-            _ => None,
-        }
+        versions_vec
+            .into_iter()
+            .find_map(|ver| Some((ver, self.get_table_from_version(product, ver)?)))
+    }
+
+    /// Looks up the table of the version of a product with the first of the tags, see
+    /// get_version_table_from_tag
+    pub fn get_table_from_tag(&self, product: &str, tag: &Vec<&str>) -> Option<table::Table> {
+        self.get_version_table_from_tag(product, tag)
+            .map(|(_, table)| table)
     }
 
     /// Creates an iterator over the database object. This will loop over the system
@@ -593,6 +945,61 @@ impl DB {
         return false;
     }
 
+    /// Checks the integrity of each source in the database. A dependency is considered declared
+    /// if any of the sources declare it.
+    pub fn check(&self) -> Vec<SourceCheck> {
+        let is_declared = |product: &str| self.has_product(product);
+        self.iter()
+            .map(|(name, db)| {
+                let mut problems = db.check(&is_declared);
+                problems.sort();
+                SourceCheck {
+                    source: name.to_string(),
+                    location: db.get_location().clone(),
+                    problems,
+                }
+            })
+            .collect()
+    }
+
+    /// Repairs the sources of the database, or only the named source if one is supplied. The
+    /// product directories recorded are rewritten if a style is supplied. Nothing is written if
+    /// dry_run is set, or to sources which are not writable, but the repairs that would be made
+    /// are still returned.
+    pub fn fix(
+        &mut self,
+        source: Option<&str>,
+        paths: Option<PathStyle>,
+        dry_run: bool,
+    ) -> Result<Vec<SourceFix>, String> {
+        if let Some(name) = source {
+            if !self.database_map.contains_key(name) {
+                return Err(format!("No source named {} found\n", name));
+            }
+        }
+        let mut fixes = vec![];
+        for name in self.database_names.iter() {
+            if source.is_some_and(|x| x != name) {
+                continue;
+            }
+            let db = self.database_map.get_mut(name).unwrap();
+            let writable = db.is_writable();
+            let mut repairs = if dry_run || !writable {
+                db.plan_fix(paths)?
+            } else {
+                db.fix(paths)?
+            };
+            repairs.sort();
+            fixes.push(SourceFix {
+                source: name.clone(),
+                location: db.get_location().clone(),
+                writable,
+                repairs,
+            });
+        }
+        Ok(fixes)
+    }
+
     /// Declares a new product to the database
     pub fn declare(
         &mut self,
@@ -603,16 +1010,7 @@ impl DB {
             if !self.database_map.contains_key(src) {
                 return DeclareResults::NoSource;
             }
-            if !self
-                .database_map
-                .get(src)
-                .unwrap()
-                .get_location()
-                .metadata()
-                .expect(&format!("Problem with metadata on source {} path", src))
-                .permissions()
-                .readonly()
-            {
+            if self.database_map.get(src).unwrap().is_writable() {
                 src.to_string()
             } else {
                 return DeclareResults::NoneWritable;
@@ -634,6 +1032,7 @@ impl DB {
 
         let active_db = self.database_map.remove(&source_name).unwrap();
         crate::debug!("Adding input into database source {}", source_name);
+        let history = history_entries(active_db.as_ref(), &inputs);
         let new_result = active_db.declare(&inputs);
         match new_result {
             Err((new, msg)) => {
@@ -641,11 +1040,73 @@ impl DB {
                 return DeclareResults::Error(source_name, msg);
             }
             Ok(new) => {
+                log_history(new.as_ref(), &history);
                 self.database_map.insert(source_name.clone(), new);
                 return DeclareResults::Success(source_name);
             }
         }
     }
+
+    /// Declares products into the overlay, which is created if nothing has been declared into
+    /// it yet. The overlay is written to its file, but no other source is changed
+    pub fn declare_overlay(&mut self, inputs: Vec<db_impl::DeclareInputs>) -> DeclareResults {
+        let overlay = match self.database_map.remove(OVERLAY_NAME) {
+            Some(overlay) => overlay,
+            None => {
+                let pth = match self.overlay.as_ref() {
+                    Some(pth) => pth,
+                    None => return DeclareResults::NoSource,
+                };
+                match db_impl::OverlayDBImpl::new(pth) {
+                    Ok(overlay) => {
+                        self.database_names.insert(0, OVERLAY_NAME.to_string());
+                        Box::new(overlay)
+                    }
+                    Err(msg) => return DeclareResults::Error(OVERLAY_NAME.to_string(), msg),
+                }
+            }
+        };
+        crate::debug!("Adding input into the overlay");
+        let history = history_entries(overlay.as_ref(), &inputs);
+        let (overlay, result) = match overlay.declare(&inputs) {
+            Err((overlay, msg)) => (
+                overlay,
+                DeclareResults::Error(OVERLAY_NAME.to_string(), msg),
+            ),
+            Ok(overlay) => {
+                log_history(overlay.as_ref(), &history);
+                (overlay, DeclareResults::Success(OVERLAY_NAME.to_string()))
+            }
+        };
+        self.database_map.insert(OVERLAY_NAME.to_string(), overlay);
+        self.cache.borrow_mut().clear();
+        result
+    }
+}
+
+/// Records the entries in the audit log of the source. The changes were already written to the
+/// source, so a log which can not be written is warned about rather than failing the declare
+fn log_history(source: &dyn db_impl::DBImpl, entries: &[HistoryEntry]) {
+    if let Err(msg) = record_history(source.get_location(), entries) {
+        crate::warn!("{}", msg);
+    }
+}
+
+/// The problems found when checking the integrity of a database source
+#[derive(Debug, Serialize)]
+pub struct SourceCheck {
+    pub source: String,
+    pub location: PathBuf,
+    pub problems: Vec<CheckProblem>,
+}
+
+/// The repairs made, or that would be made, when fixing a database source
+#[derive(Debug, Serialize)]
+pub struct SourceFix {
+    pub source: String,
+    pub location: PathBuf,
+    pub writable: bool,
+    pub repairs: Vec<Repair>,
 }
 
 #[derive(Debug)]
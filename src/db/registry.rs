@@ -0,0 +1,154 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ The registry maps the paths of database sources to the backends which open them. A path with a
+ url scheme, such as `http://host/stack.json`, is opened by the constructor registered for its
+ scheme, and a file path by the constructor registered for its extension. Paths matching neither
+ are posix sources, directories containing a ups_db directory.
+
+ The json, sqlite, and http backends of reups are registered by default. Library users may register
+ constructors for their own `DBImpl` implementations, which are then used for paths given on the
+ command line, in configuration files, and in the environment, or replace the default ones.
+*/
+use super::db_impl;
+use super::db_impl::DBImpl;
+use fnv::FnvHashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Creates a database source from its path
+pub type SourceConstructor = Arc<dyn Fn(&Path) -> Result<Box<dyn DBImpl>, String> + Send + Sync>;
+
+/// Constructors keyed by url scheme and by extension
+struct Registry {
+    schemes: FnvHashMap<String, SourceConstructor>,
+    extensions: FnvHashMap<String, SourceConstructor>,
+}
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: RwLock<Registry> = RwLock::new(Registry::with_defaults());
+}
+
+impl Registry {
+    /// Creates a registry holding the backends built into reups
+    fn with_defaults() -> Registry {
+        let mut registry = Registry {
+            schemes: FnvHashMap::default(),
+            extensions: FnvHashMap::default(),
+        };
+        registry.schemes.insert(
+            "http".to_string(),
+            Arc::new(|pth: &Path| {
+                Ok(Box::new(db_impl::HttpDBImpl::new(pth.to_str().unwrap())?) as Box<dyn DBImpl>)
+            }),
+        );
+        registry
+            .extensions
+            .insert("json".to_string(), Arc::new(open_json));
+        registry
+            .extensions
+            .insert("sqlite".to_string(), Arc::new(open_sqlite));
+        registry
+    }
+}
+
+/// Opens a json source, which is created empty in memory if it does not exist yet
+fn open_json(pth: &Path) -> Result<Box<dyn DBImpl>, String> {
+    if !pth.exists() {
+        crate::warn!(
+            "The backend {} does not exist on disk, creating empty source in memory",
+            pth.to_str().unwrap()
+        );
+        match db_impl::JsonDBImpl::new(&pth.to_path_buf()) {
+            Ok(x) => Ok(Box::new(x)),
+            Err(_) => Err("Problem creating new json source\n".to_string()),
+        }
+    } else {
+        match db_impl::JsonDBImpl::from_file(&pth.to_path_buf()) {
+            Ok(x) => Ok(Box::new(x)),
+            Err(e) => Err(format!("{}\n", e)),
+        }
+    }
+}
+
+/// Opens a sqlite source, which is created empty in memory if it does not exist yet
+fn open_sqlite(pth: &Path) -> Result<Box<dyn DBImpl>, String> {
+    if !pth.exists() {
+        crate::warn!(
+            "The backend {} does not exist on disk, creating empty source in memory",
+            pth.to_str().unwrap()
+        );
+        Ok(Box::new(db_impl::SqliteDBImpl::new(&pth.to_path_buf())?))
+    } else {
+        match db_impl::SqliteDBImpl::from_file(&pth.to_path_buf()) {
+            Ok(x) => Ok(Box::new(x)),
+            Err(e) => Err(format!("{}\n", e)),
+        }
+    }
+}
+
+/// Returns the url scheme of a path, if it has one
+pub fn source_scheme(pth: &Path) -> Option<&str> {
+    let (scheme, _) = pth.to_str()?.split_once("://")?;
+    if crate::cogs::is_scheme(scheme) {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// Registers the constructor used to open sources whose paths have the url scheme, such as
+/// `http`, replacing any constructor already registered for it
+pub fn register_scheme<F>(scheme: &str, constructor: F)
+where
+    F: Fn(&Path) -> Result<Box<dyn DBImpl>, String> + Send + Sync + 'static,
+{
+    REGISTRY
+        .write()
+        .unwrap()
+        .schemes
+        .insert(scheme.to_string(), Arc::new(constructor));
+}
+
+/// Registers the constructor used to open sources whose paths have the extension, such as
+/// `json`, replacing any constructor already registered for it
+pub fn register_extension<F>(extension: &str, constructor: F)
+where
+    F: Fn(&Path) -> Result<Box<dyn DBImpl>, String> + Send + Sync + 'static,
+{
+    REGISTRY
+        .write()
+        .unwrap()
+        .extensions
+        .insert(extension.to_string(), Arc::new(constructor));
+}
+
+/// Returns the constructor registered for the scheme of a path, or otherwise for its extension.
+/// A path with a scheme which is not registered has no constructor, even if its extension is
+pub fn registered_constructor(pth: &Path) -> Option<SourceConstructor> {
+    let registry = REGISTRY.read().unwrap();
+    match source_scheme(pth) {
+        Some(scheme) => registry.schemes.get(scheme).cloned(),
+        None => registry.extensions.get(pth.extension()?.to_str()?).cloned(),
+    }
+}
+
+/// Returns true if the path is opened by a registered constructor, rather than being a directory
+/// containing a ups_db directory
+pub fn is_registered_source(pth: &Path) -> bool {
+    registered_constructor(pth).is_some()
+}
+
+/// Returns the path a source given on the command line is opened from, a registered source is
+/// opened from the path itself, and a posix source from the ups_db directory inside it
+pub fn source_location(path: &str) -> PathBuf {
+    let pth = PathBuf::from(path);
+    if is_registered_source(&pth) {
+        pth
+    } else {
+        pth.join("ups_db")
+    }
+}
@@ -77,9 +77,7 @@ impl Table {
         prod_dir: path::PathBuf,
     ) -> Result<Table, io::Error> {
         // expand product path in case there are any relative links in the path
-        let prod_dir = prod_dir
-            .canonicalize()
-            .expect("Problem getting full table path");
+        let prod_dir = prod_dir.canonicalize()?;
         let mut f = File::open(path.clone())?;
         crate::debug!("Opened file {}", path.to_str().unwrap());
         let mut contents = String::new();
@@ -127,6 +125,57 @@ impl Table {
         })
     }
 
+    /// Creates the table of a product declared without a table file, which has no dependencies
+    /// and no environment actions, so setting it up only sets its product directory
+    pub fn empty(name: String, prod_dir: path::PathBuf) -> Table {
+        let empty_deps = || Deps {
+            required: FnvHashMap::default(),
+            optional: FnvHashMap::default(),
+        };
+        Table {
+            name,
+            path: None,
+            product_dir: prod_dir,
+            exact: Some(empty_deps()),
+            inexact: Some(empty_deps()),
+            env_var: FnvHashMap::default(),
+        }
+    }
+
+    /// Checks that the table file at the supplied path can be read and is well formed,
+    /// meaning parentheses close on the line they are opened, braces are balanced, and
+    /// every setup statement can be understood. The error describes the first problem found.
+    pub fn check_file(path: &path::Path) -> Result<(), String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("{}", e))?;
+        let mut depth = 0;
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.matches('(').count() != line.matches(')').count() {
+                return Err(format!("unbalanced parentheses on line {}", number + 1));
+            }
+            depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+            if depth < 0 {
+                return Err(format!("unexpected closing brace on line {}", number + 1));
+            }
+            if (line.starts_with("setupRequired") || line.starts_with("setupOptional"))
+                && !EXACT.is_match(line)
+                && !INEXACT.is_match(line)
+            {
+                return Err(format!(
+                    "setup statement on line {} could not be parsed",
+                    number + 1
+                ));
+            }
+        }
+        if depth != 0 {
+            return Err("unbalanced braces".to_string());
+        }
+        Ok(())
+    }
+
     pub fn to_file(&self, filename: &str) -> Result<(), String> {
         let mut contents = "".to_string();
         contents.push_str("if (type == exact) {\n");
@@ -140,11 +189,19 @@ impl Table {
         }
         contents.push_str("} else {\n");
         if self.inexact.is_some() {
+            // Dependencies without a version are written without a version constraint
+            let inexact = |kind, k, v: &String| {
+                if v.is_empty() {
+                    format!("   {}({})\n", kind, k)
+                } else {
+                    format!("   {}({} {} [>= {}])\n", kind, k, v, v)
+                }
+            };
             for (k, v) in self.inexact.as_ref().unwrap().required.iter() {
-                contents.push_str(&format!("   setupRequired({} {} [>= {}])\n", k, v, v));
+                contents.push_str(&inexact("setupRequired", k, v));
             }
             for (k, v) in self.inexact.as_ref().unwrap().optional.iter() {
-                contents.push_str(&format!("   setupOptional({} {} [>= {}])\n", k, v, v));
+                contents.push_str(&inexact("setupOptional", k, v));
             }
         }
         contents.push_str("}\n");
@@ -0,0 +1,432 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ The db_command module holds the db subcommand of reups, whose actions maintain the database
+ sources themselves rather than the products in them.
+*/
+use crate::argparse;
+use crate::cogs;
+use crate::db;
+use crate::db::DBBuilderTrait;
+use crate::logger;
+use std::io::Write;
+
+/**
+ * This is the entry-point for the db subcommand, it dispatches to the function implementing the
+ * requested action.
+ *
+ * * sub_args - Arguments matched from the command line to the db sub command
+ * * main_args - Arguments matched from the command line to the main reups executable
+ * * writer - Destination the output of the action is written to
+ **/
+pub fn db_command<W: Write>(
+    sub_args: &argparse::ArgMatches,
+    main_args: &argparse::ArgMatches,
+    writer: &mut W,
+) -> Result<(), String> {
+    match sub_args.subcommand() {
+        ("check", Some(m)) => check_command(m, main_args, writer),
+        ("fix", Some(m)) => fix_command(m, main_args, writer),
+        ("convert", Some(m)) => convert_command(m, main_args, writer),
+        ("diff", Some(m)) => diff_command(m, main_args, writer),
+        ("history", Some(m)) => history_command(m, main_args, writer),
+        ("cache", Some(m)) => cache_command(m, main_args, writer),
+        _ => Err(format!("{}\n", sub_args.usage())),
+    }
+}
+
+/**
+ * Checks every source in the database for entries which are broken, such as tags pointing at
+ * versions which do not exist, product directories or table files that are missing, table files
+ * which can not be parsed, and dependencies on products which are not declared. The problems are
+ * written to the writer, as text or as JSON if requested. An error is returned if any problem is
+ * an error rather than a warning, so that the command exits with a non-zero status.
+ **/
+pub fn check_command<W: Write>(
+    sub_args: &argparse::ArgMatches,
+    _main_args: &argparse::ArgMatches,
+    writer: &mut W,
+) -> Result<(), String> {
+    logger::build_logger(sub_args, std::io::stderr());
+    let db = db::DBBuilder::from_args(sub_args).build()?;
+    let checks = db.check();
+    let problems = checks.iter().flat_map(|x| x.problems.iter());
+    let errors = problems
+        .clone()
+        .filter(|x| x.severity == db::Severity::Error)
+        .count();
+    let warnings = problems.count() - errors;
+
+    let output = if sub_args.is_present("json") {
+        let mut json = serde_json::to_string_pretty(&checks).map_err(|e| format!("{}", e))?;
+        json.push('\n');
+        json
+    } else {
+        format_checks(&checks, errors, warnings)
+    };
+    writer
+        .write_all(output.as_bytes())
+        .map_err(|e| format!("Problem writing check results: {}", e))?;
+
+    if errors > 0 {
+        return Err(format!("Database check found {} errors\n", errors));
+    }
+    Ok(())
+}
+
+/**
+ * Repairs common damage to the sources of the database. Tags pointing at versions which are not
+ * declared are removed, identities are created for JSON versions declared without one, entries of
+ * JSON files without a partner are dropped, and if requested the product directories recorded are
+ * rewritten as absolute or relative paths. With the dry-run option the repairs are only written
+ * to the writer. An error is returned if there were repairs to make in sources which are not
+ * writable.
+ **/
+pub fn fix_command<W: Write>(
+    sub_args: &argparse::ArgMatches,
+    _main_args: &argparse::ArgMatches,
+    writer: &mut W,
+) -> Result<(), String> {
+    logger::build_logger(sub_args, std::io::stderr());
+    let mut db = db::DBBuilder::from_args(sub_args).build()?;
+    let paths = match sub_args.value_of("paths") {
+        Some("absolute") => Some(db::PathStyle::Absolute),
+        Some("relative") => Some(db::PathStyle::Relative),
+        _ => None,
+    };
+    let dry_run = sub_args.is_present("dry-run");
+    let fixes = db.fix(sub_args.value_of("source"), paths, dry_run)?;
+
+    let mut output = String::new();
+    let mut unwritable = 0;
+    for fix in fixes.iter().filter(|x| !x.repairs.is_empty()) {
+        output.push_str(&format!(
+            "{} ({})\n",
+            fix.source,
+            fix.location.to_str().unwrap_or("")
+        ));
+        for repair in fix.repairs.iter() {
+            output.push_str(&format!(
+                "  {}\n",
+                format_entry(
+                    &repair.product,
+                    &repair.version,
+                    &repair.flavor,
+                    &repair.description
+                )
+            ));
+        }
+        if !fix.writable {
+            output.push_str("  The source is not writable, these repairs were not made\n");
+            unwritable += fix.repairs.len();
+        }
+    }
+    let repairs: usize = fixes.iter().map(|x| x.repairs.len()).sum();
+    if dry_run {
+        output.push_str(&format!(
+            "Found {} repairs to make in {} sources, none were made as this is a dry run\n",
+            repairs,
+            fixes.len()
+        ));
+    } else {
+        output.push_str(&format!(
+            "Made {} repairs in {} sources\n",
+            repairs - unwritable,
+            fixes.len()
+        ));
+    }
+    writer
+        .write_all(output.as_bytes())
+        .map_err(|e| format!("Problem writing fix results: {}", e))?;
+
+    if !dry_run && unwritable > 0 {
+        return Err(format!(
+            "{} repairs could not be made as their sources are not writable\n",
+            unwritable
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * Copies every version and tag of one database source into another, which may be of a different
+ * type. Unless merging, the destination must not already exist. Entries the destination already
+ * contains are not replaced, they are written to the writer as conflicts and an error is returned
+ * so that the command exits with a non-zero status.
+ **/
+pub fn convert_command<W: Write>(
+    sub_args: &argparse::ArgMatches,
+    _main_args: &argparse::ArgMatches,
+    writer: &mut W,
+) -> Result<(), String> {
+    logger::build_logger(sub_args, std::io::stderr());
+    let from = sub_args.value_of("from").unwrap();
+    let to = sub_args.value_of("to").unwrap();
+    let source = open_path(from)?;
+
+    // Destinations are named the same way as sources, a posix destination is the ups_db
+    // directory inside the path
+    let dest_path = db::source_location(to);
+    if dest_path.exists() && !sub_args.is_present("merge") {
+        return Err(format!(
+            "Destination {} already exists, use --merge to add to it\n",
+            to
+        ));
+    }
+    if !db::is_registered_source(&dest_path) {
+        std::fs::create_dir_all(&dest_path)
+            .map_err(|e| format!("Problem creating destination {}: {}\n", to, e))?;
+    }
+    let mut dest = db::open_source(
+        &dest_path,
+        Some(&db::DBLoadControl::All),
+        None,
+        &Default::default(),
+    )?;
+
+    let conversion = db::convert(source.as_ref(), dest.as_mut());
+    for product in source.get_products() {
+        if dest.has_product(product) {
+            dest.sync(product)
+                .map_err(|e| format!("Problem writing {} to {}: {}\n", product, to, e))?;
+        }
+    }
+
+    let mut output = String::new();
+    if !conversion.conflicts.is_empty() {
+        output.push_str("Conflicts:\n");
+        for conflict in conversion.conflicts.iter() {
+            output.push_str(&format!("  {}\n", conflict));
+        }
+    }
+    output.push_str(&format!(
+        "Converted {} versions and {} tags from {} to {}\n",
+        conversion.versions, conversion.tags, from, to
+    ));
+    writer
+        .write_all(output.as_bytes())
+        .map_err(|e| format!("Problem writing convert results: {}", e))?;
+
+    if !conversion.conflicts.is_empty() {
+        return Err(format!(
+            "{} entries were not converted\n",
+            conversion.conflicts.len()
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * Compares two database sources, which may be of different types, such as two snapshots of a
+ * shared stack. The products and versions added or removed going from the first source to the
+ * second are written to the writer, along with the tags that moved and the tables whose
+ * dependencies or environment actions differ.
+ **/
+pub fn diff_command<W: Write>(
+    sub_args: &argparse::ArgMatches,
+    _main_args: &argparse::ArgMatches,
+    writer: &mut W,
+) -> Result<(), String> {
+    logger::build_logger(sub_args, std::io::stderr());
+    let name_a = sub_args.value_of("source_a").unwrap();
+    let name_b = sub_args.value_of("source_b").unwrap();
+    let (source_a, source_b) = (open_path(name_a)?, open_path(name_b)?);
+    let differences = db::diff(source_a.as_ref(), source_b.as_ref());
+
+    let mut output = String::new();
+    for difference in differences.iter() {
+        output.push_str(&format!(
+            "{}\n",
+            format_entry(
+                &Some(difference.product.clone()),
+                &difference.version,
+                &difference.flavor,
+                &difference.description
+            )
+        ));
+    }
+    output.push_str(&format!(
+        "Found {} differences between {} and {}\n",
+        differences.len(),
+        name_a,
+        name_b
+    ));
+    writer
+        .write_all(output.as_bytes())
+        .map_err(|e| format!("Problem writing diff results: {}", e))
+}
+
+/**
+ * Writes the audit log of each source in the database, the declares and tag changes made to it
+ * with who made them, when, and from which host, oldest first. If a product is given only its
+ * entries are written. The log may be written as JSON, with the entries of each source.
+ **/
+pub fn history_command<W: Write>(
+    sub_args: &argparse::ArgMatches,
+    _main_args: &argparse::ArgMatches,
+    writer: &mut W,
+) -> Result<(), String> {
+    logger::build_logger(sub_args, std::io::stderr());
+    let db = db::DBBuilder::from_args(sub_args)
+        .set_load_control(db::DBLoadControl::Lazy)
+        .build()?;
+    let product = sub_args.value_of("product");
+    let mut histories = vec![];
+    for (name, location) in db.get_db_sources() {
+        let entries = db::read_history(&location, product)?;
+        histories.push(db::SourceHistory {
+            source: name,
+            location,
+            entries,
+        });
+    }
+
+    let output = if sub_args.is_present("json") {
+        let mut json = serde_json::to_string_pretty(&histories).map_err(|e| format!("{}", e))?;
+        json.push('\n');
+        json
+    } else {
+        format_history(&histories)
+    };
+    writer
+        .write_all(output.as_bytes())
+        .map_err(|e| format!("Problem writing history: {}", e))
+}
+
+/**
+ * Manages the index caches which posix database sources are read through. The clear action
+ * removes all of the caches, so that each is rebuilt from its source the next time it is read.
+ **/
+pub fn cache_command<W: Write>(
+    sub_args: &argparse::ArgMatches,
+    _main_args: &argparse::ArgMatches,
+    writer: &mut W,
+) -> Result<(), String> {
+    logger::build_logger(sub_args, std::io::stderr());
+    let cache_dir = match cogs::get_index_cache_dir() {
+        Some(x) => x,
+        None => return Err("Could not determine the index cache directory\n".to_string()),
+    };
+    match sub_args.subcommand() {
+        ("clear", Some(_)) => {
+            let removed = db::clear_index_cache(&cache_dir)?;
+            let output = format!(
+                "Removed {} index caches from {}\n",
+                removed,
+                cache_dir.to_str().unwrap()
+            );
+            writer
+                .write_all(output.as_bytes())
+                .map_err(|e| format!("Problem writing cache results: {}", e))
+        }
+        _ => Err(format!("{}\n", sub_args.usage())),
+    }
+}
+
+/// Opens the source at a path given on the command line, a json or sqlite file or a directory
+/// containing a ups_db directory
+fn open_path(path: &str) -> Result<Box<dyn db::DBImpl>, String> {
+    match cogs::path_string_to_vec(path)?.pop() {
+        Some(source_path) => db::open_source(
+            &source_path,
+            Some(&db::DBLoadControl::All),
+            None,
+            &Default::default(),
+        ),
+        None => Err(format!("No source found at {}\n", path)),
+    }
+}
+
+/// Formats a line describing a problem or repair, prefixed by the product, version, and
+/// flavor it applies to where those are known
+fn format_entry(
+    product: &Option<String>,
+    version: &Option<String>,
+    flavor: &Option<String>,
+    message: &str,
+) -> String {
+    let mut entry: Vec<&str> = vec![];
+    entry.extend(product.as_deref());
+    entry.extend(version.as_deref());
+    let flavor = flavor.as_ref().map(|x| format!("({})", x));
+    entry.extend(flavor.as_deref());
+    if entry.is_empty() {
+        message.to_string()
+    } else {
+        format!("{}: {}", entry.join(" "), message)
+    }
+}
+
+/// Formats the results of checking the database as human readable text
+fn format_checks(checks: &[db::SourceCheck], errors: usize, warnings: usize) -> String {
+    let mut output = String::new();
+    for check in checks.iter().filter(|x| !x.problems.is_empty()) {
+        output.push_str(&format!(
+            "{} ({})\n",
+            check.source,
+            check.location.to_str().unwrap_or("")
+        ));
+        for problem in check.problems.iter() {
+            let severity = match problem.severity {
+                db::Severity::Error => "error",
+                db::Severity::Warning => "warning",
+            };
+            output.push_str(&format!(
+                "  {}: {}\n",
+                severity,
+                format_entry(
+                    &problem.product,
+                    &problem.version,
+                    &problem.flavor,
+                    &problem.message
+                )
+            ));
+        }
+    }
+    output.push_str(&format!(
+        "Checked {} sources, found {} errors and {} warnings\n",
+        checks.len(),
+        errors,
+        warnings
+    ));
+    output
+}
+
+/// Formats the audit logs of the sources as human readable text, one line per change
+fn format_history(histories: &[db::SourceHistory]) -> String {
+    let mut output = String::new();
+    for history in histories.iter().filter(|x| !x.entries.is_empty()) {
+        output.push_str(&format!(
+            "{} ({})\n",
+            history.source,
+            history.location.to_str().unwrap_or("")
+        ));
+        for entry in history.entries.iter() {
+            let mut version = format!("{} {}", entry.product, entry.version);
+            if !entry.flavor.is_empty() {
+                version.push_str(&format!(" ({})", entry.flavor));
+            }
+            if !entry.qualifiers.is_empty() {
+                version.push_str(&format!(" [{}]", entry.qualifiers));
+            }
+            let change = match (entry.operation, entry.tag.as_ref()) {
+                (db::Operation::Tag, Some(tag)) => format!("tag {} on {}", tag, version),
+                (operation, _) => format!("{} {}", operation, version),
+            };
+            output.push_str(&format!(
+                "  {} {}@{}: {}\n",
+                entry.time, entry.user, entry.host, change
+            ));
+        }
+    }
+    let entries: usize = histories.iter().map(|x| x.entries.len()).sum();
+    output.push_str(&format!(
+        "Found {} changes in {} sources\n",
+        entries,
+        histories.len()
+    ));
+    output
+}
@@ -10,8 +10,10 @@
  **/
 use crate::argparse;
 use crate::cogs;
+use crate::config;
 use crate::db;
 use crate::db::DBBuilderTrait;
+use crate::git;
 use crate::logger;
 use std::path::PathBuf;
 
@@ -21,13 +23,20 @@ use std::path::PathBuf;
  * product. It may optionally be supplied with a tag. If there is only one writable database source
  * the command will write to that source. If more than one sources are found, the source
  * argument must be supplied. The source argument specifies what database backend the declared
- * product should be written to. Depending on the backend, the ident argument may or mar not be
- * optional. Currently a posix backend does not require it, but a JSON backend does. An additional
+ * product should be written to. The ident argument is optional. A posix backend does not require
+ * one, and a JSON backend generates one from the product directory and table when it is not
+ * given, which is reported back so it can be used to refer to the declared product. An additional
  * flag specifies if the path to the product to be declared is relative or not. If this flag is not
  * set declare will turn whatever path given into an absolute path. If the path is relative, it
  * should be relative to the directory containing the database source. For a posix backend, this
  * would be the directory containing ups_db, if is is JSON it would be the directory containing the
- * JSON file.
+ * JSON file. The overlay flag declares the product into the overlay instead, a source looked up
+ * before all the others, without changing any of them. The table file is read from the ups
+ * directory of the product unless another file or ups directory is given, and a product without a
+ * table file is declared with an environment holding only its PRODUCT_DIR. With the git flag the
+ * version and ident default to the tag and hash of the commit checked out in the product directory.
+ * The dependencies of the table are resolved against the database before declaring, and those
+ * which are not declared are warned about, or refuse the declare if the strict flag is set.
  *
  * * sub_args - Arguments matched from the command line to the given sub command
  * * _main_args - Arguments matched from the command line to the main reups executable,
@@ -58,7 +67,14 @@ impl<'a> DeclareCommandImpl<'a> {
     }
 
     fn run(&mut self) -> Result<(), String> {
-        let mut db = db::DBBuilder::from_args(self.sub_args).build()?;
+        let overlay = self.sub_args.is_present("overlay");
+        let mut builder =
+            db::DBBuilder::from_args(self.sub_args).set_load_control(db::DBLoadControl::Lazy);
+        if overlay {
+            // The overlay is declared into even when user sources are not loaded
+            builder = builder.set_overlay(Some(cogs::get_overlay_path()));
+        }
+        let mut db = builder.build()?;
         // see if the user wants to specify product path relative to db location
         let relative = self.sub_args.is_present("relative");
         let prod_path_string = self.sub_args.value_of("path").unwrap();
@@ -90,33 +106,90 @@ impl<'a> DeclareCommandImpl<'a> {
             exit_with_message!("The supplied path to product does not exists");
         }
 
-        // safe to unwrap, because they are required in the argument parsing
+        // safe to unwrap, because it is required in the argument parsing
         let product = self.sub_args.value_of("product").unwrap();
-        let version = self.sub_args.value_of("version").unwrap();
 
+        // The version and ident fall back to the git commit checked out in the product directory
+        let git_head = if self.sub_args.is_present("git") {
+            Some(git::read_git_head(&prod_path)?)
+        } else {
+            None
+        };
+        let version_string = match (self.sub_args.value_of("version"), git_head.as_ref()) {
+            (Some(version), _) => version.to_string(),
+            (None, Some(head)) => head.version(),
+            // version is required unless git is set
+            (None, None) => unreachable!(),
+        };
+        let version = version_string.as_str();
+        let ident = self
+            .sub_args
+            .value_of("ident")
+            .or(git_head.as_ref().map(|x| x.sha.as_str()));
+        if let Some(head) = git_head.as_ref() {
+            crate::info!("Declaring version {} from git commit {}", version, head.sha);
+        }
+
+        let config = config::get_config()?;
         let tag = self.sub_args.value_of("tag");
-        let source = self.sub_args.value_of("source");
-
-        let ident = self.sub_args.value_of("ident");
-        let flavor = Some(cogs::SYSTEM_OS);
-        // add the path to the table file
-        let mut table_path = prod_path.clone();
-        table_path.push("ups");
-        if !table_path.exists() {
-            exit_with_message!(format!(
+        // fall back to the configured source if none was given on the command line
+        let source = self
+            .sub_args
+            .value_of("source")
+            .or(config.declare_source.as_deref());
+
+        let qualifiers = self.sub_args.value_of("qualifiers");
+        // the flavor is copied, as db is borrowed mutably when declaring
+        let flavor_string = db.get_flavor().to_string();
+        let flavor = Some(flavor_string.as_str());
+
+        // Find the table file, which is named for the product in the ups directory unless one is
+        // given. A product without a table file is declared without a table
+        let ups_dir = self.sub_args.value_of("ups-dir");
+        let ups_path = prod_path.join(ups_dir.unwrap_or("ups"));
+        if ups_dir.is_some() && !ups_path.is_dir() {
+            return Err(format!(
                 "No ups directory found at {}",
-                table_path.to_str().expect("Unwrapping table path")
-            ));
-        }
-        table_path.push(format!("{}.table", product));
-        if !prod_path.exists() {
-            exit_with_message!(format!(
-                "Cannot find table file {}",
-                table_path.to_str().expect("Unwrapping full table bath")
+                ups_path.to_str().expect("Unwrapping ups path")
             ));
         }
-        let table =
-            db::table::Table::from_file(product.to_string(), table_path, prod_path.clone()).ok();
+        let table_arg = self.sub_args.value_of("table");
+        let table_path = match table_arg {
+            Some(table_path) => PathBuf::from(table_path)
+                .canonicalize()
+                .map_err(|e| format!("Cannot find table file {}: {}", table_path, e))?,
+            None => ups_path.join(format!("{}.table", product)),
+        };
+        let table_file_string;
+        let (table, table_file) = if table_path.exists() {
+            let table = db::table::Table::from_file(
+                product.to_string(),
+                table_path.clone(),
+                prod_path.clone(),
+            )
+            .map_err(|e| {
+                format!(
+                    "Problem reading table file {}: {}",
+                    table_path.to_str().expect("Unwrapping full table path"),
+                    e
+                )
+            })?;
+            // A table given on the command line is recorded, as it is not in the ups directory
+            table_file_string = table_path.to_str().unwrap().to_string();
+            let table_file = table_arg.map(|_| table_file_string.as_str());
+            (table, table_file)
+        } else {
+            crate::info!(
+                "No table file found at {}, declaring {} without a table",
+                table_path.to_str().expect("Unwrapping full table path"),
+                product
+            );
+            let table =
+                db::table::Table::empty(product.to_string(), prod_path.canonicalize().unwrap());
+            (table, Some("none"))
+        };
+
+        check_dependencies(&db, &table, self.sub_args.is_present("strict"))?;
 
         let prod_dir = if relative {
             PathBuf::from(prod_path_string)
@@ -131,11 +204,20 @@ impl<'a> DeclareCommandImpl<'a> {
             tag,
             ident,
             flavor,
-            table,
+            qualifiers,
+            table: Some(table),
             relative: self.sub_args.is_present("relative"),
+            ups_dir,
+            table_file,
         };
 
-        let result = db.declare(vec![input], source);
+        // An identity generated by the source is reported, as the user did not choose it
+        let had_identity = db.get_identity_from_version(product, version).is_some();
+        let result = if overlay {
+            db.declare_overlay(vec![input])
+        } else {
+            db.declare(vec![input], source)
+        };
         use db::DeclareResults::*;
         match result {
             NoSource => {
@@ -152,8 +234,94 @@ impl<'a> DeclareCommandImpl<'a> {
             }
             Success(name) => {
                 crate::info!("Wrote declared product {} to source {}", product, name);
+                if ident.is_none() && !had_identity {
+                    if let Some(id) = db.get_identity_from_version(product, version) {
+                        crate::warn!(
+                            "No identity given, declared product {} version {} with identity {}",
+                            product,
+                            version,
+                            id
+                        );
+                    }
+                }
             }
         }
         Ok(())
     }
 }
+
+/// Resolves the dependencies of a table being declared against the database, finding the source
+/// each one is declared in. A required dependency which is not declared in any source, or whose
+/// exact version is not, is warned about, or is an error if strict is set, as the product could
+/// not be setup. The source of every dependency is then shown along with the problem, otherwise
+/// the sources are only logged.
+fn check_dependencies(db: &db::DB, table: &db::table::Table, strict: bool) -> Result<(), String> {
+    let mut dependencies = vec![];
+    for (deps, exact) in [
+        (table.exact.as_ref(), true),
+        (table.inexact.as_ref(), false),
+    ]
+    .iter()
+    {
+        if let Some(deps) = deps {
+            for (dependency_map, optional) in
+                [(&deps.required, false), (&deps.optional, true)].iter()
+            {
+                for (product, version) in dependency_map.iter() {
+                    let version = if *exact && !version.is_empty() {
+                        Some(version.as_str())
+                    } else {
+                        None
+                    };
+                    dependencies.push((product.as_str(), version, *optional));
+                }
+            }
+        }
+    }
+    dependencies.sort();
+    dependencies.dedup();
+
+    let mut missing = vec![];
+    let mut resolved = vec![];
+    for (product, version, optional) in dependencies {
+        let mut description = match version {
+            Some(version) => format!("{} {}", product, version),
+            None => product.to_string(),
+        };
+        if optional {
+            description.push_str(" (optional)");
+        }
+        match db.get_source_of_product(product, version) {
+            Some(source) => {
+                resolved.push(format!("{} is declared in source {}", description, source));
+            }
+            None => {
+                if !optional {
+                    missing.push(format!(
+                        "Dependency {} of {} is not declared in any source",
+                        description, table.name
+                    ));
+                }
+                resolved.push(format!("{} is not declared in any source", description));
+            }
+        }
+    }
+    if missing.is_empty() {
+        for line in resolved.iter() {
+            crate::info!("Dependency {}", line);
+        }
+        return Ok(());
+    }
+    let message = format!(
+        "{}\nThe dependencies of {} resolve to:\n  {}",
+        missing.join("\n"),
+        table.name,
+        resolved.join("\n  ")
+    );
+    if strict {
+        Err(message)
+    } else {
+        crate::warn!("{}", message);
+        Ok(())
+    }
+}
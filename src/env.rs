@@ -16,11 +16,18 @@
  *
  **/
 use crate::argparse;
+use crate::config;
 use crate::logger;
+use crate::project;
+use crate::setup;
+use fnv::FnvHashMap;
+use fs2::FileExt;
 use preferences;
 use preferences::Preferences;
 use std::env as stdEnv;
-use std::io::{stdin, Write};
+use std::fs;
+use std::io::{stdin, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 
 // This is the information used to differentiate this application to the preferences crate and is
 // used to determine what path the settings will be saved to.
@@ -33,6 +40,202 @@ const APP_INFO: preferences::AppInfo = preferences::AppInfo {
 // saved in
 const PREF_KEY: &str = "saved/environments";
 
+// Environment variable which, if set, points to a file to use as the env store
+const STORE_ENV_VAR: &str = "REUPS_ENV_STORE";
+
+// Name of a project local env store. If a file with this name is found in the current directory,
+// or any of its parents, it is used in place of the per user store
+const LOCAL_STORE_NAME: &str = ".reups_envs.json";
+
+// Environment variable used to record the stack of project directories that have been entered,
+// along with the values each one replaced, so they can be restored on leave
+const DIR_STACK_VAR: &str = "REUPS_DIR_STACK";
+
+type SavedEnvs = preferences::PreferencesMap<Vec<String>>;
+
+/**
+ * A project directory that has been entered, along with the values of the environment variables
+ * before it was entered. A value of None indicates the variable was not set.
+ **/
+struct DirFrame {
+    dir: PathBuf,
+    saved: Vec<(String, Option<String>)>,
+}
+
+/// Percent encodes a string such that it contains no whitespace, shell glob, or stack separator
+/// characters, and can safely be exported and evaluated by a shell
+fn encode_field(input: &str) -> String {
+    let mut output = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'_' | b'.' | b'-' | b':' => {
+                output.push(byte as char)
+            }
+            _ => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    output
+}
+
+/// Reverses the encoding done with encode_field
+fn decode_field(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(value) = u8::from_str_radix(&input[i + 1..i + 3], 16) {
+                output.push(value);
+                i += 3;
+                continue;
+            }
+        }
+        output.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&output).to_string()
+}
+
+/**
+ * Serializes the directory stack into a string suitable for storing in an environment variable.
+ * Frames are separated by a pipe character, and the fields of a frame by a semicolon. The first
+ * field is the directory, the rest are NAME=value pairs, or just NAME if a variable was unset.
+ **/
+fn encode_stack(stack: &[DirFrame]) -> String {
+    let frames: Vec<String> = stack
+        .iter()
+        .map(|frame| {
+            let mut fields = vec![encode_field(&frame.dir.to_string_lossy())];
+            for (name, value) in frame.saved.iter() {
+                match value {
+                    Some(v) => fields.push(format!("{}={}", name, encode_field(v))),
+                    None => fields.push(name.clone()),
+                }
+            }
+            fields.join(";")
+        })
+        .collect();
+    frames.join("|")
+}
+
+/// Reads the directory stack back out of a string created with encode_stack
+fn decode_stack(input: &str) -> Vec<DirFrame> {
+    input
+        .split('|')
+        .filter(|frame| !frame.is_empty())
+        .map(|frame| {
+            let mut fields = frame.split(';');
+            let dir = PathBuf::from(decode_field(fields.next().unwrap_or("")));
+            let saved = fields
+                .map(|field| match field.find('=') {
+                    Some(i) => (field[..i].to_string(), Some(decode_field(&field[i + 1..]))),
+                    None => (field.to_string(), None),
+                })
+                .collect();
+            DirFrame { dir, saved }
+        })
+        .collect()
+}
+
+/// Quotes a value so that it is treated as a single word when evaluated by a shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace("'", "'\\''"))
+}
+
+/**
+ * Describes where saved environments are read from and written to. By default environments are
+ * stored in the users preferences directory, but a store can also be an explicit file, such as
+ * one placed on a network filesystem so that a team may share saved environments.
+ **/
+enum EnvStore {
+    User,
+    File(PathBuf),
+}
+
+impl EnvStore {
+    /**
+     * Determines which env store should be used. The REUPS_ENV_STORE environment variable takes
+     * precedence, followed by a project local store found in the current directory or one of its
+     * parents, and finally the per user store.
+     **/
+    fn locate() -> EnvStore {
+        if let Ok(path) = stdEnv::var(STORE_ENV_VAR) {
+            if !path.is_empty() {
+                return EnvStore::File(PathBuf::from(path));
+            }
+        }
+        if let Ok(mut dir) = stdEnv::current_dir() {
+            loop {
+                let candidate = dir.join(LOCAL_STORE_NAME);
+                if candidate.is_file() {
+                    return EnvStore::File(candidate);
+                }
+                if !dir.pop() {
+                    break;
+                }
+            }
+        }
+        EnvStore::User
+    }
+
+    /**
+     * Loads the saved environments from the store. Returns None if the store does not exist yet,
+     * and an error if it exists but could not be read.
+     **/
+    fn load(&self) -> Result<Option<SavedEnvs>, String> {
+        let result = match self {
+            EnvStore::User => SavedEnvs::load(&APP_INFO, PREF_KEY),
+            EnvStore::File(path) => {
+                let mut file = match fs::File::open(path) {
+                    Ok(f) => f,
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(format!("Problem opening env store {:?}: {}", path, e)),
+                };
+                // Take a shared lock so a store on a shared filesystem is not read mid write
+                file.lock_shared()
+                    .map_err(|e| format!("Problem locking env store {:?}: {}", path, e))?;
+                let result = SavedEnvs::load_from(&mut file);
+                let _ = file.unlock();
+                result
+            }
+        };
+        match result {
+            Ok(envs) => Ok(Some(envs)),
+            Err(preferences::PreferencesError::Io(ref e)) if e.kind() == ErrorKind::NotFound => {
+                Ok(None)
+            }
+            Err(e) => Err(format!("Problem reading env store: {}", e)),
+        }
+    }
+
+    /// Writes the supplied environments out to the store, creating it if needed
+    fn save(&self, envs: &SavedEnvs) -> Result<(), String> {
+        match self {
+            EnvStore::User => envs
+                .save(&APP_INFO, PREF_KEY)
+                .map_err(|e| format!("Problem saving env store: {}", e)),
+            EnvStore::File(path) => {
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)
+                    .map_err(|e| format!("Problem opening env store {:?}: {}", path, e))?;
+                // Only truncate once the exclusive lock is held, so other writers are not
+                // clobbered
+                file.lock_exclusive()
+                    .map_err(|e| format!("Problem locking env store {:?}: {}", path, e))?;
+                let result = file
+                    .set_len(0)
+                    .map_err(|e| format!("{}", e))
+                    .and_then(|_| envs.save_to(&mut file).map_err(|e| format!("{}", e)));
+                let _ = file.unlock();
+                result.map_err(|e| format!("Problem saving env store {:?}: {}", path, e))
+            }
+        }
+    }
+}
+
 /**
  * This is the main entry point for the env sub command. This command is used to save and restore
  * the (r)eups managed environment that is setup in the current shell. This function has different
@@ -66,7 +269,8 @@ struct EnvCommandImpl<'a, W: Write> {
     _main_args: &'a argparse::ArgMatches<'a>,
     current_commands: Vec<String>,
     name: String,
-    saved_envs: preferences::PreferencesMap<Vec<String>>,
+    store: EnvStore,
+    saved_envs: SavedEnvs,
     writer: &'a mut W,
 }
 
@@ -82,8 +286,18 @@ impl<'a, W: Write> EnvCommandImpl<'a, W> {
         _main_args: &'a argparse::ArgMatches<'a>,
         writer: &'a mut W,
     ) -> EnvCommandImpl<'a, W> {
+        // Actions which manage project directories produce output that is evaluated by the shell,
+        // so log messages for those must not go to standard out
+        let project_action = matches!(
+            sub_args.value_of("command"),
+            Some("enter") | Some("leave") | Some("hook")
+        );
         // make a logger object
-        logger::build_logger(sub_args, std::io::stdout());
+        if project_action {
+            logger::build_logger(sub_args, std::io::stderr());
+        } else {
+            logger::build_logger(sub_args, std::io::stdout());
+        }
         // Get the environment variable
         let current_commands = match stdEnv::var("REUPS_HISTORY") {
             Ok(existing) => existing.split("|").map(|x| String::from(x)).collect(),
@@ -99,14 +313,38 @@ impl<'a, W: Write> EnvCommandImpl<'a, W> {
             }
         };
 
-        // Load in an existing save environment
-        let saved_envs = preferences::PreferencesMap::<Vec<String>>::load(&APP_INFO, PREF_KEY);
-        // Check that there was an existing environment, otherwise create one.
-        let saved_envs = {
-            if saved_envs.is_ok() {
+        // Load in an existing save environment, project actions do not make use of the store
+        let store = EnvStore::locate();
+        let saved_envs = if project_action {
+            Some(SavedEnvs::new())
+        } else {
+            match store.load() {
+                Ok(x) => x,
+                Err(msg) => {
+                    exit_with_message!(msg);
+                }
+            }
+        };
+        // Check that there was an existing environment, otherwise create one. Saving always
+        // creates a store, as does the yes flag or its configuration option, so that non
+        // interactive use never blocks.
+        let create_store = sub_args.is_present("yes")
+            || match config::get_config() {
+                Ok(config) => config.create_env_store == Some(true),
+                Err(msg) => {
+                    exit_with_message!(msg);
+                }
+            };
+        let saved_envs = match saved_envs {
+            Some(envs) => {
                 crate::debug!("saved_envs loaded existing env");
-                saved_envs.unwrap()
-            } else {
+                envs
+            }
+            None if sub_args.value_of("command") == Some("save") || create_store => {
+                crate::info!("No existing env store found, creating a new one");
+                SavedEnvs::new()
+            }
+            None => {
                 // there is no existing preferences
                 crate::debug!("Existing env was not loaded, create and use new env store");
                 crate::warn!("No existing env store could be found create a new one? (y/N)");
@@ -122,7 +360,7 @@ impl<'a, W: Write> EnvCommandImpl<'a, W> {
                 }
                 if s == "y" || s == "Y" {
                     crate::warn!("Creating new env store");
-                    preferences::PreferencesMap::<Vec<String>>::new()
+                    SavedEnvs::new()
                 } else {
                     exit_with_message!("No env store found or created, exiting");
                 }
@@ -135,6 +373,7 @@ impl<'a, W: Write> EnvCommandImpl<'a, W> {
             _main_args,
             current_commands,
             name,
+            store,
             saved_envs: saved_envs,
             writer,
         }
@@ -150,6 +389,9 @@ impl<'a, W: Write> EnvCommandImpl<'a, W> {
             "restore" => self.run_restore(),
             "delete" => self.run_delete(),
             "list" => self.run_list(),
+            "enter" => self.run_enter(),
+            "leave" => self.run_leave(),
+            "hook" => self.run_hook(),
             _ => (),
         }
     }
@@ -159,8 +401,9 @@ impl<'a, W: Write> EnvCommandImpl<'a, W> {
     fn run_save(&mut self) {
         self.saved_envs
             .insert(self.name.clone(), self.current_commands.clone());
-        let save_result = self.saved_envs.save(&APP_INFO, PREF_KEY);
-        save_result.expect("There was a problem saving the current env");
+        if let Err(msg) = self.store.save(&self.saved_envs) {
+            exit_with_message!(msg);
+        }
     }
 
     /** Restores a given environment. This action is most likely to be activated by the rrestore
@@ -210,9 +453,11 @@ impl<'a, W: Write> EnvCommandImpl<'a, W> {
             exit_with_message!("Cannot delete default save");
         }
         self.saved_envs.remove(&self.name);
-        let save_result = self.saved_envs.save(&APP_INFO, PREF_KEY);
-        if !save_result.is_ok() {
-            exit_with_message!("There was a problem deleting the environment");
+        if let Err(msg) = self.store.save(&self.saved_envs) {
+            exit_with_message!(format!(
+                "There was a problem deleting the environment: {}",
+                msg
+            ));
         }
     }
 
@@ -225,4 +470,220 @@ impl<'a, W: Write> EnvCommandImpl<'a, W> {
             crate::info!("{:?}", v);
         }
     }
+
+    /** Sets up the environment described by a project file. The project file is found by
+     * searching the directory supplied as the name argument (or the current directory if none
+     * is given) and all of its parents. This should be used though the renter shell function
+     * supplied by reups prep, as the output must be evaluated by the shell.
+     **/
+    fn run_enter(&mut self) {
+        let start = if self.sub_args.is_present("name") {
+            PathBuf::from(&self.name)
+        } else {
+            stdEnv::current_dir().expect("Problem determining current directory")
+        };
+        let project_file = match project::find_project_file(&start) {
+            Some(x) => x,
+            None => {
+                exit_with_message!(format!(
+                    "No {} found in {:?} or any of its parents",
+                    project::PROJECT_FILE_NAME,
+                    start
+                ));
+            }
+        };
+        let mut stack = self.current_stack();
+        let mut touched = vec![];
+        let already_entered = stack
+            .last()
+            .is_some_and(|frame| Some(frame.dir.as_path()) == project_file.parent());
+        if !already_entered {
+            self.enter_project(&project_file, &mut stack, &mut touched);
+        }
+        self.write_changes(touched, &stack);
+    }
+
+    /** Restores the environment that was active before the most recently entered project
+     **/
+    fn run_leave(&mut self) {
+        let mut stack = self.current_stack();
+        let mut touched = vec![];
+        if stack.is_empty() {
+            crate::warn!("No project environment has been entered");
+        } else {
+            self.leave_project(&mut stack, &mut touched);
+        }
+        self.write_changes(touched, &stack);
+    }
+
+    /** Enters or leaves project environments such that they match the current directory. This
+     * is run each time the shell prompt is drawn if the hook is enabled with reups prep --hook.
+     * Any entered project the current directory is no longer inside of is left, and if the
+     * current directory is inside a project that is not yet entered, it is entered.
+     **/
+    fn run_hook(&mut self) {
+        let cwd = match stdEnv::current_dir() {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let mut stack = self.current_stack();
+        let mut touched = vec![];
+        while stack
+            .last()
+            .is_some_and(|frame| !cwd.starts_with(&frame.dir))
+        {
+            self.leave_project(&mut stack, &mut touched);
+        }
+        if let Some(project_file) = project::find_project_file(&cwd) {
+            let entered = stack
+                .iter()
+                .any(|frame| Some(frame.dir.as_path()) == project_file.parent());
+            if !entered {
+                self.enter_project(&project_file, &mut stack, &mut touched);
+            }
+        }
+        self.write_changes(touched, &stack);
+    }
+
+    /// Reads the stack of entered project directories from the environment
+    fn current_stack(&self) -> Vec<DirFrame> {
+        match stdEnv::var(DIR_STACK_VAR) {
+            Ok(existing) => decode_stack(&existing),
+            Err(_) => vec![],
+        }
+    }
+
+    /** Runs setup for each of the entries in a project file. The resulting variables are applied
+     * to the environment of this process, so that each setup sees the ones before it, and the
+     * names of all variables that changed are added to touched. A new frame recording the
+     * previous values is pushed onto the stack.
+     **/
+    fn enter_project(
+        &self,
+        project_file: &Path,
+        stack: &mut Vec<DirFrame>,
+        touched: &mut Vec<String>,
+    ) {
+        let project = match project::ProjectFile::from_file(project_file) {
+            Ok(x) => x,
+            Err(msg) => {
+                exit_with_message!(msg);
+            }
+        };
+        let root = project_file
+            .parent()
+            .expect("Problem finding directory containing project file")
+            .to_path_buf();
+        crate::info!("Entering project environment at {:?}", root);
+        let before: FnvHashMap<String, String> = stdEnv::vars().collect();
+        let mut changed: Vec<String> = vec![];
+        for args in project.setup_args(&root) {
+            let matches = argparse::build_cli().get_matches_from(self.setup_command_line(args));
+            let setup_args = matches
+                .subcommand_matches("setup")
+                .expect("Problem building setup arguments");
+            let mut env_vars = match setup::make_setup_env_map(setup_args, None) {
+                Ok(x) => x,
+                Err(msg) => {
+                    exit_with_message!(msg);
+                }
+            };
+            // Project setups are tied to the directory, and are not replayable commands, so they
+            // should not be recorded in the history
+            env_vars.remove("REUPS_HISTORY");
+            for (name, value) in env_vars {
+                if value == "UNSET" {
+                    stdEnv::remove_var(&name);
+                } else {
+                    // setup escapes spaces for the shell, undo that as it is being set directly
+                    stdEnv::set_var(&name, value.replace("\\ ", " "));
+                }
+                if !changed.contains(&name) {
+                    changed.push(name);
+                }
+            }
+        }
+        let saved = changed
+            .iter()
+            .map(|name| (name.clone(), before.get(name).cloned()))
+            .collect();
+        stack.push(DirFrame { dir: root, saved });
+        touched.extend(changed);
+    }
+
+    /// Pops the most recently entered project off the stack, restoring the values it replaced
+    fn leave_project(&self, stack: &mut Vec<DirFrame>, touched: &mut Vec<String>) {
+        if let Some(frame) = stack.pop() {
+            crate::info!("Leaving project environment at {:?}", frame.dir);
+            for (name, value) in frame.saved {
+                match value {
+                    Some(v) => stdEnv::set_var(&name, v),
+                    None => stdEnv::remove_var(&name),
+                }
+                touched.push(name);
+            }
+        }
+    }
+
+    /// Builds the full command line used to run setup with the supplied arguments, passing along
+    /// any database options given to this command
+    fn setup_command_line(&self, args: Vec<String>) -> Vec<String> {
+        let mut command = vec!["reups".to_string()];
+        for _ in 0..self.sub_args.occurrences_of("verbose") {
+            command.push("-v".to_string());
+        }
+        if self.sub_args.is_present("nouser") {
+            command.push("-U".to_string());
+        }
+        if self.sub_args.is_present("nosys") {
+            command.push("-S".to_string());
+        }
+        if let Some(database) = self.sub_args.value_of("database") {
+            command.push("-Z".to_string());
+            command.push(database.to_string());
+        }
+        if let Some(flavor) = self.sub_args.value_of("flavor") {
+            command.push("--flavor".to_string());
+            command.push(flavor.to_string());
+        }
+        if self.sub_args.is_present("no-cache") {
+            command.push("--no-cache".to_string());
+        }
+        command.push("setup".to_string());
+        command.extend(args);
+        command
+    }
+
+    /** Writes out a string which, when evaluated by the shell, sets the current values of all the
+     * touched variables and records the project stack. Nothing is written if nothing changed.
+     **/
+    fn write_changes(&mut self, mut touched: Vec<String>, stack: &[DirFrame]) {
+        if touched.is_empty() {
+            return;
+        }
+        touched.push(DIR_STACK_VAR.to_string());
+        if stack.is_empty() {
+            stdEnv::remove_var(DIR_STACK_VAR);
+        } else {
+            stdEnv::set_var(DIR_STACK_VAR, encode_stack(stack));
+        }
+        touched.sort();
+        touched.dedup();
+        let mut exports = vec![];
+        let mut unsets = vec![];
+        for name in touched {
+            match stdEnv::var(&name) {
+                Ok(value) => exports.push(format!("{}={}", name, shell_quote(&value))),
+                Err(_) => unsets.push(name),
+            }
+        }
+        let mut output = String::new();
+        if !exports.is_empty() {
+            output.push_str(&format!("export {};", exports.join(" ")));
+        }
+        if !unsets.is_empty() {
+            output.push_str(&format!(" unset {};", unsets.join(" ")));
+        }
+        let _ = self.writer.write(format!("{}\n", output.trim()).as_bytes());
+    }
 }
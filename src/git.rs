@@ -0,0 +1,175 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ The git module reads the commit checked out in a git repository directly from its .git directory,
+ without running git, so products can be declared with a version and identity taken from the commit
+ they were built from.
+*/
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The commit checked out in a repository, and the tag pointing at it if there is one
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitHead {
+    pub sha: String,
+    pub tag: Option<String>,
+}
+
+impl GitHead {
+    /// Returns the version described by the commit, as git describe --tags --always would for
+    /// a commit which is tagged or has no tags before it. This is the tag if there is one, and
+    /// otherwise the abbreviated hash of the commit
+    pub fn version(&self) -> String {
+        match self.tag.as_ref() {
+            Some(tag) => tag.clone(),
+            None => self.sha.chars().take(7).collect(),
+        }
+    }
+}
+
+/// Returns the git directory of the repository at dir, following the gitdir file of a worktree
+/// or submodule
+fn find_git_dir(dir: &Path) -> Result<PathBuf, String> {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return Ok(dot_git);
+    }
+    let contents = fs::read_to_string(&dot_git)
+        .map_err(|_| format!("No git repository found in {}", dir.to_str().unwrap()))?;
+    match contents.trim().strip_prefix("gitdir:") {
+        Some(path) => Ok(dir.join(path.trim())),
+        None => Err(format!("Could not read {}", dot_git.to_str().unwrap())),
+    }
+}
+
+/// Returns the name and hash of each ref in the packed-refs file, along with the commit each
+/// annotated tag points at when git recorded it
+fn packed_refs(common_dir: &Path) -> Vec<(String, String, Option<String>)> {
+    let contents = fs::read_to_string(common_dir.join("packed-refs")).unwrap_or_default();
+    let mut refs: Vec<(String, String, Option<String>)> = vec![];
+    for line in contents.lines() {
+        if let Some(peeled) = line.strip_prefix('^') {
+            if let Some(last) = refs.last_mut() {
+                last.2 = Some(peeled.trim().to_string());
+            }
+        } else if !line.starts_with('#') {
+            if let Some((sha, name)) = line.split_once(' ') {
+                refs.push((name.trim().to_string(), sha.to_string(), None));
+            }
+        }
+    }
+    refs
+}
+
+/// Returns the hash the named ref points at, following symbolic refs
+fn resolve_ref(git_dir: &Path, common_dir: &Path, name: &str) -> Result<String, String> {
+    let mut name = name.to_string();
+    // Symbolic refs may point at each other, but not endlessly
+    for _ in 0..10 {
+        let loose = [git_dir.join(&name), common_dir.join(&name)]
+            .iter()
+            .find_map(|x| fs::read_to_string(x).ok());
+        let target = match loose {
+            Some(contents) => contents.trim().to_string(),
+            None => match packed_refs(common_dir).into_iter().find(|x| x.0 == name) {
+                Some((_, sha, _)) => sha,
+                None => return Err(format!("Could not find git ref {}", name)),
+            },
+        };
+        match target.strip_prefix("ref:") {
+            Some(next) => name = next.trim().to_string(),
+            None => return Ok(target),
+        }
+    }
+    Err(format!("Could not resolve git ref {}", name))
+}
+
+/// Returns the commit an annotated tag object points at, if the object is stored loose. A hash
+/// which is not a loose tag object is returned unchanged, as it is most likely a commit
+fn peel_loose_tag(common_dir: &Path, sha: &str) -> String {
+    if sha.len() < 3 {
+        return sha.to_string();
+    }
+    let object = common_dir.join("objects").join(&sha[..2]).join(&sha[2..]);
+    let contents = fs::read(object)
+        .ok()
+        .and_then(|x| miniz_oxide::inflate::decompress_to_vec_zlib(&x).ok());
+    let contents = match contents {
+        Some(x) => String::from_utf8_lossy(&x).to_string(),
+        None => return sha.to_string(),
+    };
+    if !contents.starts_with("tag ") {
+        return sha.to_string();
+    }
+    contents
+        .split('\0')
+        .nth(1)
+        .and_then(|body| body.lines().next())
+        .and_then(|line| line.strip_prefix("object "))
+        .map(|x| x.trim().to_string())
+        .unwrap_or_else(|| sha.to_string())
+}
+
+/// Adds the name and hash of each loose ref below dir to refs, named relative to the common
+/// directory
+fn loose_refs(common_dir: &Path, dir: &Path, refs: &mut Vec<(String, String)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|x| x.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            loose_refs(common_dir, &path, refs);
+        } else if let (Ok(name), Ok(sha)) =
+            (path.strip_prefix(common_dir), fs::read_to_string(&path))
+        {
+            refs.push((name.to_str().unwrap().to_string(), sha.trim().to_string()));
+        }
+    }
+}
+
+/// Returns the name of a tag pointing at the commit, preferring the first in name order when
+/// there are several
+fn find_tag(common_dir: &Path, commit: &str) -> Option<String> {
+    let mut tags: Vec<String> = vec![];
+    let mut loose = vec![];
+    loose_refs(common_dir, &common_dir.join("refs").join("tags"), &mut loose);
+    for (name, sha) in loose {
+        if peel_loose_tag(common_dir, &sha) == commit {
+            tags.push(name);
+        }
+    }
+    for (name, sha, peeled) in packed_refs(common_dir) {
+        let target = peeled.unwrap_or_else(|| peel_loose_tag(common_dir, &sha));
+        if name.starts_with("refs/tags/") && target == commit {
+            tags.push(name);
+        }
+    }
+    tags.sort();
+    tags.into_iter()
+        .next()
+        .map(|x| x.trim_start_matches("refs/tags/").to_string())
+}
+
+/// Reads the commit checked out in the git repository at dir, and the tag pointing at it
+pub fn read_git_head(dir: &Path) -> Result<GitHead, String> {
+    let git_dir = find_git_dir(dir)?;
+    // Worktrees keep their refs in the common directory of the main repository
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(path) => git_dir.join(path.trim()),
+        Err(_) => git_dir.clone(),
+    };
+    let sha = resolve_ref(&git_dir, &common_dir, "HEAD")?;
+    if sha.len() != 40 || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "The git HEAD of {} is not a commit",
+            dir.to_str().unwrap()
+        ));
+    }
+    let tag = find_tag(&common_dir, &sha);
+    Ok(GitHead { sha, tag })
+}
@@ -0,0 +1,68 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+use clap::{App, Arg};
+use reups_lib as reups;
+use reups_lib::DBImpl;
+use std::path::PathBuf;
+
+/**
+ * This is a small application to covert json (r)eups database sources into
+ * a posix based database source, which can be read by eups.
+ *
+ * Arguments
+ * ---------
+ * source - Path to input json file
+ * dest - Path of the ups_db directory to write, files already in it are merged with
+ * tables - Optional flag to write the tables of the json source into the destination
+ *
+ **/
+fn main() {
+    let app = App::new("Json2Posix")
+        .author("Nate Lust")
+        .about("Writes a(n) (r)eups json db source out as a posix ups_db directory")
+        .version("0.0.1")
+        .arg(
+            Arg::with_name("source")
+                .help("Json source path")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("dest")
+                .help("Path of the ups_db directory to write output to")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("tables")
+                .help("Write table files into the output, instead of using those in the product directories")
+                .long("tables"),
+        );
+    let matches = app.get_matches();
+    let source =
+        match reups::JsonDBImpl::from_file(&PathBuf::from(matches.value_of("source").unwrap())) {
+            Ok(x) => x,
+            Err(e) => {
+                reups::exit_with_message!(format!("Problem reading json source, message {}", e));
+            }
+        };
+
+    let dest = PathBuf::from(matches.value_of("dest").unwrap());
+    if let Err(e) = std::fs::create_dir_all(&dest) {
+        reups::exit_with_message!(format!(
+            "Problem creating {}, message {}",
+            dest.display(),
+            e
+        ));
+    }
+    let posixdb = source.to_posix(&dest, matches.is_present("tables"));
+    for product in posixdb.get_products() {
+        if let Err(e) = posixdb.sync(product) {
+            eprintln!(
+                "There was a problem writing product {} out to posix, message {}",
+                product, e
+            );
+        }
+    }
+}
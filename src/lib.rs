@@ -19,21 +19,31 @@ use regex;
 mod cogs;
 mod argparse;
 mod completions;
+mod config;
 #[macro_use]
 mod db;
+mod db_command;
 mod declare;
 mod env;
+mod git;
 mod list;
 mod logger;
+mod overlay;
 mod prep;
+mod project;
 mod setup;
 pub use crate::argparse::*;
 pub use crate::cogs::*;
 pub use crate::completions::*;
+pub use crate::config::*;
 pub use crate::db::*;
+pub use crate::db_command::*;
 pub use crate::declare::*;
 pub use crate::env::*;
+pub use crate::git::*;
 pub use crate::list::*;
 pub use crate::logger::*;
+pub use crate::overlay::*;
 pub use crate::prep::*;
+pub use crate::project::*;
 pub use crate::setup::*;
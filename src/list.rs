@@ -243,11 +243,21 @@ impl<'a, W: Write> ListImpl<'a, W> {
                 version_to_tags_vec.sort_by(|tup1, tup2| tup1.0.cmp(&tup2.0));
                 // Iterate over and print results
                 for (ver, tags) in version_to_tags_vec {
+                    // If requested, show the flavors each version is declared with
+                    let flavors = if self.sub_args.is_present("flavors") {
+                        format!(
+                            "  ({})",
+                            self.db.get_flavors_from_version(product, ver).join(", ")
+                        )
+                    } else {
+                        String::new()
+                    };
                     self.output_string.push_str(
                         format!(
-                            "{:25}{:>25}{:10}{}]",
+                            "{:25}{:>25}{}{:10}{}]",
                             product,
                             ver,
+                            flavors,
                             "",
                             tags.iter()
                                 .fold(String::from("["), |acc, &x| {
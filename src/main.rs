@@ -55,16 +55,37 @@ Reups has a few global options that apply to any of the subcommands listed below
 
 * -v --verbose: Changes verbosity of messages printed to the command line, multiple instance of this switch increase verbosity
 * -Z --database: Colon-separated list of paths to database sources to use, adds additional sources
-used to populate the database.
-* -U --nouser: Disable loading default sources from user locations
+used to populate the database. Each path is a json file, a sqlite file, an http url of a json file,
+or a directory containing a ups_db directory. Sqlite sources only write the rows of the products declared, so they stay fast to
+declare into as they grow, and may be read while another process writes to them.
+Sources at http urls are read only. The last copy fetched of each is cached in the user cache
+directory (or the remote directory in REUPS_CACHE_DIR if it is set), and is only fetched again if the
+server reports it has changed. The cached copy is used when the server can not be reached. The
+product directories recorded in these sources should be absolute paths on a local or mounted file
+system. Urls may also be given in REUPS_PATH. Programs using reups_lib may register their own kinds
+of source, opened by url scheme or file extension, with register_scheme and register_extension,
+or add a source they opened themselves with the add_source method of the database builder, such
+as a MemoryDBImpl populated in code, which never touches disk.
+* -U --nouser: Disable loading default sources from user locations, and the overlay
 * -S --nosys: Disable loading default sources from system environment variables
+* --flavor: Flavor to use in place of the one configured or detected for this system. Only versions and
+tags declared with this flavor, or a generic flavor (NULL, generic, or empty), are used
+* --no-cache: Read posix sources without their index caches. Each posix source is normally read
+through a cache in the user cache directory (or REUPS_CACHE_DIR if it is set), and only the product
+directories whose modification times changed since they were cached are read off disk
+
+Setup and declare read posix sources lazily instead, only reading the directories of the products
+they look up, such as the dependencies of the product being setup, and not using the index caches.
 
 Details of the sub-commands are as follows:
 
 **Prep**
 
 This command is used to setup reups, and is responsible for assembling all the shell functionality such as
-providing the `rsetup`, `rrestore`, and `rsave` tools. This command is most commonly used as `eval $(reups prep).
+providing the `rsetup`, `rrestore`, `rsave`, `renter`, and `rleave` tools. This command is most commonly used as `eval $(reups prep).
+
+* --hook: Also install a shell hook which enters and leaves project environments automatically as the
+current directory changes (bash and zsh)
 
 **Completions**
 
@@ -93,7 +114,13 @@ The setup command (and thus rsetup) support the following options:
 * -k --keep: Keep any products already setup, dont replace them when reruning a new command
 * -t --tag: Use this tag when setting up products, multiple are allowed and are evaluated left to right
 * -E --inexact: Use only tags in deciding what to setup, ignore any versions declared in table files
+* -i --ident: Setup the version of the product with this identity instead of resolving it with tags
+* -q --qualifiers: Setup builds declared with these qualifiers (such as debug), products without a build
+with these qualifiers fall back to one declared without qualifiers. Builds with qualifiers are only used
+when requested
 * \<product\>: Positional argument which is the name of the product to setup, conflicts with relative option
+* \<version\>: Optional positional argument which is the version of the product to setup, instead of
+resolving it with tags, conflicts with ident
 
 **List**
 
@@ -104,6 +131,7 @@ Lists various properties about managed packages, and the current environment
 * --onlyTags: Only list products and tags on output. This is faster than listing products, tags, and versions, conflicts with only Versions
 * --onlyVers: Only list product and versions on output. This is faster than listing products, tags, and versions conflicts with onlyTags
 * -l --local Only list products that have been setup with the -r option. Conflicts with setup or a product as an argument.
+* --flavors: Show the flavors each version is declared with, conflicts with onlyTags and onlyVers
 * \<product>: Name of product to list
 
 **Env**
@@ -122,17 +150,56 @@ must be used to restore an environment, as shell environment variables are being
 `rsave` is exactly identical to typing out `reups env save` and is supplied as a convienence to the user.
 
 * -v --verbose Sets the level of verbosity, multiple occurances increases verbosity
-* \<action\>: Required, one of save, restore, delete, list
-* \<name\>: Optional, a name to use when saving or restoring
+* -y --yes Create a new env store without prompting if none exists, saving always creates one. The
+create_env_store configuration option does the same
+* \<action\>: Required, one of save, restore, delete, list, enter, leave, hook
+* \<name\>: Optional, a name to use when saving or restoring, or the directory to start searching from when entering
+
+Saved environments are kept in a per user store. This location can be overridden by setting the
+REUPS_ENV_STORE environment variable to the path of a file, or by placing a file named
+`.reups_envs.json` in a project directory (or any of its parents). This allows a team to share a
+store, for instance on a network filesystem.
+
+A directory may also describe the environment that should be active while working in it with a
+`.reups.toml` project file, listing products (and tags) to setup and local paths to setup like `rsetup -r`.
+`renter` (`reups env enter`) sets up the environment of the nearest project file, recording the values it
+replaced, and `rleave` (`reups env leave`) restores them. With `reups prep --hook` this happens
+automatically each time the prompt is drawn, using `reups env hook`.
+
+**Configuration**
+
+The defaults reups uses may be set in configuration files. A site wide `reups.toml` is read from the
+shared configuration directory (or REUPS_SITE_CONFIG), then the users `reups.toml` from their
+configuration directory (or REUPS_USER_CONFIG), and finally the `[config]` table of a `.reups.toml`
+project file. Later layers override earlier ones. The following options are supported:
+* tags: Tags setup uses when none are given with -t
+* source: A list of additional database sources, each with a path, and optionally a name, priority,
+and ident_regex used for that source in place of the global one
+* priority: A table of priorities for the sources reups finds by default, such as posix_user
+* system, user: Set to false to disable loading sources as with -S and -U
+* declare_source: The source declare writes to when --source is not given
+* flavor: Flavor to use in place of the one detected for this system
+* shell: Syntax of the output of setup, one of sh, csh, or fish
+* ident_regex: Regular expression used to extract identities from the versions of posix sources, if it
+contains a group named `ident` only the text matched by that group is used
+* create_env_store: Set to true to create an env store without prompting if none exists, as with env --yes
+* workers: Number of threads used to read the directories and files of each posix source, defaults to
+the available parallelism. A higher number can help on network file systems with high latency
+
+When the same version is found in more than one source, the source with the highest priority is used.
+By default sources given with -Z have a priority of 100, configured sources 0, the user sources -100, and
+the system sources from EUPS_PATH and REUPS_PATH -200. Sources with the same priority are ordered in that
+same sequence, and then by name.
 
 **Declare**
 Declare is used to add a product to a reups database such that it can be setup and managed by the reups
 system. A minimum decalare invocation takes a directory and a version and adds and entry for the product in
-the database. Depending on what backend is used, an identity for the product must also be supplied. A
+the database. An identity for the product may also be supplied. A
 version can be thought of as a human readable identifier that can be an arbitrary string, and an identity
 can be thought of as only needing to be machine readable. It is possible for these values to be the same,
 but this distinction allows for more flexibility in keeping track of products. Currently a posix backend to
-the reups database does not require an identity to be declared, but a JSON backedn does. A tag may also be
+the reups database does not require an identity to be declared, and a JSON backend generates one when it is
+not given, by hashing the product directory and table file, and reports it. A tag may also be
 specified when declarning a product, which can be helpful with associating multiple products together.
 As mentioned, reups can make use of many different database backends. If more than one writable backend
 source is found, the source to declare to must be specified in the declare command.
@@ -140,15 +207,81 @@ source is found, the source to declare to must be specified in the declare comma
 The declare options are as follows:
 
 * product: The name of the product to declare.
-* version: The version to associate with the product
+* version: The version to associate with the product, optional when --git is given
 * -r --root: Path to product directory to declare
 * -t --tag: Tag to add to product when declaring
 * --source: Name of source to declare product to, needed if there is more that one source that is
 * writable view all with reups list --sources
-* --ident: Identity to assign to product when declaring
+* --ident: Identity to assign to product when declaring, in place of one generated by a JSON backend
+* --qualifiers: Qualifiers distinguishing this build of the product from others of the same version and
+flavor, such as debug
 * --relative: Allows setting a relative path for the product path, otherwise reups will record it
 * as an absolute path. If the path is to be relative it is relative to the directory containing the
 * database source it is to be declared to.
+* --overlay: Declare the product into the overlay instead of a database source. The overlay is a
+json file in the temporary directory (or at REUPS_OVERLAY if it is set) which is looked up before
+every other source, so a local build can be tried on top of the shared stack without changing it.
+It is discarded with `reups overlay clear`
+* --table: Table file to declare the product with, instead of the one named for the product in its ups
+directory
+* --ups-dir: Directory within the product directory to find the table file in, ups if not given
+* --git: Read the version and identity from the git repository in the product directory. The version
+is the tag pointing at the checked out commit, or its abbreviated hash if it is not tagged, and the
+identity is its full hash. Either may still be given to override it
+
+* --strict: Refuse to declare a product whose table requires a product, or an exact version of one, which
+is not declared in any source, instead of warning about it
+
+A product with no table file is declared without a table, and its environment only sets its PRODUCT_DIR.
+The dependencies of the table are looked up before declaring. When one is missing, the source each
+dependency is declared in is shown with the warning or error, otherwise run with -v to show them
+
+**Db**
+
+The db command maintains the database sources themselves, with the following actions:
+
+* check: Check every source for broken entries, such as tags pointing at versions which are not
+declared, product directories or table files which do not exist, table files which can not be parsed,
+JSON sources whose Versions and Tables do not pair up, and dependencies on products which are not
+declared in any source. The command exits with a non-zero status if any errors are found
+  * --json: Write the problems found as JSON instead of text
+* fix: Repair common damage found in the sources. Tags pointing at versions which are not declared
+are removed, identities are created for versions of JSON sources declared without one, and entries
+of JSON sources whose Versions and Tables do not pair up are dropped. Sources which are not writable
+are not changed, and the command exits with a non-zero status if they needed repairs
+  * --dry-run: Print the repairs that would be made without making them
+  * --paths: Rewrite the product directories recorded as absolute paths, or as paths relative to the
+  directory containing the source, one of absolute or relative
+  * --source: Only repair the source with this name
+* convert: Copy every version and tag of one source into another, which may be of a different type.
+The destination must not exist unless merging, and entries it already contains are reported as
+conflicts and left unchanged, in which case the command exits with a non-zero status
+  * --from: The source to copy from, a json or sqlite file, or a directory containing a ups_db directory
+  * --to: The source to copy into, a json or sqlite file, or a directory containing a ups_db directory
+  * --merge: Add into an existing destination
+* diff: List the differences between two sources, which may be of different types, such as two
+snapshots of a stack. Products and versions added or removed going from the first source to the
+second are listed, along with flavors added or removed, tags which moved, and tables whose
+dependencies or environment actions differ
+  * source_a: The source to compare from, a json or sqlite file, or a directory containing a ups_db directory
+  * source_b: The source to compare to, a json or sqlite file, or a directory containing a ups_db directory
+* history: Show the audit log of each source, the declares and tags made to it oldest first, with the
+user, time, and host that made each. Tags can not be moved once declared, so each is recorded once, with
+the version it was pointed at. Declaring into a source appends to its log, which is the file
+.reups_log.jsonl in the ups_db directory of a posix source, or a hidden file named after a json or
+sqlite source beside it
+  * product: Only show the changes made to this product
+  * --json: Write the history as JSON instead of text
+* cache clear: Remove the index caches of all posix sources, so they are rebuilt the next time they
+are read. Products are read again whenever their directory or any of their files change, so this is
+only needed if a file is changed without changing its modification time
+
+**Overlay**
+
+The overlay command manages the products declared with `reups declare --overlay`, with the
+following actions:
+
+* clear: Discard the overlay and every product declared into it, so the other sources are used again
 **/
 
 extern crate reups_lib;
@@ -163,6 +296,7 @@ fn handle_result(res: Result<(), String>) {
             std::io::stderr()
                 .write(msg.as_bytes())
                 .expect("Error writing error message");
+            std::process::exit(1);
         }
     };
 }
@@ -172,8 +306,8 @@ fn main() {
 
     match args.subcommand() {
         ("setup", Some(m)) => handle_result(reups::setup_command(m, &args, &mut std::io::stdout())),
-        ("prep", Some(_)) => {
-            println!("{}", reups::build_prep_string());
+        ("prep", Some(m)) => {
+            println!("{}", reups::build_prep_string(m.is_present("hook")));
         }
         ("list", Some(m)) => handle_result(reups::list_command(m, &args, &mut std::io::stdout())),
         ("completions", Some(m)) => {
@@ -183,6 +317,10 @@ fn main() {
             reups::env_command(m, &args, &mut std::io::stdout());
         }
         ("declare", Some(m)) => handle_result(reups::declare_command(m, &args)),
+        ("db", Some(m)) => handle_result(reups::db_command(m, &args, &mut std::io::stdout())),
+        ("overlay", Some(m)) => {
+            handle_result(reups::overlay_command(m, &args, &mut std::io::stdout()))
+        }
         _ => println!("{}", args.usage()),
     }
 }
@@ -0,0 +1,50 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ The overlay module holds the overlay subcommand of reups, which manages the products declared
+ into the overlay with `reups declare --overlay`.
+*/
+use crate::argparse;
+use crate::cogs;
+use crate::logger;
+use std::io::Write;
+
+/**
+ * This is the entry-point for the overlay subcommand. The clear action discards the overlay, so
+ * the products declared into it are no longer found and the shared stack is used again.
+ *
+ * * sub_args - Arguments matched from the command line to the overlay sub command
+ * * _main_args - Arguments matched from the command line to the main reups executable
+ * * writer - Destination the output of the action is written to
+ **/
+pub fn overlay_command<W: Write>(
+    sub_args: &argparse::ArgMatches,
+    _main_args: &argparse::ArgMatches,
+    writer: &mut W,
+) -> Result<(), String> {
+    logger::build_logger(sub_args, std::io::stderr());
+    let overlay = cogs::get_overlay_path();
+    match sub_args.subcommand() {
+        ("clear", Some(_)) => {
+            let output = if overlay.exists() {
+                std::fs::remove_file(&overlay).map_err(|e| {
+                    format!(
+                        "Problem removing overlay {}: {}\n",
+                        overlay.to_str().unwrap(),
+                        e
+                    )
+                })?;
+                format!("Removed overlay {}\n", overlay.to_str().unwrap())
+            } else {
+                format!("No overlay found at {}\n", overlay.to_str().unwrap())
+            };
+            writer
+                .write_all(output.as_bytes())
+                .map_err(|e| format!("Problem writing overlay results: {}", e))
+        }
+        _ => Err(format!("{}\n", sub_args.usage())),
+    }
+}
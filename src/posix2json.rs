@@ -17,6 +17,7 @@ use std::path::PathBuf;
  * ---------
  * source - Path to input posix store
  * dest - Location to write output file
+ * ident-regex - Optional regular expression used to extract identities from versions
  *
  **/
 fn main() {
@@ -33,12 +34,25 @@ fn main() {
             Arg::with_name("dest")
                 .help("Path to write output")
                 .required(true),
+        )
+        .arg(
+            Arg::with_name("ident-regex")
+                .help("Regular expression used to extract identities from versions, if it contains a group named ident only that group is used")
+                .long("ident-regex")
+                .takes_value(true),
         );
     let matches = app.get_matches();
+    let ident_regex = match matches.value_of("ident-regex").map(regex::Regex::new) {
+        Some(Ok(x)) => Some(x),
+        Some(Err(e)) => {
+            reups::exit_with_message!(format!("Problem parsing ident regex, message {}", e));
+        }
+        None => None,
+    };
     let source = reups::PosixDBImpl::new(
         PathBuf::from(matches.value_of("source").unwrap()),
         Some(&reups::DBLoadControl::All),
-        None,
+        ident_regex,
     );
 
     let jsondb = source
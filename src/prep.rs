@@ -7,9 +7,11 @@
 ///the users shell. The string returned from this function adds various
 ///components (at this point only rsetup) to the users environment. The
 ///resulting string must be eval-ed by the user, most commonly done with
-///eval $(reups prep)
-pub fn build_prep_string() -> &'static str {
-    "rsetup() {
+///eval $(reups prep). If hook is true, a function that enters and leaves
+///project environments as the current directory changes is also added, and
+///registered to run each time the prompt is drawn.
+pub fn build_prep_string(hook: bool) -> String {
+    let mut prep = "rsetup() {
     local _args=\"$*\";
     if [[ $_args = *\"-h\"* ]] || [[ $_args = *\"--help\"* ]];
     then
@@ -36,5 +38,35 @@ eval $(reups env restore \"$@\");
 rsave() {
 reups env save \"$@\";
 };
+
+renter() {
+eval $(reups env enter \"$@\");
+};
+
+rleave() {
+eval $(reups env leave);
+};
 "
+    .to_string();
+    if hook {
+        prep.push_str(
+            "
+_reups_dir_hook() {
+eval $(reups env hook);
+};
+
+if [[ -n \"$ZSH_VERSION\" ]];
+then
+    if [[ -z \"${precmd_functions[(r)_reups_dir_hook]}\" ]];
+    then
+        precmd_functions+=(_reups_dir_hook);
+    fi;
+elif [[ \"$PROMPT_COMMAND\" != *_reups_dir_hook* ]];
+then
+    PROMPT_COMMAND=\"_reups_dir_hook;${PROMPT_COMMAND}\";
+fi;
+",
+        );
+    }
+    prep
 }
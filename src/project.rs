@@ -0,0 +1,142 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ * Copyright Nate Lust 2019*/
+
+/*!
+ A project file describes the (r)eups environment that should be active while working within a
+ directory. The file is named `.reups.toml` and is placed at the root of a project, for instance a
+ repository. It lists products to setup, tags to use when resolving those products, and local
+ products that should be setup from a path, like `rsetup -r`.
+
+ An example project file:
+
+ ```toml
+ # Tags to consider, evaluated left to right before current
+ tags = ["stable"]
+ # Use inexact versions from table files when setting up products
+ inexact = false
+
+ [[product]]
+ name = "fooA"
+
+ [[product]]
+ name = "fooB"
+ tags = ["testing"]
+
+ # A version may be given to setup instead of resolving one with tags
+ [[product]]
+ name = "fooC"
+ version = "v2"
+
+ # Paths are relative to the directory containing the project file
+ [[local]]
+ path = "."
+
+ # Configuration options for reups while working in this project, see the config module
+ [config]
+ declare_source = "project"
+ ```
+*/
+
+use crate::config::Config;
+use serde_derive::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the file which marks a directory as having a project environment
+pub const PROJECT_FILE_NAME: &str = ".reups.toml";
+
+/// A product that is to be setup from the database
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProjectProduct {
+    pub name: String,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub just: bool,
+}
+
+/// A product that is to be setup from a path on disk
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProjectLocal {
+    pub path: String,
+    #[serde(default)]
+    pub just: bool,
+}
+
+/// In memory representation of a `.reups.toml` file
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ProjectFile {
+    pub tags: Vec<String>,
+    pub inexact: bool,
+    pub product: Vec<ProjectProduct>,
+    pub local: Vec<ProjectLocal>,
+    pub config: Config,
+}
+
+/// Searches the supplied directory and all of its parents for a project file, returning the path
+/// to the first one found.
+pub fn find_project_file(start: &Path) -> Option<PathBuf> {
+    let mut dir = start.to_path_buf();
+    loop {
+        let candidate = dir.join(PROJECT_FILE_NAME);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+impl ProjectFile {
+    /// Reads and parses the project file located at the supplied path
+    pub fn from_file(path: &Path) -> Result<ProjectFile, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Problem reading project file {:?}: {}", path, e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Problem parsing project file {:?}: {}", path, e))
+    }
+
+    /// Turns the contents of the project file into the arguments that would be supplied to the
+    /// setup subcommand, one vector of arguments per product. Local paths are expanded relative
+    /// to root, which should be the directory containing the project file.
+    pub fn setup_args(&self, root: &Path) -> Vec<Vec<String>> {
+        let mut commands = vec![];
+        for product in self.product.iter() {
+            let mut args = vec![];
+            for tag in product.tags.iter().chain(self.tags.iter()) {
+                args.push("-t".to_string());
+                args.push(tag.clone());
+            }
+            if product.just {
+                args.push("-j".to_string());
+            }
+            if self.inexact {
+                args.push("-E".to_string());
+            }
+            args.push(product.name.clone());
+            if let Some(version) = product.version.as_ref() {
+                args.push(version.clone());
+            }
+            commands.push(args);
+        }
+        for local in self.local.iter() {
+            let mut args = vec![];
+            for tag in self.tags.iter() {
+                args.push("-t".to_string());
+                args.push(tag.clone());
+            }
+            if local.just {
+                args.push("-j".to_string());
+            }
+            args.push("-r".to_string());
+            args.push(root.join(&local.path).to_string_lossy().to_string());
+            commands.push(args);
+        }
+        commands
+    }
+}
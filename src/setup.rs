@@ -16,7 +16,7 @@ use std::io::Write;
 use std::path::PathBuf;
 
 use crate::argparse;
-use crate::cogs;
+use crate::config;
 use crate::db;
 use crate::db::DBBuilderTrait;
 use crate::logger;
@@ -60,10 +60,14 @@ pub fn setup_table(
     // add this product in to the environment map that is to be setup
     let mut setup_string_vec = vec![product_table.name.clone(), product_version.clone()];
 
-    // if there is no flavor use the system os as platform
+    // if there is no flavor use the configured flavor, or the system os as platform
     setup_string_vec.push("-f".to_string());
     if flavor.is_empty() {
-        setup_string_vec.push(cogs::SYSTEM_OS.to_string());
+        let default_flavor = match config::get_config() {
+            Ok(config) => config.flavor().to_string(),
+            Err(_) => crate::cogs::SYSTEM_OS.to_string(),
+        };
+        setup_string_vec.push(default_flavor);
     } else {
         setup_string_vec.push(flavor.clone());
     }
@@ -284,6 +288,24 @@ pub fn setup_command<W: Write>(
     writer: &mut W,
 ) -> Result<(), String> {
     let env_vars = make_setup_env_map(sub_args, None)?;
+    let shell = config::get_config()?.shell.clone();
+    let return_string = match shell.as_deref() {
+        None | Some("sh") => format_sh(env_vars),
+        Some("csh") => format_csh(env_vars),
+        Some("fish") => format_fish(env_vars),
+        Some(other) => {
+            return Err(format!(
+                "Unknown shell {} in config, must be one of sh, csh, or fish",
+                other
+            ));
+        }
+    };
+    let _ = writer.write(format!("{}\n", return_string).as_bytes());
+    Ok(())
+}
+
+/// Formats environment variables to be set and unset using the syntax of sh like shells
+fn format_sh(env_vars: FnvHashMap<String, String>) -> String {
     // Process all the environment variables into a string to return
     let mut return_string = String::from("export ");
     let mut unset_string = String::from("");
@@ -300,8 +322,35 @@ pub fn setup_command<W: Write>(
         return_string.push_str("; ");
         return_string.push_str(unset_string.as_str());
     }
-    let _ = writer.write(format!("{}\n", return_string).as_bytes());
-    Ok(())
+    return_string
+}
+
+/// Formats environment variables to be set and unset using the syntax of csh like shells
+fn format_csh(env_vars: FnvHashMap<String, String>) -> String {
+    let mut return_string = String::from("");
+    for (k, v) in env_vars {
+        match v.as_str() {
+            "UNSET" => return_string.push_str(&format!("unsetenv {}; ", k)),
+            _ => return_string.push_str(&format!("setenv {} {}; ", k, v)),
+        }
+    }
+    return_string
+}
+
+/// Formats environment variables to be set and unset using the syntax of the fish shell
+fn format_fish(env_vars: FnvHashMap<String, String>) -> String {
+    let mut return_string = String::from("");
+    for (k, v) in env_vars {
+        match v.as_str() {
+            "UNSET" => return_string.push_str(&format!("set -e {}; ", k)),
+            // Fish expects path like variables to be lists
+            _ if k.ends_with("PATH") => {
+                return_string.push_str(&format!("set -gx {} {}; ", k, v.replace(":", " ")))
+            }
+            _ => return_string.push_str(&format!("set -gx {} {}; ", k, v)),
+        }
+    }
+    return_string
 }
 
 pub fn make_setup_env_map(
@@ -315,7 +364,9 @@ pub fn make_setup_env_map(
     // if no db was passed in, create one from the sub_args
     let db = match db {
         Some(db) => db,
-        None => db::DBBuilder::from_args(sub_args).build()?,
+        None => db::DBBuilder::from_args(sub_args)
+            .set_load_control(db::DBLoadControl::Lazy)
+            .build()?,
     };
 
     // We process local arguments here to set the state that will be used to setup a product
@@ -330,6 +381,11 @@ pub fn make_setup_env_map(
         for t in tags_str.iter() {
             tags.push(t);
         }
+    } else {
+        // Use the configured default tags if none were given
+        for t in config::get_config()?.tags.iter() {
+            tags.push(t.as_str());
+        }
     }
     // Always put the current tag
     tags.push(current);
@@ -348,16 +404,35 @@ pub fn make_setup_env_map(
             if !db.has_product(&name.to_string()) {
                 exit_with_message!(format!("Cannot find product `{}` to setup", name));
             }
-            let local_table = db.get_table_from_tag(name, &tags);
-            let versions = db.get_versions_from_tag(&name.to_string(), &tags);
-            let mut version = String::from("");
-            match versions.first() {
-                Some(v) => {
-                    version = v.to_string();
+            if let Some(version) = sub_args.value_of("version") {
+                // An explicit version selects exactly that version, tags are not used
+                match db.get_table_from_version(name, version) {
+                    Some(table) => (Some(table), version.to_string()),
+                    None => {
+                        return Err(format!(
+                            "Cannot find version `{}` for product `{}`",
+                            version, name
+                        ));
+                    }
+                }
+            } else if let Some(ident) = sub_args.value_of("ident") {
+                // An identity selects exactly one version of the product, tags are not used
+                let version = match db.get_version_from_identity(name, ident) {
+                    Some(v) => v.to_string(),
+                    None => {
+                        return Err(format!(
+                            "Cannot find identity `{}` for product `{}`",
+                            ident, name
+                        ));
+                    }
+                };
+                (db.get_table_from_version(name, &version), version)
+            } else {
+                match db.get_version_table_from_tag(name, &tags) {
+                    Some((version, table)) => (Some(table), version.to_string()),
+                    None => (None, String::from("")),
                 }
-                None => (),
             }
-            (local_table, version)
         }
         (None, Some(path)) => {
             // specifying a directory of table file to setup manually implies that version type
@@ -399,11 +474,10 @@ pub fn make_setup_env_map(
         }
         // create a hashmap to hold all the environment variables to set
         let mut env_vars: FnvHashMap<String, String> = FnvHashMap::default();
-        let flavors = db.get_flavors_from_version(&table.name, &version);
-        let flavor = match flavors.last() {
-            Some(flav) => flav.to_string(),
-            None => String::from(""),
-        };
+        let flavor = db
+            .get_flavor_from_version(&table.name, &version)
+            .unwrap_or_else(|| db.get_flavor())
+            .to_string();
 
         let db_path = db.get_database_path_from_version(&table.name, &version);
 
@@ -429,27 +503,23 @@ pub fn make_setup_env_map(
                 // easiest, but it could be wrong and this code should be thought through more.
                 // FINDME
                 let mut largest_version = versions.iter().max().unwrap().clone().clone();
-                let node_table_option: Option<table::Table>;
-                if largest_version.as_str() != "" {
-                    node_table_option = db.get_table_from_version(&name, &largest_version);
+                let node_table_option = if largest_version.as_str() != "" {
+                    db.get_table_from_version(&name, &largest_version)
                 } else {
-                    node_table_option = db.get_table_from_tag(&name, &tags);
-                    let versions = db.get_versions_from_tag(&name, &tags);
-                    match versions.last() {
-                        Some(v) => {
-                            largest_version = v.to_string();
+                    match db.get_version_table_from_tag(&name, &tags) {
+                        Some((version, table)) => {
+                            largest_version = version.to_string();
+                            Some(table)
                         }
-                        None => (),
+                        None => None,
                     }
-                }
+                };
                 match (node_table_option, dependencies.is_optional(&name)) {
                     (Some(node_table), _) => {
-                        let flavors =
-                            db.get_flavors_from_version(&node_table.name, &largest_version);
-                        let flavor = match flavors.last() {
-                            Some(flav) => flav.to_string(),
-                            None => String::from(""),
-                        };
+                        let flavor = db
+                            .get_flavor_from_version(&node_table.name, &largest_version)
+                            .unwrap_or_else(|| db.get_flavor())
+                            .to_string();
                         let db_path =
                             db.get_database_path_from_version(&node_table.name, &largest_version);
                         setup_table(
@@ -0,0 +1,226 @@
+extern crate reups_lib;
+use reups::DBBuilderTrait;
+use reups_lib as reups;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[test]
+fn test_config_layers() {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push("resources/config");
+    let mut config = reups::Config::from_file(&root.join("site.toml")).unwrap();
+    assert_eq!(config.flavor(), "Linux64");
+    config.merge(reups::Config::from_file(&root.join("user.toml")).unwrap());
+
+    assert_eq!(config.tags, vec!["testing", "stable"]);
+    assert_eq!(config.declare_source, Some("json_user".to_string()));
+    assert_eq!(config.shell, Some("fish".to_string()));
+    assert_eq!(config.workers, Some(4));
+    assert_eq!(config.create_env_store, Some(true));
+    // Options not set in the user layer are kept from the site layer
+    assert_eq!(config.flavor(), "Linux64");
+    assert_eq!(config.ident_regex, Some("g[0-9a-f]+".to_string()));
+    // Sources accumulate, and priorities are overridden by later layers
+    let sources: Vec<&str> = config.source.iter().map(|x| x.path.as_str()).collect();
+    assert_eq!(
+        sources,
+        vec!["/software/site/reups_db.json", "/home/user/reups_db.json"]
+    );
+    assert_eq!(config.source[0].priority, 5);
+    assert_eq!(config.source[1].priority, 0);
+    assert_eq!(config.priority["posix_user"], 10);
+}
+
+/// Writes a copy of the json test source to the supplied path, with fooA v2 tagged current in
+/// place of v3 and located in its own product directory
+fn write_high_source(path: &Path, prod_dir: &Path) {
+    let json = resource("resources/json_db/json_db.json");
+    let mut db: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+    let packages = resource("resources/test_packages");
+    for version in db["Versions"].as_array_mut().unwrap() {
+        for key in &["PROD_DIR", "TABLE_FILE"] {
+            let rebased = version[key]
+                .as_str()
+                .unwrap()
+                .replace("../test_packages", packages.to_str().unwrap());
+            version[key] = serde_json::Value::from(rebased);
+        }
+        if version["PRODUCT"] == "fooA" && version["VERSION"] == "v2" {
+            version["PROD_DIR"] = serde_json::Value::from(prod_dir.to_str().unwrap());
+        }
+    }
+    for tag in db["Tags"].as_array_mut().unwrap() {
+        if tag["PRODUCT"] == "fooA" {
+            tag["VERSION"] = serde_json::Value::from("v2");
+        }
+    }
+    fs::write(path, serde_json::to_string(&db).unwrap()).unwrap();
+}
+
+fn resource(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+#[test]
+fn test_source_priority() {
+    let mut root = env::temp_dir();
+    root.push(format!("reups_priority_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let high_dir = root.join("fooA_high");
+    write_high_source(&root.join("high.json"), &high_dir);
+
+    let build = |low: i32, high: i32| {
+        reups::DBBuilder::new()
+            .add_eups_env(false)
+            .add_eups_user(false)
+            .add_reups_env(false)
+            .add_reups_user(false)
+            .set_overlay(None)
+            .add_named_path("low", resource("resources/json_db/json_db.json"), low)
+            .add_named_path("high", root.join("high.json"), high)
+            .set_flavor("Linux64")
+            .build()
+            .unwrap()
+    };
+
+    // The tag, version and table are all looked up in the highest priority source first
+    let db = build(1, 5);
+    let names: Vec<String> = db.get_db_sources().into_iter().map(|x| x.0).collect();
+    assert_eq!(names, vec!["high", "low"]);
+    assert_eq!(
+        db.get_versions_from_tag("fooA", &vec!["current"]),
+        vec!["v2", "v3"]
+    );
+    let (version, table) = db
+        .get_version_table_from_tag("fooA", &vec!["current"])
+        .unwrap();
+    assert_eq!(version, "v2");
+    assert_eq!(table.product_dir, high_dir);
+    assert_eq!(
+        db.get_table_from_version("fooA", "v2").unwrap().product_dir,
+        high_dir
+    );
+
+    // Swapping the priorities swaps which source wins
+    let db = build(5, 1);
+    let names: Vec<String> = db.get_db_sources().into_iter().map(|x| x.0).collect();
+    assert_eq!(names, vec!["low", "high"]);
+    assert_eq!(
+        db.get_versions_from_tag("fooA", &vec!["current"]),
+        vec!["v3", "v2"]
+    );
+    let (version, table) = db
+        .get_version_table_from_tag("fooA", &vec!["current"])
+        .unwrap();
+    assert_eq!(version, "v3");
+    assert_eq!(table.product_dir, resource("resources/test_packages/fooA"));
+    assert_ne!(
+        db.get_table_from_version("fooA", "v2").unwrap().product_dir,
+        high_dir
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_from_args() {
+    let mut root = env::temp_dir();
+    root.push(format!("reups_from_args_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let high_dir = root.join("fooA_high");
+    write_high_source(&root.join("high.json"), &high_dir);
+    let json = resource("resources/json_db/json_db.json");
+    let site = format!(
+        "system = false\nuser = false\nflavor = \"Linux64\"\n\n\
+         [[source]]\nname = \"low\"\npath = \"{}\"\npriority = 1\n\n\
+         [[source]]\nname = \"high\"\npath = \"{}\"\npriority = 5\n",
+        json.to_str().unwrap(),
+        root.join("high.json").to_str().unwrap()
+    );
+    fs::write(root.join("site.toml"), site).unwrap();
+    // This is the only test in this file to load the configuration
+    env::set_var("REUPS_SITE_CONFIG", root.join("site.toml"));
+    env::set_var("REUPS_USER_CONFIG", root.join("user.toml"));
+    env::set_var("REUPS_OVERLAY", root.join("overlay.json"));
+
+    let from_args = |args: Vec<&str>| {
+        let matches = reups::build_cli().get_matches_from(args);
+        let (_, sub_args) = matches.subcommand();
+        reups::DBBuilder::from_args(sub_args.unwrap())
+            .build()
+            .unwrap()
+    };
+
+    // Configured sources are added with their priorities, and the configured flavor is used
+    let db = from_args(vec!["reups", "list"]);
+    let names: Vec<String> = db.get_db_sources().into_iter().map(|x| x.0).collect();
+    assert_eq!(names, vec!["high", "low"]);
+    assert_eq!(db.get_flavor(), "Linux64");
+    let (version, table) = db
+        .get_version_table_from_tag("fooA", &vec!["current"])
+        .unwrap();
+    assert_eq!(version, "v2");
+    assert_eq!(table.product_dir, high_dir);
+
+    // Command line arguments take precedence over the configuration, and sources given with
+    // -Z are looked up before the configured ones
+    let extra = resource("resources/posix_db");
+    let db = from_args(vec![
+        "reups",
+        "list",
+        "--flavor",
+        "Darwin64",
+        "-Z",
+        extra.to_str().unwrap(),
+    ]);
+    assert_eq!(db.get_flavor(), "Darwin64");
+    let names: Vec<String> = db.get_db_sources().into_iter().map(|x| x.0).collect();
+    assert_eq!(names, vec!["Extra_0", "high", "low"]);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_default_priorities() {
+    let mut root = env::temp_dir();
+    root.push(format!("reups_defaults_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join(".eups/ups_db")).unwrap();
+    env::set_var("HOME", &root);
+    env::set_var("EUPS_PATH", resource("resources/posix_db"));
+    env::set_var("REUPS_PATH", resource("resources/json_db/json_db.json"));
+
+    // Sources given with -Z come first, then the user sources, and then the system ones
+    let db = reups::DBBuilder::new()
+        .set_overlay(None)
+        .set_cache_dir(None)
+        .add_path(resource("resources/broken_db/ups_db"))
+        .build()
+        .unwrap();
+    let names: Vec<String> = db.get_db_sources().into_iter().map(|x| x.0).collect();
+    assert_eq!(
+        names,
+        vec![
+            "Extra_0",
+            "posix_user",
+            "json_system_json_db",
+            "posix_system_posix_db"
+        ]
+    );
+
+    // A system source given the priority of the user sources is still looked up after them
+    let db = reups::DBBuilder::new()
+        .set_overlay(None)
+        .set_cache_dir(None)
+        .set_priority("posix_system_posix_db", reups::USER_PRIORITY)
+        .build()
+        .unwrap();
+    let names: Vec<String> = db.get_db_sources().into_iter().map(|x| x.0).collect();
+    assert_eq!(
+        names,
+        vec!["posix_user", "posix_system_posix_db", "json_system_json_db"]
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
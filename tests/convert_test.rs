@@ -0,0 +1,254 @@
+extern crate reups_lib;
+use reups::DBImpl;
+use reups_lib as reups;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn resource(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let mut root = std::env::temp_dir();
+    root.push(format!("reups_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("ups_db")).unwrap();
+    root
+}
+
+/// Writes every product of a posix source out to disk, and reads the source back in
+fn sync_posix(posix: &reups::PosixDBImpl) -> reups::PosixDBImpl {
+    for product in posix.get_products() {
+        posix.sync(product).unwrap();
+    }
+    reups::PosixDBImpl::new(
+        posix.get_location().clone(),
+        Some(&reups::DBLoadControl::All),
+        None,
+    )
+    .unwrap()
+}
+
+fn sorted<'a>(values: Option<Vec<&'a str>>) -> Vec<&'a str> {
+    let mut values = values.unwrap_or_default();
+    values.sort();
+    values
+}
+
+/// Asserts two database sources hold the same products, versions, tags, and table dependencies
+fn assert_same(a: &dyn DBImpl, b: &dyn DBImpl) {
+    let mut products = a.get_products();
+    products.sort();
+    assert_eq!(products, sorted(Some(b.get_products())));
+    for product in products {
+        let versions = sorted(a.get_versions(product));
+        assert_eq!(versions, sorted(b.get_versions(product)));
+        let tags = sorted(a.get_tags(product));
+        assert_eq!(tags, sorted(b.get_tags(product)));
+        for tag in tags {
+            assert_eq!(
+                a.lookup_version_tag(product, tag, None, None),
+                b.lookup_version_tag(product, tag, None, None)
+            );
+        }
+        for version in versions {
+            assert_eq!(
+                sorted(a.get_flavors(product, version)),
+                sorted(b.get_flavors(product, version))
+            );
+            let table_a = a.get_table(product, version, None, None).unwrap();
+            let table_b = b.get_table(product, version, None, None).unwrap();
+            assert_eq!(table_a.exact, table_b.exact);
+            assert_eq!(table_a.inexact, table_b.inexact);
+            assert_eq!(
+                table_a.product_dir.canonicalize().unwrap(),
+                table_b.product_dir.canonicalize().unwrap()
+            );
+        }
+    }
+}
+
+/// Reads a version file, leaving out the paths which are rewritten when a source is converted
+fn version_file(path: &Path) -> String {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .filter(|x| !x.contains("PROD_DIR") && !x.contains("TABLE_FILE"))
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+#[test]
+fn test_json2posix() {
+    let root = temp_dir("json2posix");
+    let json = reups::JsonDBImpl::from_file(&resource("resources/json_db/json_db.json")).unwrap();
+    let posix = sync_posix(&json.to_posix(&root.join("ups_db"), false));
+    assert_same(&json, &posix);
+    // The new source is in another directory, so the product directories are made absolute
+    assert_eq!(
+        posix.get_table("fooA", "v1", None, None).unwrap().path,
+        Some(resource("resources/test_packages/fooA/ups/fooA.table"))
+    );
+
+    // Converting back gives the same source, with the same identities
+    let round_trip = posix.to_json(&resource("resources/json_db/json_db.json"));
+    assert_same(&json, &round_trip);
+    for product in json.get_products() {
+        for ident in json.get_identities(product).unwrap() {
+            assert_eq!(
+                json.lookup_version_ident(product, ident),
+                round_trip.lookup_version_ident(product, ident)
+            );
+        }
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_posix2json2posix() {
+    let root = temp_dir("posix2json2posix");
+    let original = reups::PosixDBImpl::new(
+        resource("resources/posix_db/ups_db"),
+        Some(&reups::DBLoadControl::All),
+        None,
+    )
+    .unwrap();
+    let json = original.to_json(&resource("resources/posix_db/posix_db.json"));
+    let posix = sync_posix(&json.to_posix(&root.join("ups_db"), false));
+    assert_same(&original, &posix);
+    for (product, version) in vec![("fooA", "v1"), ("fooA", "v3"), ("fooC", "v2")] {
+        let name = format!("{}/{}.version", product, version);
+        assert_eq!(
+            version_file(&root.join("ups_db").join(&name)),
+            version_file(&resource("resources/posix_db/ups_db").join(&name))
+        );
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_json2posix_tables() {
+    let root = temp_dir("json2posix_tables");
+    let json = reups::JsonDBImpl::from_file(&resource("resources/json_db/json_db.json")).unwrap();
+    let posix = sync_posix(&json.to_posix(&root.join("ups_db"), true));
+    assert_same(&json, &posix);
+    let table_file = root.join("ups_db/fooA/v1/Linux64/fooA.table");
+    assert!(reups::table::Table::check_file(&table_file).is_ok());
+    assert_eq!(
+        posix.get_table("fooA", "v1", None, None).unwrap().path,
+        Some(table_file)
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+fn convert(from: &str, to: &str, merge: bool) -> (String, Result<(), String>) {
+    let mut args = vec!["reups", "db", "convert", "--from", from, "--to", to];
+    if merge {
+        args.push("--merge");
+    }
+    let mut cursor = std::io::Cursor::new(vec![]);
+    let matches = reups::build_cli().get_matches_from(args);
+    let (_, m) = matches.subcommand();
+    let result = reups::db_command(m.unwrap(), &matches, &mut cursor);
+    (String::from_utf8(cursor.into_inner()).unwrap(), result)
+}
+
+#[test]
+fn test_convert_posix_to_json() {
+    let root = temp_dir("convert_posix_to_json");
+    let from = resource("resources/posix_db");
+    let to = root.join("converted.json");
+    let (output, result) = convert(from.to_str().unwrap(), to.to_str().unwrap(), false);
+    assert!(result.is_ok());
+    assert!(output.starts_with("Converted 6 versions and 3 tags from"));
+
+    let original = reups::PosixDBImpl::new(
+        resource("resources/posix_db/ups_db"),
+        Some(&reups::DBLoadControl::All),
+        None,
+    )
+    .unwrap();
+    let json = reups::JsonDBImpl::from_file(&to).unwrap();
+    assert_same(&original, &json);
+    // Posix sources have no identities, so they are hashed from the versions
+    assert_eq!(
+        json.lookup_version_ident("fooA", &reups::hash_ident("v2")),
+        Some("v2")
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_convert_qualifiers() {
+    let root = temp_dir("convert_qualifiers");
+    let to = root.join("converted.json");
+    let (_, result) = convert(
+        resource("resources/qualifier_db").to_str().unwrap(),
+        to.to_str().unwrap(),
+        false,
+    );
+    assert!(result.is_ok());
+    let json = reups::JsonDBImpl::from_file(&to).unwrap();
+    assert!(json.get_table("fooC", "v1", None, Some("debug")).is_some());
+    assert!(json.get_table("fooC", "v1", None, Some("")).is_none());
+    assert_eq!(
+        json.lookup_version_tag("fooC", "current", None, Some("")),
+        Some("v2")
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_convert_merge() {
+    let root = temp_dir("convert_merge");
+    fs::remove_dir_all(root.join("ups_db")).unwrap();
+    let to = root.to_str().unwrap();
+    assert!(
+        convert(resource("resources/posix_db").to_str().unwrap(), to, false)
+            .1
+            .is_ok()
+    );
+
+    // An existing destination is only added to when merging
+    let multigroup = resource("resources/multigroup_db");
+    let (_, result) = convert(multigroup.to_str().unwrap(), to, false);
+    assert_eq!(
+        result,
+        Err(format!(
+            "Destination {} already exists, use --merge to add to it\n",
+            to
+        ))
+    );
+
+    // Entries the destination already has are reported and not replaced
+    let (output, result) = convert(multigroup.to_str().unwrap(), to, true);
+    assert_eq!(result, Err("2 entries were not converted\n".to_string()));
+    let expected = format!(
+        "Conflicts:
+  Database already contains product fooC with version v1 for flavor Linux64 and qualifiers \"\"
+  Database already contains tag current for product fooC version v1
+Converted 3 versions and 1 tags from {} to {}
+",
+        multigroup.to_str().unwrap(),
+        to
+    );
+    assert_eq!(output, expected);
+
+    let merged =
+        reups::PosixDBImpl::new(root.join("ups_db"), Some(&reups::DBLoadControl::All), None)
+            .unwrap();
+    assert_eq!(
+        merged.lookup_version_tag("fooC", "current", Some("Linux64"), None),
+        Some("v2")
+    );
+    assert_eq!(
+        merged.lookup_version_tag("fooC", "current", Some("DarwinARM64"), None),
+        Some("v2")
+    );
+    // The groups the destination did not have are added to its versions
+    assert_eq!(
+        sorted(merged.get_flavors("fooC", "v1")),
+        vec!["DarwinARM64", "Linux64", "Linux64"]
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
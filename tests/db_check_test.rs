@@ -0,0 +1,91 @@
+extern crate reups_lib;
+use reups_lib as reups;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn resource(path: &str) -> String {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push(path);
+    root.to_str().unwrap().to_string()
+}
+
+fn check(database: &str, json: bool) -> (String, Result<(), String>) {
+    let mut args = vec!["reups", "db", "check", "-U", "-S", "-Z", database];
+    if json {
+        args.push("--json");
+    }
+    let mut cursor = Cursor::new(vec![]);
+    let matches = reups::build_cli().get_matches_from(args);
+    let (_, m) = matches.subcommand();
+    let result = reups::db_command(m.unwrap(), &matches, &mut cursor);
+    (String::from_utf8(cursor.into_inner()).unwrap(), result)
+}
+
+#[test]
+fn test_check_clean() {
+    let database = format!(
+        "{}:{}:{}",
+        resource("resources/posix_db"),
+        resource("resources/json_db/json_db.json"),
+        resource("resources/multigroup_db")
+    );
+    let (output, result) = check(&database, false);
+    assert!(result.is_ok());
+    assert_eq!(output, "Checked 3 sources, found 0 errors and 0 warnings\n");
+}
+
+#[test]
+fn test_check_broken() {
+    let database = format!(
+        "{}:{}",
+        resource("resources/broken_db"),
+        resource("resources/broken_db/broken_db.json")
+    );
+    let (output, result) = check(&database, false);
+    assert_eq!(result, Err("Database check found 7 errors\n".to_string()));
+    let root = resource("resources/broken_db");
+    let expected = format!("Extra_0 ({root}/ups_db)
+  error: fooA (Linux64): tag current points at version v2 which is not declared
+  error: fooA v1 (Linux64): product directory {root}/packages/fooA does not exist
+  error: fooB v1 (Linux64): table file {root}/packages/fooB/ups/fooB.table could not be parsed: unbalanced parentheses on line 1
+  error: fooC v1 (Linux64): required dependency fooZ is not declared
+  warning: fooC v1 (Linux64): optional dependency fooY is not declared
+  error: fooD v1 (Linux64): table file {root}/packages/fooD/ups/fooD.table does not exist
+Extra_1 ({root}/broken_db.json)
+  error: file has 2 Versions but 1 Tables, entries without a partner are ignored
+  error: fooE (Linux64): tag current points at version v3 which is not declared
+Checked 2 sources, found 7 errors and 1 warnings
+", root = root);
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_check_json() {
+    // Without the posix source, the dependency of fooE on fooA is not declared
+    let (output, result) = check(&resource("resources/broken_db/broken_db.json"), true);
+    assert!(result.is_err());
+    let checks: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let problems = checks[0]["problems"].as_array().unwrap();
+    assert_eq!(problems.len(), 3);
+    assert_eq!(problems[2]["product"], "fooE");
+    assert_eq!(problems[2]["version"], "v1");
+    assert_eq!(problems[2]["severity"], "error");
+    assert_eq!(
+        problems[2]["message"],
+        "required dependency fooA is not declared"
+    );
+}
+
+#[test]
+fn test_check_table_file() {
+    let table = |name| PathBuf::from(resource(name));
+    assert!(
+        reups::table::Table::check_file(&table("resources/test_packages/fooA/ups/fooA.table"))
+            .is_ok()
+    );
+    assert_eq!(
+        reups::table::Table::check_file(&table("resources/broken_db/packages/fooB/ups/fooB.table")),
+        Err("unbalanced parentheses on line 1".to_string())
+    );
+    assert!(reups::table::Table::check_file(&table("resources/broken_db/missing.table")).is_err());
+}
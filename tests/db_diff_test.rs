@@ -0,0 +1,91 @@
+extern crate reups_lib;
+use reups_lib as reups;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn resource(path: &str) -> String {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join(path)
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+fn diff(a: &str, b: &str) -> (String, Result<(), String>) {
+    let mut cursor = Cursor::new(vec![]);
+    let matches = reups::build_cli().get_matches_from(vec!["reups", "db", "diff", a, b]);
+    let (_, m) = matches.subcommand();
+    let result = reups::db_command(m.unwrap(), &matches, &mut cursor);
+    (String::from_utf8(cursor.into_inner()).unwrap(), result)
+}
+
+#[test]
+fn test_diff_same() {
+    // The json source holds the same products as the posix one
+    let a = resource("resources/posix_db");
+    let b = resource("resources/json_db/json_db.json");
+    let (output, result) = diff(&a, &b);
+    assert!(result.is_ok());
+    assert_eq!(
+        output,
+        format!("Found 0 differences between {} and {}\n", a, b)
+    );
+}
+
+#[test]
+fn test_diff_groups() {
+    let a = resource("resources/posix_db");
+    let b = resource("resources/multigroup_db");
+    let (output, result) = diff(&a, &b);
+    assert!(result.is_ok());
+    let expected = format!(
+        "fooA: product removed
+fooB: product removed
+fooC (DarwinARM64): tag current added at version v2
+fooC (Linux64): tag current moved from version v2 to v1
+fooC v1 (DarwinARM64): flavor added
+fooC v1 (Linux64 debug): flavor added
+fooC v2 (DarwinARM64): flavor added
+fooC v2 (Linux64): flavor removed
+Found 8 differences between {} and {}
+",
+        a, b
+    );
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_diff_tables() {
+    // The broken source changes the dependencies in the table of fooC v1
+    let differences = reups::diff(
+        reups::open_source(
+            &PathBuf::from(resource("resources/posix_db/ups_db")),
+            Some(&reups::DBLoadControl::All),
+            None,
+            &Default::default(),
+        )
+        .unwrap()
+        .as_ref(),
+        reups::open_source(
+            &PathBuf::from(resource("resources/broken_db/ups_db")),
+            Some(&reups::DBLoadControl::All),
+            None,
+            &Default::default(),
+        )
+        .unwrap()
+        .as_ref(),
+    );
+    let fooc: Vec<String> = differences
+        .iter()
+        .filter(|x| x.product == "fooC" && x.version.as_deref() == Some("v1"))
+        .map(|x| x.description.clone())
+        .collect();
+    assert_eq!(
+        fooc,
+        vec![
+            "inexact optional dependency fooB added",
+            "inexact optional dependency fooY added",
+            "inexact required dependency fooZ added",
+        ]
+    );
+}
@@ -0,0 +1,262 @@
+extern crate reups_lib;
+use reups::DBBuilderTrait;
+use reups_lib as reups;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        let target = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &target);
+        } else {
+            fs::copy(&path, &target).unwrap();
+        }
+    }
+}
+
+/// Copies the broken database to a temporary location, as fixing writes to it. The json source
+/// is damaged further by removing an identity and giving it a Tables entry without a Versions one
+fn broken_copy(name: &str) -> PathBuf {
+    let mut root = std::env::temp_dir();
+    root.push(format!("reups_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    copy_dir(
+        &PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/broken_db"),
+        &root,
+    );
+    let json_path = root.join("broken_db.json");
+    let mut json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+    json["Versions"][0].as_object_mut().unwrap().remove("IDENT");
+    json["Versions"].as_array_mut().unwrap().pop();
+    let table = json["Tables"][0].clone();
+    json["Tables"].as_array_mut().unwrap().push(table);
+    fs::write(&json_path, serde_json::to_string(&json).unwrap()).unwrap();
+    root
+}
+
+fn run(root: &Path, args: Vec<&str>) -> (String, Result<(), String>) {
+    let database = format!(
+        "{}:{}",
+        root.to_str().unwrap(),
+        root.join("broken_db.json").to_str().unwrap()
+    );
+    run_on(&database, args)
+}
+
+fn run_on(database: &str, args: Vec<&str>) -> (String, Result<(), String>) {
+    let mut args: Vec<&str> = args;
+    args.extend(vec!["-U", "-S", "-Z", database]);
+    let mut cursor = Cursor::new(vec![]);
+    let matches = reups::build_cli().get_matches_from(args);
+    let (_, m) = matches.subcommand();
+    let result = reups::db_command(m.unwrap(), &matches, &mut cursor);
+    (String::from_utf8(cursor.into_inner()).unwrap(), result)
+}
+
+#[test]
+fn test_fix_dry_run() {
+    let root = broken_copy("fix_dry_run");
+    let chain = fs::read_to_string(root.join("ups_db/fooA/current.chain")).unwrap();
+    let json = fs::read_to_string(root.join("broken_db.json")).unwrap();
+    let (output, result) = run(&root, vec!["reups", "db", "fix", "--dry-run"]);
+    assert!(result.is_ok());
+    let expected = format!(
+        "Extra_0 ({root}/ups_db)
+  fooA (Linux64): remove tag current which points at undeclared version v2
+Extra_1 ({root}/broken_db.json)
+  drop 1 Tables entries with no Versions
+  fooE (Linux64): remove tag current which points at undeclared version v3
+  fooE v1 (Linux64): create identity {ident}
+Found 4 repairs to make in 2 sources, none were made as this is a dry run
+",
+        root = root.to_str().unwrap(),
+        ident = reups::hash_ident("v1")
+    );
+    assert_eq!(output, expected);
+    // Nothing is written in a dry run
+    assert_eq!(
+        fs::read_to_string(root.join("ups_db/fooA/current.chain")).unwrap(),
+        chain
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("broken_db.json")).unwrap(),
+        json
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_fix_dry_run_unchanged() {
+    let root = broken_copy("fix_dry_run_unchanged");
+    let mut db = reups::DBBuilder::new()
+        .add_eups_env(false)
+        .add_eups_user(false)
+        .add_reups_env(false)
+        .add_reups_user(false)
+        .set_overlay(None)
+        .add_path(root.join("ups_db"))
+        .add_path(root.join("broken_db.json"))
+        .set_flavor("Linux64")
+        .build()
+        .unwrap();
+    let repairs = |db: &mut reups::DB| -> Vec<Vec<reups::Repair>> {
+        db.fix(None, Some(reups::PathStyle::Absolute), true)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.repairs)
+            .collect()
+    };
+    let first = repairs(&mut db);
+    assert!(first.iter().all(|x| !x.is_empty()));
+    // The sources in memory are left as they were, so the same repairs are found again
+    assert_eq!(repairs(&mut db), first);
+    assert_eq!(db.product_tags("fooA"), vec!["current"]);
+    assert_eq!(db.get_identity_from_version("fooE", "v1"), None);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_fix() {
+    let root = broken_copy("fix");
+    let (output, result) = run(&root, vec!["reups", "db", "fix"]);
+    assert!(result.is_ok());
+    assert!(output.ends_with("Made 4 repairs in 2 sources\n"));
+    assert!(!root.join("ups_db/fooA/current.chain").exists());
+
+    let json = reups::JsonDBImpl::from_file(&root.join("broken_db.json")).unwrap();
+    let serialized: serde_json::Value =
+        serde_json::from_str(&serde_json::to_string(&json).unwrap()).unwrap();
+    assert_eq!(serialized["Tables"].as_array().unwrap().len(), 1);
+    assert_eq!(serialized["Tags"].as_array().unwrap().len(), 0);
+    assert_eq!(serialized["Versions"][0]["IDENT"], reups::hash_ident("v1"));
+
+    // Only the problems fix does not repair are left
+    let (output, _) = run(&root, vec!["reups", "db", "check"]);
+    assert!(!output.contains("tag current"));
+    assert!(!output.contains("Versions"));
+    let (output, result) = run(&root, vec!["reups", "db", "fix"]);
+    assert!(result.is_ok());
+    assert_eq!(output, "Made 0 repairs in 2 sources\n");
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_fix_sqlite() {
+    let root = broken_copy("fix_sqlite");
+    let sqlite = root.join("broken_db.sqlite");
+    let json = root.join("broken_db.json");
+    let matches = reups::build_cli().get_matches_from(vec![
+        "reups",
+        "db",
+        "convert",
+        "--from",
+        json.to_str().unwrap(),
+        "--to",
+        sqlite.to_str().unwrap(),
+    ]);
+    // The dangling tag is not converted, so it is added to the file directly
+    let (_, m) = matches.subcommand();
+    let result = reups::db_command(m.unwrap(), &matches, &mut Cursor::new(vec![]));
+    assert_eq!(result, Err("1 entries were not converted\n".to_string()));
+    let connection = rusqlite::Connection::open(&sqlite).unwrap();
+    connection
+        .execute(
+            "INSERT INTO tags (product, tag, flavor, qualifiers, version, info)
+             VALUES ('fooE', 'current', 'Linux64', '', 'v9', ?1)",
+            [r#"{"FLAVOR": "Linux64", "QUALIFIERS": "", "TAG": "current", "VERSION": "v9"}"#],
+        )
+        .unwrap();
+    drop(connection);
+    let database = sqlite.to_str().unwrap();
+
+    let (output, _) = run_on(database, vec!["reups", "db", "check"]);
+    assert!(output.contains("tag current"));
+    let (output, result) = run_on(database, vec!["reups", "db", "fix"]);
+    assert!(result.is_ok());
+    assert!(output.ends_with("Made 1 repairs in 1 sources\n"));
+
+    // The repairs were written to the file
+    let (output, _) = run_on(database, vec!["reups", "db", "check"]);
+    assert!(!output.contains("tag current"));
+    let source = reups::open_source(&sqlite, None, None, &Default::default()).unwrap();
+    assert_eq!(
+        source.lookup_version_tag("fooE", "current", None, None),
+        None
+    );
+    assert_eq!(
+        source.lookup_version_tag("fooE", "current", Some("Linux64"), None),
+        None
+    );
+    assert_eq!(source.get_versions("fooE"), Some(vec!["v1"]));
+    let (output, result) = run_on(database, vec!["reups", "db", "fix"]);
+    assert!(result.is_ok());
+    assert_eq!(output, "Made 0 repairs in 1 sources\n");
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_fix_paths() {
+    let root = broken_copy("fix_paths");
+    let version_file = root.join("ups_db/fooC/v1.version");
+    let original = fs::read_to_string(&version_file).unwrap();
+    let absolute = root.canonicalize().unwrap().join("packages/fooC");
+
+    let (_, result) = run(
+        &root,
+        vec![
+            "reups", "db", "fix", "--source", "Extra_0", "--paths", "absolute",
+        ],
+    );
+    assert!(result.is_ok());
+    let contents = fs::read_to_string(&version_file).unwrap();
+    assert!(contents.contains(&format!("PROD_DIR = {}\n", absolute.to_str().unwrap())));
+    // The json source was not repaired
+    assert!(fs::read_to_string(root.join("broken_db.json"))
+        .unwrap()
+        .contains("\"packages/fooC\""));
+
+    let (_, result) = run(
+        &root,
+        vec![
+            "reups", "db", "fix", "--source", "Extra_0", "--paths", "relative",
+        ],
+    );
+    assert!(result.is_ok());
+    assert_eq!(fs::read_to_string(&version_file).unwrap(), original);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_rewrite_path() {
+    let base = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/posix_db");
+    let absolute = base.join("../test_packages/fooA").canonicalize().unwrap();
+    assert_eq!(
+        reups::rewrite_path(&base, "../test_packages/fooA", reups::PathStyle::Absolute),
+        Some(absolute.to_str().unwrap().to_string())
+    );
+    assert_eq!(
+        reups::rewrite_path(
+            &base,
+            absolute.to_str().unwrap(),
+            reups::PathStyle::Relative
+        ),
+        Some("../test_packages/fooA".to_string())
+    );
+    assert_eq!(
+        reups::rewrite_path(&base, "../test_packages/fooA", reups::PathStyle::Relative),
+        None
+    );
+    assert_eq!(
+        reups::rewrite_path(
+            &base,
+            "../test_packages/missing",
+            reups::PathStyle::Absolute
+        ),
+        None
+    );
+}
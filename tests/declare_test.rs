@@ -0,0 +1,267 @@
+extern crate reups_lib;
+use reups::DBBuilderTrait;
+use reups_lib as reups;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn resource(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+/// Makes an empty temporary directory for a test
+fn temp_root(name: &str) -> PathBuf {
+    let mut root = std::env::temp_dir();
+    root.push(format!("reups_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+fn declare(args: Vec<&str>) -> Result<(), String> {
+    let mut full = vec!["reups", "declare", "-U"];
+    full.extend(args);
+    let matches = reups::build_cli().get_matches_from(full);
+    let (_, m) = matches.subcommand();
+    reups::declare_command(m.unwrap(), &matches)
+}
+
+fn open(source: &Path) -> reups::DB {
+    reups::DBBuilder::new()
+        .add_eups_env(false)
+        .add_eups_user(false)
+        .add_reups_env(false)
+        .add_reups_user(false)
+        .add_path(source.to_path_buf())
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_declare_tables() {
+    let root = temp_root("declare_tables");
+    let source = root.join("ups_db");
+    fs::create_dir_all(&source).unwrap();
+    let source_str = root.to_str().unwrap();
+
+    // A product without a table file only sets its product directory
+    let bare = root.join("bare");
+    fs::create_dir_all(&bare).unwrap();
+    declare(vec![
+        "bare",
+        "v1",
+        "-r",
+        bare.to_str().unwrap(),
+        "-Z",
+        source_str,
+    ])
+    .unwrap();
+
+    // The table may be read from another ups directory, or be given directly
+    let other = root.join("other");
+    fs::create_dir_all(other.join("etc")).unwrap();
+    let fooa_table = resource("resources/test_packages/fooA/ups/fooA.table");
+    fs::copy(&fooa_table, other.join("etc/other.table")).unwrap();
+    declare(vec![
+        "other",
+        "v1",
+        "-r",
+        other.to_str().unwrap(),
+        "--ups-dir",
+        "etc",
+        "-Z",
+        source_str,
+    ])
+    .unwrap();
+    declare(vec![
+        "other",
+        "v2",
+        "-r",
+        bare.to_str().unwrap(),
+        "--table",
+        fooa_table.to_str().unwrap(),
+        "-Z",
+        source_str,
+    ])
+    .unwrap();
+
+    // A missing ups directory or table file given on the command line is an error
+    assert!(declare(vec![
+        "other",
+        "v3",
+        "-r",
+        other.to_str().unwrap(),
+        "--ups-dir",
+        "missing",
+        "-Z",
+        source_str,
+    ])
+    .is_err());
+    assert!(declare(vec![
+        "other",
+        "v3",
+        "-r",
+        other.to_str().unwrap(),
+        "--table",
+        root.join("missing.table").to_str().unwrap(),
+        "-Z",
+        source_str,
+    ])
+    .is_err());
+
+    let db = open(&source);
+    let table = db.get_table_from_version("bare", "v1").unwrap();
+    assert_eq!(table.product_dir, bare.canonicalize().unwrap());
+    assert!(table.env_var.is_empty());
+    for version in &["v1", "v2"] {
+        let table = db.get_table_from_version("other", version).unwrap();
+        assert!(table.env_var.contains_key("PATH"));
+    }
+    assert!(db.get_table_from_version("other", "v3").is_none());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_declare_git() {
+    let root = temp_root("declare_git");
+    let source = root.join("db.json");
+    let product = root.join("fooA");
+    let sha = "0123456789abcdef0123456789abcdef01234567";
+    let other = "89abcdef0123456789abcdef0123456789abcdef";
+    fs::create_dir_all(product.join(".git/refs/heads")).unwrap();
+    fs::create_dir_all(product.join(".git/refs/tags")).unwrap();
+    fs::write(product.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+    fs::write(product.join(".git/refs/heads/main"), format!("{}\n", sha)).unwrap();
+    fs::write(
+        product.join(".git/packed-refs"),
+        format!(
+            "# pack-refs with: peeled fully-peeled sorted\n{} refs/tags/v0.9\n{} refs/tags/w1.0\n",
+            other, sha
+        ),
+    )
+    .unwrap();
+
+    // The version is the tag of the checked out commit and the ident its hash
+    let head = reups::read_git_head(&product).unwrap();
+    assert_eq!(head.tag, Some("w1.0".to_string()));
+    fs::write(product.join(".git/refs/tags/v1.0"), format!("{}\n", sha)).unwrap();
+    declare(vec![
+        "fooA",
+        "-r",
+        product.to_str().unwrap(),
+        "--git",
+        "-Z",
+        source.to_str().unwrap(),
+    ])
+    .unwrap();
+    let db = open(&source);
+    assert_eq!(db.get_version_from_identity("fooA", sha), Some("v1.0"));
+
+    // An untagged commit is versioned by its abbreviated hash
+    fs::remove_file(product.join(".git/refs/tags/v1.0")).unwrap();
+    fs::remove_file(product.join(".git/packed-refs")).unwrap();
+    let head = reups::read_git_head(&product).unwrap();
+    assert_eq!(head.version(), "0123456");
+
+    // Without a repository there is nothing to declare
+    let plain = root.join("plain");
+    fs::create_dir_all(&plain).unwrap();
+    assert!(reups::read_git_head(&plain).is_err());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_declare_generated_ident() {
+    let root = temp_root("declare_ident");
+    let source = root.join("db.json");
+    let source_str = source.to_str().unwrap();
+    let prod_dir = resource("resources/test_packages/fooA");
+    let prod_str = prod_dir.to_str().unwrap();
+
+    // A JSON source makes an identity from the product directory and table
+    declare(vec!["fooA", "v1", "-r", prod_str, "-Z", source_str]).unwrap();
+    let ident = reups::content_ident(
+        "fooA",
+        "v1",
+        &prod_dir.canonicalize().unwrap(),
+        Some(&prod_dir.join("ups/fooA.table")),
+    );
+    let db = open(&source);
+    assert_eq!(
+        db.get_identity_from_version("fooA", "v1"),
+        Some(ident.as_str())
+    );
+
+    // Tagging the version later keeps its identity, and one given is used instead
+    declare(vec![
+        "fooA", "v1", "-r", prod_str, "-t", "current", "-Z", source_str,
+    ])
+    .unwrap();
+    declare(vec![
+        "fooA", "v2", "-r", prod_str, "--ident", "given", "-Z", source_str,
+    ])
+    .unwrap();
+    let db = open(&source);
+    assert_eq!(db.get_version_from_identity("fooA", &ident), Some("v1"));
+    assert_eq!(
+        db.get_versions_from_tag("fooA", &vec!["current"]),
+        vec!["v1"]
+    );
+    assert_eq!(db.get_identity_from_version("fooA", "v2"), Some("given"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_declare_strict() {
+    let root = temp_root("declare_strict");
+    let source = root.join("db.json");
+    fs::copy(resource("resources/json_db/json_db.json"), &source).unwrap();
+    let source_str = source.to_str().unwrap();
+    let prod_dir = resource("resources/test_packages/fooB");
+    let prod_str = prod_dir.to_str().unwrap();
+    let table = |name: &str, contents: &str| {
+        let path = root.join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let declare_table = |version: &str, table: &str, strict: bool| {
+        let mut args = vec![
+            "fooB", version, "-r", prod_str, "--table", table, "-Z", source_str,
+        ];
+        if strict {
+            args.push("--strict");
+        }
+        declare(args)
+    };
+
+    // Dependencies declared in the source are accepted
+    declare(vec![
+        "fooB", "v5", "-r", prod_str, "--strict", "-Z", source_str,
+    ])
+    .unwrap();
+    let optional = table("optional.table", "setupOptional(fooZ)\n");
+    declare_table("v6", &optional, true).unwrap();
+
+    // Missing products and exact versions refuse a strict declare, and are otherwise warnings
+    let missing = table("missing.table", "setupRequired(fooZ)\n");
+    let version = table("version.table", "setupRequired(fooC -j v9)\n");
+    assert!(declare_table("v7", &missing, true).is_err());
+    assert!(declare_table("v7", &version, true).is_err());
+    // The source of each dependency is shown along with the missing ones
+    let mixed = table("mixed.table", "setupRequired(fooC)\nsetupRequired(fooZ)\n");
+    let message = declare_table("v7", &mixed, true).unwrap_err();
+    assert!(message.contains("Dependency fooZ of fooB is not declared in any source"));
+    assert!(message.contains("  fooC is declared in source Extra_0\n"));
+    assert!(message.ends_with("  fooZ is not declared in any source"));
+    declare_table("v7", &version, false).unwrap();
+
+    let db = open(&source);
+    assert_eq!(
+        db.get_source_of_product("fooC", Some("v1")),
+        Some("Extra_0")
+    );
+    assert_eq!(db.get_source_of_product("fooC", Some("v9")), None);
+    let mut versions = db.product_versions("fooB");
+    versions.sort();
+    assert_eq!(versions, vec!["v1", "v5", "v6", "v7"]);
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,147 @@
+extern crate reups_lib;
+use reups_lib as reups;
+use reups_lib::DBImpl;
+use std::env;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn db_path() -> String {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push("resources/flavor_db/flavor_db.json");
+    root.to_str().unwrap().to_string()
+}
+
+fn setup(args: Vec<&str>) -> Result<String, String> {
+    env::set_var("PATH", "");
+    env::set_var("REUPS_HISTORY", "");
+    let mut args: Vec<String> = args.iter().map(|&s| s.to_string()).collect();
+    args.push(db_path());
+
+    let mut cursor = Cursor::new(vec![]);
+
+    let app = reups::build_cli();
+    let matches = app.get_matches_from(args);
+    let (_, m) = matches.subcommand();
+    reups::setup_command(m.unwrap(), &matches, &mut cursor)?;
+    Ok(String::from_utf8(cursor.into_inner()).unwrap())
+}
+
+#[test]
+fn test_generic_flavor() {
+    assert!(reups::is_generic_flavor("NULL"));
+    assert!(reups::is_generic_flavor("generic"));
+    assert!(reups::is_generic_flavor(""));
+    assert!(!reups::is_generic_flavor("Linux64"));
+}
+
+#[test]
+fn test_json_flavors() {
+    let db = reups::JsonDBImpl::from_file(&PathBuf::from(db_path())).unwrap();
+    assert_eq!(
+        db.get_flavors("fooC", "v1"),
+        Some(vec!["Linux64", "DarwinARM64"])
+    );
+    assert_eq!(
+        db.lookup_version_tag("fooC", "current", Some("Linux64"), None),
+        Some("v1")
+    );
+    assert_eq!(
+        db.lookup_version_tag("fooC", "current", Some("DarwinARM64"), None),
+        Some("v2")
+    );
+    assert_eq!(
+        db.lookup_version_tag("fooC", "current", Some("Linux"), None),
+        None
+    );
+    let darwin = db
+        .get_table("fooC", "v1", Some("DarwinARM64"), None)
+        .unwrap();
+    assert!(darwin.product_dir.ends_with("test_packages/fooB"));
+    // a generic flavor matches any host
+    assert!(db.get_table("fooC", "v2", Some("Linux"), None).is_some());
+    assert!(db.get_table("fooC", "v1", Some("Linux"), None).is_none());
+
+    // all the flavors survive writing the database back out
+    let serialized = serde_json::to_string(&db).unwrap();
+    let round_trip: reups::JsonDBImpl = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(
+        round_trip.get_flavors("fooC", "v1"),
+        Some(vec!["Linux64", "DarwinARM64"])
+    );
+    assert_eq!(
+        round_trip.lookup_version_tag("fooC", "current", Some("DarwinARM64"), None),
+        Some("v2")
+    );
+}
+
+#[test]
+fn test_setup_flavor() {
+    let linux = setup(vec![
+        "reups", "setup", "-U", "-S", "--flavor", "Linux64", "fooC", "v1", "-j", "-Z",
+    ])
+    .unwrap();
+    assert!(linux.contains("test_packages/fooC "));
+    assert!(linux.contains("SETUP_FOOC=fooC\\ v1\\ -f\\ Linux64\\ "));
+
+    let darwin = setup(vec![
+        "reups",
+        "setup",
+        "-U",
+        "-S",
+        "--flavor",
+        "DarwinARM64",
+        "fooC",
+        "v1",
+        "-j",
+        "-Z",
+    ])
+    .unwrap();
+    assert!(darwin.contains("test_packages/fooB "));
+    assert!(darwin.contains("SETUP_FOOC=fooC\\ v1\\ -f\\ DarwinARM64\\ "));
+
+    // The current tag points to a generic version for this flavor
+    let tagged = setup(vec![
+        "reups",
+        "setup",
+        "-U",
+        "-S",
+        "--flavor",
+        "DarwinARM64",
+        "fooC",
+        "-j",
+        "-Z",
+    ])
+    .unwrap();
+    assert!(tagged.contains("SETUP_FOOC=fooC\\ v2\\ -f\\ NULL\\ "));
+
+    assert!(setup(vec![
+        "reups", "setup", "-U", "-S", "--flavor", "Linux", "fooC", "v1", "-j", "-Z",
+    ])
+    .is_err());
+}
+
+#[test]
+fn test_list_flavors() {
+    let args = vec![
+        "reups",
+        "list",
+        "-U",
+        "-S",
+        "--flavors",
+        "--flavor",
+        "Linux64",
+        "-Z",
+    ];
+    let mut args: Vec<String> = args.iter().map(|&s| s.to_string()).collect();
+    args.push(db_path());
+    let mut cursor = Cursor::new(vec![]);
+    let matches = reups::build_cli().get_matches_from(args);
+    let (_, m) = matches.subcommand();
+    assert!(reups::list_command(m.unwrap(), &matches, &mut cursor).is_ok());
+    let output = String::from_utf8(cursor.into_inner()).unwrap();
+    let expected = "fooC                                            v1  (Linux64, DarwinARM64)          [\u{1b}[96mcurrent\u{1b}[0m]
+
+fooC                                            v2  (NULL)          []
+";
+    assert_eq!(output, expected);
+}
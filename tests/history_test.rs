@@ -0,0 +1,64 @@
+extern crate reups_lib;
+use reups_lib as reups;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn resource(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn run(args: Vec<&str>) -> Result<String, String> {
+    let mut full = vec!["reups"];
+    full.extend(args);
+    let matches = reups::build_cli().get_matches_from(full);
+    let (name, m) = matches.subcommand();
+    let mut cursor = Cursor::new(vec![]);
+    match name {
+        "declare" => reups::declare_command(m.unwrap(), &matches)?,
+        _ => reups::db_command(m.unwrap(), &matches, &mut cursor)?,
+    }
+    Ok(String::from_utf8(cursor.into_inner()).unwrap())
+}
+
+#[test]
+fn test_history() {
+    let mut root = std::env::temp_dir();
+    root.push(format!("reups_history_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("ups_db")).unwrap();
+    let prod_dir = resource("resources/test_packages/fooC");
+    let prod_str = prod_dir.to_str().unwrap();
+
+    // Both posix and json sources keep a log of the changes made to them
+    let json = root.join("db.json");
+    for source in [root.to_str().unwrap(), json.to_str().unwrap()].iter() {
+        let declare = |version, tag: Option<&str>| {
+            let mut args = vec!["declare", "-U", "fooC", version, "-r", prod_str];
+            args.extend(&["--ident", version, "--flavor", "Linux64", "-Z", source]);
+            if let Some(tag) = tag {
+                args.extend(&["-t", tag]);
+            }
+            run(args)
+        };
+        declare("v1", None).unwrap();
+        declare("v1", Some("current")).unwrap();
+
+        let output = run(vec!["db", "history", "-U", "-Z", source]).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].ends_with(": declare fooC v1 (Linux64)"));
+        assert!(lines[2].ends_with(": tag current on fooC v1 (Linux64)"));
+        assert_eq!(lines[3], "Found 2 changes in 1 sources");
+        let output = run(vec!["db", "history", "fooA", "-U", "-Z", source]).unwrap();
+        assert_eq!(output, "Found 0 changes in 1 sources\n");
+    }
+    assert!(root.join("ups_db/.reups_log.jsonl").exists());
+
+    let entries = reups::read_history(&json, Some("fooC")).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].operation, reups::Operation::Declare);
+    assert_eq!(entries[1].tag, Some("current".to_string()));
+    assert_eq!(entries[1].host, reups::get_host());
+    fs::remove_dir_all(&root).unwrap();
+}
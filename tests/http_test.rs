@@ -0,0 +1,166 @@
+extern crate reups_lib;
+use reups::DBImpl;
+use reups_lib as reups;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+fn resource(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let mut root = std::env::temp_dir();
+    root.push(format!("reups_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    root
+}
+
+/// A local stand in for the web server a source is published on. It serves the json test source,
+/// with its product directories made absolute, and records the If-None-Match header of each
+/// request it answers. While down is set it answers every request as unavailable, and while
+/// unchanged is set it answers every request as not modified
+struct Server {
+    url: String,
+    requests: Arc<Mutex<Vec<Option<String>>>>,
+    down: Arc<AtomicBool>,
+    unchanged: Arc<AtomicBool>,
+    server: Arc<tiny_http::Server>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Server {
+    fn start() -> Server {
+        let packages = resource("resources/test_packages");
+        let body = fs::read_to_string(resource("resources/json_db/json_db.json"))
+            .unwrap()
+            .replace("../test_packages", packages.to_str().unwrap());
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}/stack.json", server.server_addr());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let down = Arc::new(AtomicBool::new(false));
+        let unchanged = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (server, requests) = (server.clone(), requests.clone());
+            let (down, unchanged) = (down.clone(), unchanged.clone());
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let etag = request
+                        .headers()
+                        .iter()
+                        .find(|x| x.field.equiv("If-None-Match"))
+                        .map(|x| x.value.to_string());
+                    requests.lock().unwrap().push(etag.clone());
+                    let header = tiny_http::Header::from_bytes("ETag", "\"v1\"").unwrap();
+                    let response = if down.load(Ordering::SeqCst) {
+                        tiny_http::Response::from_string("").with_status_code(503)
+                    } else if unchanged.load(Ordering::SeqCst) || etag.as_deref() == Some("\"v1\"")
+                    {
+                        tiny_http::Response::from_string("").with_status_code(304)
+                    } else {
+                        tiny_http::Response::from_string(body.clone())
+                    };
+                    let _ = request.respond(response.with_header(header));
+                }
+            })
+        };
+        Server {
+            url,
+            requests,
+            down,
+            unchanged,
+            server,
+            handle: Some(handle),
+        }
+    }
+
+    fn stop(&mut self) {
+        self.server.unblock();
+        self.handle.take().unwrap().join().unwrap();
+    }
+}
+
+#[test]
+fn test_http_cache() {
+    let cache_dir = temp_dir("http_cache");
+    let mut server = Server::start();
+    let json = reups::JsonDBImpl::from_file(&resource("resources/json_db/json_db.json")).unwrap();
+
+    // The first load fetches the source, and later ones revalidate the cached copy
+    for _ in 0..2 {
+        let remote = reups::HttpDBImpl::new_with_cache(&server.url, Some(&cache_dir)).unwrap();
+        assert_eq!(reups::diff(&json, &remote), vec![]);
+        assert_eq!(remote.get_location(), &PathBuf::from(&server.url));
+        assert!(!remote.is_writable());
+    }
+    assert_eq!(
+        *server.requests.lock().unwrap(),
+        vec![None, Some("\"v1\"".to_string())]
+    );
+
+    // While the server is unavailable the cached copy is used
+    server.down.store(true, Ordering::SeqCst);
+    let remote = reups::HttpDBImpl::new_with_cache(&server.url, Some(&cache_dir)).unwrap();
+    assert_eq!(
+        remote.lookup_version_tag("fooA", "current", None, None),
+        Some("v3")
+    );
+    assert!(reups::HttpDBImpl::new_with_cache(&server.url, None).is_err());
+    server.stop();
+
+    // A server which can not be reached is an error without a cached copy
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let url = format!("http://127.0.0.1:{}/stack.json", port);
+    assert!(reups::HttpDBImpl::new_with_cache(&url, Some(&cache_dir)).is_err());
+    fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_http_not_modified_without_cache() {
+    let cache_dir = temp_dir("http_not_modified");
+    let mut server = Server::start();
+    server.unchanged.store(true, Ordering::SeqCst);
+    // A server answering not modified to a request without a cached copy is an error
+    for cache in &[None, Some(cache_dir.as_path())] {
+        let result = reups::HttpDBImpl::new_with_cache(&server.url, *cache);
+        assert!(result.err().unwrap().contains("no cached copy"));
+    }
+    assert!(!cache_dir.exists());
+    server.stop();
+}
+
+#[test]
+fn test_http_read_only() {
+    let cache_dir = temp_dir("http_read_only");
+    let mut server = Server::start();
+    let mut remote = reups::HttpDBImpl::new_with_cache(&server.url, Some(&cache_dir)).unwrap();
+    assert!(remote.sync("fooA").is_err());
+    assert!(remote.fix(None).is_err());
+    assert_eq!(remote.plan_fix(None), Ok(vec![]));
+    server.stop();
+    fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_url_paths() {
+    let json = resource("resources/json_db/json_db.json");
+    let paths = reups::path_string_to_vec(&format!(
+        "http://example.com:8080/stack.json:{}:http://example.com/a/b.json",
+        json.to_str().unwrap()
+    ))
+    .unwrap();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("http://example.com:8080/stack.json"),
+            json,
+            PathBuf::from("http://example.com/a/b.json"),
+        ]
+    );
+}
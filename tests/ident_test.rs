@@ -0,0 +1,49 @@
+extern crate reups_lib;
+use reups_lib as reups;
+use reups_lib::{DBBuilderTrait, DBImpl};
+use std::path::PathBuf;
+
+#[test]
+fn test_extract_ident() {
+    let whole = regex::Regex::new(r"g[0-9a-f]{7}").unwrap();
+    assert_eq!(
+        reups::extract_ident(&whole, "1.2.3-g5a6df72"),
+        Some("g5a6df72".to_string())
+    );
+    let group = regex::Regex::new(r"g(?P<ident>[0-9a-f]{7})$").unwrap();
+    assert_eq!(
+        reups::extract_ident(&group, "1.2.3-g5a6df72"),
+        Some("5a6df72".to_string())
+    );
+    assert_eq!(reups::extract_ident(&group, "1.2.3"), None);
+}
+
+#[test]
+fn test_posix_ident_regex() {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push("resources/posix_db/ups_db");
+    let ident_regex = regex::Regex::new(r"v(?P<ident>[0-9]+)").unwrap();
+    let db =
+        reups::PosixDBImpl::new(root, Some(&reups::DBLoadControl::All), Some(ident_regex)).unwrap();
+    assert!(db.has_identity("fooA", "2"));
+    assert_eq!(db.lookup_version_ident("fooA", "3"), Some("v3"));
+    assert_eq!(db.lookup_version_ident("fooA", "4"), None);
+}
+
+#[test]
+fn test_reups_path_ident_regex() {
+    // Sources in REUPS_PATH are named from the directory containing them
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/posix_db");
+    std::env::set_var("REUPS_PATH", &root);
+    let db = reups::DBBuilder::new()
+        .add_eups_env(false)
+        .add_eups_user(false)
+        .add_reups_user(false)
+        .set_overlay(None)
+        .set_source_ident_regex("json_system_posix_db", r"v(?P<ident>[0-9]+)")
+        .set_load_control(reups::DBLoadControl::All)
+        .build()
+        .unwrap();
+    assert!(db.has_identity("fooA", "2"));
+    assert_eq!(db.get_version_from_identity("fooA", "3"), Some("v3"));
+}
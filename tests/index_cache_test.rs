@@ -0,0 +1,159 @@
+extern crate reups_lib;
+use reups::DBImpl;
+use reups_lib as reups;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        let target = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &target);
+        } else {
+            fs::copy(&path, &target).unwrap();
+        }
+    }
+}
+
+/// Copies the posix database to a temporary location, as the tests change it, returning the
+/// path of the copied ups_db directory and a cache directory to use with it
+fn posix_copy(name: &str) -> (PathBuf, PathBuf) {
+    let mut root = std::env::temp_dir();
+    root.push(format!("reups_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    copy_dir(
+        &PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/posix_db/ups_db"),
+        &root.join("ups_db"),
+    );
+    (root.join("ups_db"), root.join("cache"))
+}
+
+fn open(location: &Path, cache_dir: Option<&Path>) -> reups::PosixDBImpl {
+    let options = reups::PosixReadOptions {
+        cache_dir: cache_dir.map(|x| x.to_path_buf()),
+        ..Default::default()
+    };
+    reups::PosixDBImpl::new_with_options(
+        location.to_path_buf(),
+        Some(&reups::DBLoadControl::All),
+        None,
+        &options,
+    )
+    .unwrap()
+}
+
+fn cache_files(cache_dir: &Path) -> usize {
+    fs::read_dir(cache_dir).map_or(0, |x| x.count())
+}
+
+fn sorted(values: Option<Vec<&str>>) -> Vec<&str> {
+    let mut values = values.unwrap_or_default();
+    values.sort();
+    values
+}
+
+#[test]
+fn test_cache_matches_source() {
+    let (location, cache_dir) = posix_copy("cache_matches_source");
+    let uncached = open(&location, None);
+    assert_eq!(cache_files(&cache_dir), 0);
+    // The first read writes the cache, and the second reads the source out of it
+    for _ in 0..2 {
+        let cached = open(&location, Some(&cache_dir));
+        assert_eq!(cache_files(&cache_dir), 1);
+        let mut products = cached.get_products();
+        products.sort();
+        assert_eq!(products, sorted(Some(uncached.get_products())));
+        for product in products {
+            assert_eq!(
+                sorted(cached.get_versions(product)),
+                sorted(uncached.get_versions(product))
+            );
+            assert_eq!(
+                sorted(cached.get_tags(product)),
+                sorted(uncached.get_tags(product))
+            );
+        }
+        assert_eq!(
+            cached.lookup_version_tag("fooA", "current", None, None),
+            Some("v3")
+        );
+        assert_eq!(
+            cached.get_groups("fooC", "v2"),
+            uncached.get_groups("fooC", "v2")
+        );
+    }
+    fs::remove_dir_all(location.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_cache_invalidation() {
+    let (location, cache_dir) = posix_copy("cache_invalidation");
+    open(&location, Some(&cache_dir));
+
+    // Editing a file in place does not change the modification time of its directory, but does
+    // change that of the file, so the product is read again. The time is moved forward in case
+    // the file system records times too coarsely to tell the writes apart
+    let chain = location.join("fooA/current.chain");
+    let contents = fs::read_to_string(&chain).unwrap();
+    let written = fs::metadata(&chain).unwrap().modified().unwrap();
+    fs::write(&chain, contents.replace("v3", "v2")).unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&chain)
+        .unwrap()
+        .set_modified(written + Duration::from_secs(1))
+        .unwrap();
+    let cached = open(&location, Some(&cache_dir));
+    assert_eq!(
+        cached.lookup_version_tag("fooA", "current", None, None),
+        Some("v2")
+    );
+    // The cache was updated with the edited file
+    let cached = open(&location, Some(&cache_dir));
+    assert_eq!(
+        cached.lookup_version_tag("fooA", "current", None, None),
+        Some("v2")
+    );
+
+    // Adding a file changes the directory, so the product is read again
+    fs::copy(
+        location.join("fooA/v3.version"),
+        location.join("fooA/v4.version"),
+    )
+    .unwrap();
+    let cached = open(&location, Some(&cache_dir));
+    assert_eq!(
+        sorted(cached.get_versions("fooA")),
+        vec!["v1", "v2", "v3", "v4"]
+    );
+    assert_eq!(
+        cached.lookup_version_tag("fooA", "current", None, None),
+        Some("v2")
+    );
+
+    // Products which are removed are dropped from the cache
+    fs::remove_dir_all(location.join("fooB")).unwrap();
+    assert!(!open(&location, Some(&cache_dir)).has_product("fooB"));
+    assert!(!open(&location, Some(&cache_dir)).has_product("fooB"));
+    fs::remove_dir_all(location.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_cache_removed_on_write() {
+    let (location, cache_dir) = posix_copy("cache_removed_on_write");
+    let cached = open(&location, Some(&cache_dir));
+    assert_eq!(cache_files(&cache_dir), 1);
+    cached.sync("fooA").unwrap();
+    assert_eq!(cache_files(&cache_dir), 0);
+
+    open(&location, Some(&cache_dir));
+    let other = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/multigroup_db/ups_db");
+    open(&other, Some(&cache_dir));
+    assert_eq!(reups::clear_index_cache(&cache_dir), Ok(2));
+    assert_eq!(cache_files(&cache_dir), 0);
+    fs::remove_dir_all(location.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,72 @@
+extern crate reups_lib;
+use reups::DBImpl;
+use reups_lib as reups;
+use std::path::PathBuf;
+
+fn location() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/posix_db/ups_db")
+}
+
+fn lazy() -> reups::LazyPosixDBImpl {
+    reups::LazyPosixDBImpl::new(location(), None, &Default::default()).unwrap()
+}
+
+fn sorted(values: Option<Vec<&str>>) -> Vec<&str> {
+    let mut values = values.unwrap_or_default();
+    values.sort();
+    values
+}
+
+#[test]
+fn test_lazy_matches_full() {
+    let full = reups::PosixDBImpl::new(location(), Some(&reups::DBLoadControl::All), None).unwrap();
+    let lazy = lazy();
+    let mut products = lazy.get_products();
+    products.sort();
+    assert_eq!(products, sorted(Some(full.get_products())));
+    for product in products {
+        assert_eq!(
+            sorted(lazy.get_tags(product)),
+            sorted(full.get_tags(product))
+        );
+        for version in sorted(full.get_versions(product)) {
+            assert_eq!(
+                lazy.get_groups(product, version),
+                full.get_groups(product, version)
+            );
+            assert_eq!(
+                lazy.get_table(product, version, None, None),
+                full.get_table(product, version, None, None)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_lazy_reads_touched_products() {
+    let lazy = lazy();
+    assert_eq!(lazy.loaded_count(), 0);
+    assert_eq!(
+        lazy.lookup_version_tag("fooA", "current", None, None),
+        Some("v3")
+    );
+    assert_eq!(lazy.loaded_count(), 1);
+    // Products which are not in the source are not read
+    assert!(!lazy.has_product("fooZ"));
+    assert_eq!(lazy.loaded_count(), 1);
+    assert_eq!(sorted(lazy.get_versions("fooC")), vec!["v1", "v2"]);
+    assert_eq!(lazy.loaded_count(), 2);
+}
+
+#[test]
+fn test_lazy_missing_source() {
+    let result = reups::LazyPosixDBImpl::new(
+        PathBuf::from("/nonexistent/ups_db"),
+        None,
+        &Default::default(),
+    );
+    assert_eq!(
+        result.err(),
+        Some("Problem reading database at /nonexistent/ups_db".to_string())
+    );
+}
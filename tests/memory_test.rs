@@ -0,0 +1,134 @@
+extern crate reups_lib;
+use reups::graph::{Graph, NodeType};
+use reups::table::{EnvActionType, VersionType};
+use reups::{DBBuilderTrait, DBImpl};
+use reups_lib as reups;
+use std::path::PathBuf;
+
+/// A stack where fooA v2 requires fooB, and fooB v1 is current though fooB v2 is also declared
+fn memory_db() -> reups::DB {
+    let source = reups::MemoryDBImpl::builder()
+        .version("fooB", "v1", "/opt/fooB/v1")
+        .tag("current")
+        .version("fooB", "v2", "/opt/fooB/v2")
+        .version("fooC", "v1", "/opt/fooC/v1")
+        .flavor("Linux64")
+        .tag("current")
+        .version("fooA", "v2", "/opt/fooA/v2")
+        .ident("fooA-v2")
+        .tag("current")
+        .requires("fooB", "v2")
+        .optional("fooC", "v1")
+        .env("PATH", EnvActionType::Prepend, "${PRODUCT_DIR}/bin")
+        .build()
+        .unwrap();
+    reups::DBBuilder::new()
+        .add_eups_env(false)
+        .add_eups_user(false)
+        .add_reups_env(false)
+        .add_reups_user(false)
+        .add_source("memory", Box::new(source), 0)
+        .set_flavor("Linux64")
+        .build()
+        .unwrap()
+}
+
+/// Builds the graph of the dependencies of the current version of fooA
+fn resolve(db: &reups::DB, mode: VersionType) -> Graph {
+    let tags = vec!["current"];
+    let table = db.get_table_from_tag("fooA", &tags).unwrap();
+    let mut graph = Graph::new();
+    graph
+        .make_db_helper(db)
+        .add_table(&table, mode, NodeType::Required, Some(&tags), true);
+    graph
+}
+
+#[test]
+fn test_memory_lookups() {
+    let db = memory_db();
+    assert_eq!(db.get_version_from_identity("fooA", "fooA-v2"), Some("v2"));
+    let table = db.get_table_from_version("fooA", "v2").unwrap();
+    assert_eq!(table.product_dir, PathBuf::from("/opt/fooA/v2"));
+    assert_eq!(
+        table.env_var.get("PATH"),
+        Some(&(EnvActionType::Prepend, "/opt/fooA/v2/bin".to_string()))
+    );
+    assert_eq!(
+        db.get_versions_from_tag("fooB", &vec!["current"]),
+        vec!["v1"]
+    );
+}
+
+#[test]
+fn test_memory_resolve() {
+    let db = memory_db();
+    // Exact mode uses the versions in the table
+    let graph = resolve(&db, VersionType::Exact);
+    assert_eq!(graph.product_versions(&"fooB".to_string()), vec!["v2"]);
+    assert_eq!(graph.product_versions(&"fooC".to_string()), vec!["v1"]);
+    assert!(graph.is_optional(&"fooC".to_string()));
+
+    // Otherwise the dependencies are found by tag
+    let graph = resolve(&db, VersionType::Inexact);
+    assert!(graph.has_product(&"fooB".to_string()));
+    assert!(graph.has_product(&"fooC".to_string()));
+    assert!(!graph.is_optional(&"fooB".to_string()));
+}
+
+#[test]
+fn test_memory_builder_errors() {
+    assert!(reups::MemoryDBImpl::builder()
+        .tag("current")
+        .build()
+        .is_err());
+    let duplicate = reups::MemoryDBImpl::builder()
+        .version("fooA", "v1", "/opt/fooA/v1")
+        .version("fooA", "v1", "/opt/fooA/v1")
+        .build();
+    assert!(duplicate.is_err());
+
+    // An empty source can be declared into, and keeps the products it was given
+    let mut source = reups::MemoryDBImpl::new();
+    let prod_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/test_packages/fooA");
+    let input = reups::DeclareInputs {
+        product: "fooA",
+        prod_dir: &prod_dir,
+        version: "v1",
+        tag: Some("current"),
+        ident: Some("ident1"),
+        flavor: None,
+        qualifiers: None,
+        table: None,
+        relative: false,
+        ups_dir: None,
+        table_file: None,
+    };
+    source.declare_in_memory_impl(&vec![input]).unwrap();
+    source.sync("fooA").unwrap();
+    assert_eq!(
+        source.lookup_version_tag("fooA", "current", None, None),
+        Some("v1")
+    );
+    assert!(source.get_table("fooA", "v1", None, None).is_some());
+}
+
+#[test]
+fn test_memory_fix() {
+    // A relative product directory is resolved from the working directory, which is the crate
+    let mut source = reups::MemoryDBImpl::builder()
+        .version("fooA", "v1", "resources/test_packages/fooA")
+        .build()
+        .unwrap();
+    let paths = Some(reups::PathStyle::Absolute);
+    assert_eq!(source.plan_fix(paths).unwrap().len(), 1);
+    // Planning the repairs leaves the source unchanged
+    assert_eq!(source.plan_fix(paths).unwrap().len(), 1);
+
+    assert_eq!(source.fix(paths).unwrap().len(), 1);
+    assert!(source.plan_fix(paths).unwrap().is_empty());
+    assert!(source.check(&|_| true).is_empty());
+    let prod_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/test_packages/fooA");
+    let table = source.get_table("fooA", "v1", None, None).unwrap();
+    assert_eq!(table.product_dir, prod_dir.canonicalize().unwrap());
+}
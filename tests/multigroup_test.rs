@@ -0,0 +1,106 @@
+extern crate reups_lib;
+use reups_lib as reups;
+use reups_lib::DBImpl;
+use std::fs;
+use std::path::PathBuf;
+
+fn resource(path: &str) -> PathBuf {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push(path);
+    root
+}
+
+fn load(path: PathBuf) -> reups::PosixDBImpl {
+    reups::PosixDBImpl::new(path, Some(&reups::DBLoadControl::All), None).unwrap()
+}
+
+#[test]
+fn test_posix_groups() {
+    let db = load(resource("resources/multigroup_db/ups_db"));
+    assert_eq!(
+        db.get_flavors("fooC", "v1"),
+        Some(vec!["Linux64", "DarwinARM64", "Linux64"])
+    );
+    assert_eq!(
+        db.lookup_version_tag("fooC", "current", Some("Linux64"), None),
+        Some("v1")
+    );
+    assert_eq!(
+        db.lookup_version_tag("fooC", "current", Some("DarwinARM64"), None),
+        Some("v2")
+    );
+    assert_eq!(
+        db.lookup_flavor_version("fooC", "v1", Some("DarwinARM64"), Some("debug")),
+        Some("DarwinARM64")
+    );
+    assert_eq!(
+        db.lookup_flavor_version("fooC", "v1", Some("Linux64"), Some("debug")),
+        Some("Linux64")
+    );
+    assert!(db
+        .get_table("fooC", "v1", Some("Linux64"), Some("debug"))
+        .is_some());
+    assert!(db.get_table("fooC", "v2", Some("Linux64"), None).is_none());
+
+    // Every group is carried over when converting to json
+    let json = db.to_json(&resource("resources/multigroup_db/multigroup_db.json"));
+    assert_eq!(
+        json.get_flavors("fooC", "v1"),
+        Some(vec!["Linux64", "DarwinARM64", "Linux64"])
+    );
+    assert_eq!(
+        json.lookup_version_tag("fooC", "current", Some("DarwinARM64"), None),
+        Some("v2")
+    );
+}
+
+#[test]
+fn test_posix_sync_groups() {
+    // Work on a copy of the database, as syncing writes to it
+    let mut root = std::env::temp_dir();
+    root.push(format!("reups_multigroup_{}", std::process::id()));
+    let mut product_dir = root.clone();
+    product_dir.push("ups_db/fooC");
+    fs::create_dir_all(&product_dir).unwrap();
+    for entry in fs::read_dir(resource("resources/multigroup_db/ups_db/fooC")).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, product_dir.join(path.file_name().unwrap())).unwrap();
+    }
+
+    let mut db = load(root.join("ups_db"));
+    let prod_dir = resource("resources/test_packages/fooC");
+    let input = reups::DeclareInputs {
+        product: "fooC",
+        prod_dir: &prod_dir,
+        version: "v1",
+        tag: Some("current"),
+        ident: None,
+        flavor: Some("LinuxARM64"),
+        qualifiers: None,
+        table: None,
+        relative: false,
+        ups_dir: None,
+        table_file: None,
+    };
+    assert!(db.declare_in_memory_impl(&vec![input]).is_ok());
+    assert!(db.sync("fooC").is_ok());
+
+    let synced = load(root.join("ups_db"));
+    assert_eq!(
+        synced.get_flavors("fooC", "v1"),
+        Some(vec!["Linux64", "DarwinARM64", "Linux64", "LinuxARM64"])
+    );
+    assert_eq!(
+        synced.lookup_flavor_version("fooC", "v1", Some("Linux64"), Some("debug")),
+        Some("Linux64")
+    );
+    assert_eq!(
+        synced.lookup_version_tag("fooC", "current", Some("DarwinARM64"), None),
+        Some("v2")
+    );
+    assert_eq!(
+        synced.lookup_version_tag("fooC", "current", Some("LinuxARM64"), None),
+        Some("v1")
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,107 @@
+extern crate reups_lib;
+use reups::DBBuilderTrait;
+use reups_lib as reups;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn resource(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn run(args: Vec<&str>) -> String {
+    let matches = reups::build_cli().get_matches_from(args);
+    let (name, m) = matches.subcommand();
+    let mut cursor = Cursor::new(vec![]);
+    match name {
+        "declare" => reups::declare_command(m.unwrap(), &matches).unwrap(),
+        "setup" => reups::setup_command(m.unwrap(), &matches, &mut cursor).unwrap(),
+        _ => reups::overlay_command(m.unwrap(), &matches, &mut cursor).unwrap(),
+    }
+    String::from_utf8(cursor.into_inner()).unwrap()
+}
+
+fn current_versions(overlay: Option<PathBuf>) -> Vec<String> {
+    let db = reups::DBBuilder::new()
+        .add_eups_env(false)
+        .add_eups_user(false)
+        .add_reups_env(false)
+        .add_reups_user(false)
+        .add_path(resource("resources/json_db/json_db.json"))
+        .set_overlay(overlay)
+        .set_flavor("Linux64")
+        .build()
+        .unwrap();
+    db.get_versions_from_tag("fooA", &vec!["current"])
+        .iter()
+        .map(|x| x.to_string())
+        .collect()
+}
+
+#[test]
+fn test_overlay() {
+    let mut root = env::temp_dir();
+    root.push(format!("reups_overlay_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let overlay = root.join("overlay.json");
+    env::set_var("REUPS_OVERLAY", &overlay);
+    // Keep any sources of the user running the tests out of setup
+    env::set_var("HOME", &root);
+
+    // Declaring into the overlay leaves the shared source unchanged
+    let json = resource("resources/json_db/json_db.json");
+    let before = fs::read_to_string(&json).unwrap();
+    let prod_dir = root.join("fooA9");
+    fs::create_dir_all(prod_dir.join("ups")).unwrap();
+    fs::copy(
+        resource("resources/test_packages/fooA/ups/fooA.table"),
+        prod_dir.join("ups/fooA.table"),
+    )
+    .unwrap();
+    run(vec![
+        "reups",
+        "declare",
+        "-U",
+        "-S",
+        "fooA",
+        "v9",
+        "-r",
+        prod_dir.to_str().unwrap(),
+        "-t",
+        "current",
+        "--ident",
+        "overlay9",
+        "--overlay",
+        "-Z",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(fs::read_to_string(&json).unwrap(), before);
+    assert!(overlay.exists());
+
+    // The overlay is looked up before the other sources
+    assert_eq!(current_versions(Some(overlay.clone())), vec!["v9", "v3"]);
+    assert_eq!(current_versions(None), vec!["v3"]);
+
+    // Setup takes both the version and the product directory from the overlay
+    let output = run(vec![
+        "reups",
+        "setup",
+        "-S",
+        "-Z",
+        json.to_str().unwrap(),
+        "-j",
+        "fooA",
+    ]);
+    assert!(output.contains("SETUP_FOOA=fooA\\ v9\\ "));
+    assert!(output.contains(&format!("FOOA_DIR={} ", prod_dir.to_str().unwrap())));
+
+    let output = run(vec!["reups", "overlay", "clear"]);
+    assert!(output.starts_with("Removed overlay"));
+    assert!(!overlay.exists());
+    assert_eq!(current_versions(Some(overlay.clone())), vec!["v3"]);
+    let output = run(vec!["reups", "overlay", "clear"]);
+    assert!(output.starts_with("No overlay found"));
+    fs::remove_dir_all(&root).unwrap();
+}
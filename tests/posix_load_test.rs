@@ -0,0 +1,72 @@
+extern crate reups_lib;
+use reups::DBImpl;
+use reups_lib as reups;
+use std::path::PathBuf;
+
+fn load(path: &str, workers: usize) -> reups::PosixDBImpl {
+    let options = reups::PosixReadOptions {
+        cache_dir: None,
+        workers,
+    };
+    reups::PosixDBImpl::new_with_options(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path),
+        Some(&reups::DBLoadControl::All),
+        None,
+        &options,
+    )
+    .unwrap()
+}
+
+fn sorted(values: Option<Vec<&str>>) -> Vec<&str> {
+    let mut values = values.unwrap_or_default();
+    values.sort();
+    values
+}
+
+#[test]
+fn test_worker_counts() {
+    for path in &[
+        "resources/posix_db/ups_db",
+        "resources/multigroup_db/ups_db",
+    ] {
+        let single = load(path, 1);
+        // More workers than product directories leaves some with only stolen work, or none
+        for workers in &[2, 16] {
+            let pooled = load(path, *workers);
+            let mut products = single.get_products();
+            products.sort();
+            assert_eq!(products, sorted(Some(pooled.get_products())));
+            for product in products {
+                assert_eq!(
+                    sorted(single.get_tags(product)),
+                    sorted(pooled.get_tags(product))
+                );
+                for version in sorted(single.get_versions(product)) {
+                    assert_eq!(
+                        single.get_groups(product, version),
+                        pooled.get_groups(product, version)
+                    );
+                }
+                assert_eq!(
+                    sorted(single.get_versions(product)),
+                    sorted(pooled.get_versions(product))
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_missing_source() {
+    let options = reups::PosixReadOptions::default();
+    let result = reups::PosixDBImpl::new_with_options(
+        PathBuf::from("/nonexistent/ups_db"),
+        None,
+        None,
+        &options,
+    );
+    assert_eq!(
+        result.err(),
+        Some("Problem reading database at /nonexistent/ups_db".to_string())
+    );
+}
@@ -0,0 +1,109 @@
+extern crate reups_lib;
+use reups_lib as reups;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+#[test]
+fn test_project_setup_args() {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push("resources/project");
+    let project_path = reups::find_project_file(&root.join("nested")).unwrap();
+    assert_eq!(project_path, root.join(reups::PROJECT_FILE_NAME));
+
+    let project = reups::ProjectFile::from_file(&project_path).unwrap();
+    let args = project.setup_args(&root);
+    let local_path = root.join(".").to_str().unwrap().to_string();
+    let expected = vec![
+        vec!["-t", "testing", "-t", "stable", "fooA"],
+        vec!["-t", "stable", "-j", "fooC", "v2"],
+        vec!["-t", "stable", "-j", "-r", local_path.as_str()],
+    ];
+    assert_eq!(args, expected);
+}
+
+fn run_env(args: Vec<&str>) -> String {
+    let matches = reups::build_cli().get_matches_from(args);
+    let (_, m) = matches.subcommand();
+    let mut cursor = Cursor::new(vec![]);
+    reups::env_command(m.unwrap(), &matches, &mut cursor);
+    String::from_utf8(cursor.into_inner()).unwrap()
+}
+
+#[test]
+fn test_project_environment() {
+    let mut root = env::temp_dir();
+    root.push(format!("reups_project_env_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let project = root.join("project");
+    fs::create_dir_all(project.join("nested")).unwrap();
+    fs::write(
+        project.join(reups::PROJECT_FILE_NAME),
+        "[[product]]\nname = \"fooA\"\nversion = \"v3\"\njust = true\n",
+    )
+    .unwrap();
+    let crate_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let database = crate_path.join("resources/posix_db");
+    let database = database.to_str().unwrap();
+    let fooa_dir = crate_path.join("resources/test_packages/fooA");
+    env::remove_var("REUPS_DIR_STACK");
+    env::remove_var("FOOA_DIR");
+    // Values replaced by a project are saved in the stack, so use one with the characters the
+    // stack encoding has to escape
+    let path = "/usr/bin:/odd dir;with|separators=%";
+    env::set_var("PATH", path);
+
+    // Entering sets up the project, passing along the database options given to enter
+    let project_str = project.to_str().unwrap();
+    let enter = vec![
+        "reups",
+        "env",
+        "enter",
+        project_str,
+        "-U",
+        "-S",
+        "-Z",
+        database,
+        "--no-cache",
+    ];
+    let output = run_env(enter.clone());
+    assert!(output.starts_with("export "));
+    assert_eq!(env::var("FOOA_DIR").unwrap(), fooa_dir.to_str().unwrap());
+    assert!(env::var("PATH").unwrap().ends_with(path));
+    let stack = env::var("REUPS_DIR_STACK").unwrap();
+    let mut fields: Vec<&str> = stack.split(';').collect();
+    assert_eq!(fields.remove(0), project_str);
+    fields.sort();
+    assert!(fields.contains(&"FOOA_DIR"));
+    assert!(fields.contains(&"PATH=/usr/bin:/odd%20dir%3Bwith%7Cseparators%3D%25"));
+
+    // Entering a project which is already entered changes nothing
+    assert_eq!(run_env(enter), "");
+
+    // Leaving restores the values the project replaced, and unsets those it added
+    let output = run_env(vec!["reups", "env", "leave"]);
+    assert!(output.contains("unset "));
+    assert!(output.contains("FOOA_DIR"));
+    assert_eq!(env::var("PATH").unwrap(), path);
+    assert!(env::var("FOOA_DIR").is_err());
+    assert!(env::var("REUPS_DIR_STACK").is_err());
+
+    // The hook enters the project when inside its directory, and leaves it outside of it
+    let hook = vec!["reups", "env", "hook", "-U", "-S", "-Z", database];
+    env::set_current_dir(project.join("nested")).unwrap();
+    run_env(hook.clone());
+    assert_eq!(env::var("FOOA_DIR").unwrap(), fooa_dir.to_str().unwrap());
+    assert!(env::var("REUPS_DIR_STACK")
+        .unwrap()
+        .starts_with(project_str));
+    assert_eq!(run_env(hook.clone()), "");
+    env::set_current_dir(&root).unwrap();
+    run_env(hook);
+    assert!(env::var("FOOA_DIR").is_err());
+    assert_eq!(env::var("PATH").unwrap(), path);
+    assert!(env::var("REUPS_DIR_STACK").is_err());
+
+    env::set_current_dir(&crate_path).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,117 @@
+extern crate reups_lib;
+use reups_lib as reups;
+use reups_lib::DBImpl;
+use std::env;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn resource(path: &str) -> PathBuf {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push(path);
+    root
+}
+
+fn setup(args: Vec<&str>) -> Result<String, String> {
+    env::set_var("PATH", "");
+    env::set_var("REUPS_HISTORY", "");
+    let mut args: Vec<String> = args.iter().map(|&s| s.to_string()).collect();
+    args.push(
+        resource("resources/qualifier_db")
+            .to_str()
+            .unwrap()
+            .to_string(),
+    );
+
+    let mut cursor = Cursor::new(vec![]);
+
+    let app = reups::build_cli();
+    let matches = app.get_matches_from(args);
+    let (_, m) = matches.subcommand();
+    reups::setup_command(m.unwrap(), &matches, &mut cursor)?;
+    Ok(String::from_utf8(cursor.into_inner()).unwrap())
+}
+
+#[test]
+fn test_normalize_qualifiers() {
+    assert_eq!(reups::normalize_qualifiers("\"\""), "");
+    assert_eq!(reups::normalize_qualifiers("\"debug\""), "debug");
+    assert_eq!(reups::normalize_qualifiers("debug"), "debug");
+}
+
+#[test]
+fn test_setup_qualifiers() {
+    // A build with qualifiers is only used when they are requested
+    assert!(setup(vec!["reups", "setup", "-U", "-S", "fooC", "v1", "-j", "-Z"]).is_err());
+    let debug = setup(vec![
+        "reups", "setup", "-U", "-S", "-q", "debug", "fooC", "v1", "-j", "-Z",
+    ])
+    .unwrap();
+    assert!(debug.contains("SETUP_FOOC=fooC\\ v1\\ "));
+
+    // Builds without qualifiers are used when there is none with the requested qualifiers
+    let tagged = setup(vec![
+        "reups", "setup", "-U", "-S", "-q", "debug", "fooC", "-j", "-Z",
+    ])
+    .unwrap();
+    assert!(tagged.contains("SETUP_FOOC=fooC\\ v2\\ "));
+}
+
+#[test]
+fn test_posix2json_qualifiers() {
+    let posix = reups::PosixDBImpl::new(
+        resource("resources/qualifier_db/ups_db"),
+        Some(&reups::DBLoadControl::All),
+        None,
+    )
+    .unwrap();
+    let json = posix.to_json(&resource("resources/qualifier_db/qualifier_db.json"));
+    let serialized = serde_json::to_string(&json).unwrap();
+    let round_trip: reups::JsonDBImpl = serde_json::from_str(&serialized).unwrap();
+    assert!(json.get_table("fooC", "v1", None, Some("debug")).is_some());
+    assert!(json.get_table("fooC", "v1", None, Some("")).is_none());
+    assert!(json.get_table("fooC", "v2", None, Some("debug")).is_some());
+    assert_eq!(
+        round_trip.lookup_flavor_version("fooC", "v1", None, Some("debug")),
+        Some("NULL")
+    );
+    assert_eq!(
+        round_trip.lookup_flavor_version("fooC", "v1", None, Some("")),
+        None
+    );
+}
+
+#[test]
+fn test_declare_qualifiers() {
+    let mut db = reups::JsonDBImpl::new(&resource("resources/qualifier_db/declared.json")).unwrap();
+    let prod_dir = resource("resources/test_packages/fooC");
+    let input = |qualifiers| reups::DeclareInputs {
+        product: "fooC",
+        prod_dir: &prod_dir,
+        version: "v1",
+        tag: None,
+        ident: Some("abc"),
+        flavor: Some("Linux64"),
+        qualifiers,
+        table: None,
+        relative: false,
+        ups_dir: None,
+        table_file: None,
+    };
+    assert!(db.declare_in_memory_impl(&vec![input(None)]).is_ok());
+    assert!(db
+        .declare_in_memory_impl(&vec![input(Some("debug"))])
+        .is_ok());
+    assert!(db
+        .declare_in_memory_impl(&vec![input(Some("debug"))])
+        .is_err());
+    assert!(db
+        .get_table("fooC", "v1", Some("Linux64"), Some("debug"))
+        .is_some());
+
+    let serialized = serde_json::to_string(&db).unwrap();
+    let round_trip: reups::JsonDBImpl = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(
+        round_trip.get_flavors("fooC", "v1"),
+        Some(vec!["Linux64", "Linux64"])
+    );
+}
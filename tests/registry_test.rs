@@ -0,0 +1,81 @@
+extern crate reups_lib;
+use reups::{DBBuilderTrait, DBImpl};
+use reups_lib as reups;
+use std::path::{Path, PathBuf};
+
+fn resource(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+/// Opens the json test source, whatever path it is asked to open
+fn open_test_source(_: &Path) -> Result<Box<dyn DBImpl>, String> {
+    let source = reups::JsonDBImpl::from_file(&resource("resources/json_db/json_db.json"))
+        .map_err(|e| e.to_string())?;
+    Ok(Box::new(source))
+}
+
+/// A builder that only uses the sources added to it
+fn builder() -> Result<reups::DBBuilder, String> {
+    reups::DBBuilder::new()
+        .add_eups_env(false)
+        .add_eups_user(false)
+        .add_reups_env(false)
+        .add_reups_user(false)
+}
+
+#[test]
+fn test_registered_sources() {
+    reups::register_scheme("metadata", open_test_source);
+    reups::register_extension("stack", open_test_source);
+    assert!(reups::is_registered_source(Path::new(
+        "metadata://example.com:8080/stack"
+    )));
+    assert!(reups::is_registered_source(Path::new("/some/dir/a.stack")));
+    assert!(!reups::is_registered_source(Path::new(
+        "other://example.com/a.stack"
+    )));
+    assert!(!reups::is_registered_source(Path::new("/some/dir")));
+
+    // Registered paths are kept whole, and opened with their constructors
+    let paths =
+        reups::path_string_to_vec("metadata://example.com:8080/stack:/some/dir/a.stack").unwrap();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("metadata://example.com:8080/stack"),
+            PathBuf::from("/some/dir/a.stack"),
+        ]
+    );
+    let db = builder()
+        .add_path_vec(paths)
+        .set_flavor("Linux64")
+        .build()
+        .unwrap();
+    assert_eq!(db.get_db_sources().len(), 2);
+    assert_eq!(
+        db.get_versions_from_tag("fooA", &vec!["current"]),
+        vec!["v3", "v3"]
+    );
+}
+
+#[test]
+fn test_add_source() {
+    let source = open_test_source(Path::new("")).unwrap();
+    let db = builder()
+        .add_source("in_house", source, 1)
+        .set_flavor("Linux64")
+        .build()
+        .unwrap();
+    assert_eq!(db.get_db_sources()[0].0, "in_house");
+    assert!(db.has_product("fooA"));
+    assert!(db.get_table_from_version("fooA", "v3").is_some());
+
+    // Names are shared with sources added by path
+    let json = resource("resources/json_db/json_db.json");
+    let result = builder().add_named_path("in_house", json, 0).add_source(
+        "in_house",
+        open_test_source(Path::new("")).unwrap(),
+        0,
+    );
+    assert!(result.is_err());
+}
@@ -38,27 +38,57 @@ fn common(args: Vec<&str>, expected: &str, json: bool) {
 #[test]
 fn test_setup_exact() {
     let args = vec!["reups", "setup", "-U", "-S", "fooA", "-Z"];
-    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v3\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ REUPS_HISTORY=\"|$CRATE_PATH/target/debug/deps/setup_test-7fa2884cecac2627\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ \n";
+    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v3\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ REUPS_HISTORY=\"|$CURRENT_EXE\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ \n";
     common(args, expected, false);
 }
 
 #[test]
 fn test_setup_inexact() {
     let args = vec!["reups", "setup", "-U", "-S", "-E", "fooA", "-Z"];
-    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v2\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v3\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ REUPS_HISTORY=\"|$CRATE_PATH/target/debug/deps/setup_test-7fa2884cecac2627\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ \n";
+    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v2\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v3\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ REUPS_HISTORY=\"|$CURRENT_EXE\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ \n";
     common(args, expected, false);
 }
 
 #[test]
 fn test_setup_exact_json() {
     let args = vec!["reups", "setup", "-U", "-S", "fooA", "-Z"];
-    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v3\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json REUPS_HISTORY=\"|$CRATE_PATH/target/debug/deps/setup_test-7fa2884cecac2627\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json \n";
+    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v3\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json REUPS_HISTORY=\"|$CURRENT_EXE\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json \n";
     common(args, expected, true);
 }
 
 #[test]
 fn test_setup_inexact_json() {
     let args = vec!["reups", "setup", "-U", "-S", "-E", "fooA", "-Z"];
-    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v2\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v3\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json REUPS_HISTORY=\"|$CRATE_PATH/target/debug/deps/setup_test-7fa2884cecac2627\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json \n";
+    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v2\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v3\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json REUPS_HISTORY=\"|$CURRENT_EXE\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json \n";
+    common(args, expected, true);
+}
+
+#[test]
+fn test_setup_ident_json() {
+    let args = vec![
+        "reups",
+        "setup",
+        "-U",
+        "-S",
+        "fooA",
+        "--ident",
+        "a1047eab1035d58682a53557e0b2a75edbfd15fd",
+        "-Z",
+    ];
+    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v2\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json REUPS_HISTORY=\"|$CURRENT_EXE\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json \n";
+    common(args, expected, true);
+}
+
+#[test]
+fn test_setup_version() {
+    let args = vec!["reups", "setup", "-U", "-S", "fooA", "v2", "-Z"];
+    let expected = "export FOOC_DIR=$CRATE_PATH/resources/test_packages/fooC SETUP_FOOC=fooC\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ FOOB_DIR=$CRATE_PATH/resources/test_packages/fooB SETUP_FOOA=fooA\\ v2\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ REUPS_HISTORY=\"|$CURRENT_EXE\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA SETUP_FOOB=fooB\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/posix_db/ \n";
+    common(args, expected, false);
+}
+
+#[test]
+fn test_setup_version_just_json() {
+    let args = vec!["reups", "setup", "-U", "-S", "-j", "fooA", "v1", "-Z"];
+    let expected = "export SETUP_FOOA=fooA\\ v1\\ -f\\ Linux64\\ -Z\\ $CRATE_PATH/resources/json_db/json_db.json REUPS_HISTORY=\"|$CURRENT_EXE\" PATH=$CRATE_PATH/resources/test_packages/fooA/bin: FOOA_DIR=$CRATE_PATH/resources/test_packages/fooA \n";
     common(args, expected, true);
 }
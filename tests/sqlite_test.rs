@@ -0,0 +1,116 @@
+extern crate reups_lib;
+use reups::DBImpl;
+use reups_lib as reups;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn resource(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+/// Converts the json test source into a sqlite source in a temporary directory, returning the
+/// path of the sqlite file
+fn sqlite_copy(name: &str) -> PathBuf {
+    let mut root = std::env::temp_dir();
+    root.push(format!("reups_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let dest = root.join("db.sqlite");
+    let from = resource("resources/json_db/json_db.json");
+    let matches = reups::build_cli().get_matches_from(vec![
+        "reups",
+        "db",
+        "convert",
+        "--from",
+        from.to_str().unwrap(),
+        "--to",
+        dest.to_str().unwrap(),
+    ]);
+    let (_, m) = matches.subcommand();
+    let mut cursor = Cursor::new(vec![]);
+    reups::db_command(m.unwrap(), &matches, &mut cursor).unwrap();
+    dest
+}
+
+fn open(path: &PathBuf) -> Box<dyn DBImpl> {
+    reups::open_source(path, None, None, &Default::default()).unwrap()
+}
+
+fn declare(source: &mut dyn DBImpl, version: &str, ident: &str) {
+    let prod_dir = resource("resources/test_packages/fooA");
+    let input = reups::DeclareInputs {
+        product: "fooA",
+        prod_dir: &prod_dir,
+        version,
+        tag: None,
+        ident: Some(ident),
+        flavor: Some("Linux64"),
+        qualifiers: None,
+        table: None,
+        relative: false,
+        ups_dir: None,
+        table_file: None,
+    };
+    source.declare_in_memory_impl(&vec![input]).unwrap();
+    source.sync("fooA").unwrap();
+}
+
+#[test]
+fn test_sqlite_matches_json() {
+    let path = sqlite_copy("sqlite_matches_json");
+    let json = open(&resource("resources/json_db/json_db.json"));
+    let sqlite = open(&path);
+    assert_eq!(reups::diff(json.as_ref(), sqlite.as_ref()), vec![]);
+    let ident = json.get_identities("fooA").unwrap()[0];
+    assert_eq!(
+        sqlite.lookup_version_ident("fooA", ident),
+        json.lookup_version_ident("fooA", ident)
+    );
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_sqlite_declare() {
+    let path = sqlite_copy("sqlite_declare");
+    // Two sources read before either declares, syncing one does not remove the rows of the other
+    let mut first = open(&path);
+    let mut second = open(&path);
+    declare(first.as_mut(), "v4", "ident4");
+    declare(second.as_mut(), "v5", "ident5");
+    assert!(!second.has_identity("fooA", "ident4"));
+
+    let reread = open(&path);
+    let mut versions = reread.get_versions("fooA").unwrap();
+    versions.sort();
+    assert_eq!(versions, vec!["v1", "v2", "v3", "v4", "v5"]);
+    assert_eq!(reread.lookup_version_ident("fooA", "ident4"), Some("v4"));
+    assert_eq!(reread.lookup_version_ident("fooA", "ident5"), Some("v5"));
+    assert!(reread.get_table("fooA", "v5", None, None).is_some());
+
+    // Declaring without an identity generates one from the product directory and table
+    let prod_dir = resource("resources/test_packages/fooA");
+    let input = reups::DeclareInputs {
+        product: "fooA",
+        prod_dir: &prod_dir,
+        version: "v6",
+        tag: None,
+        ident: None,
+        flavor: Some("Linux64"),
+        qualifiers: None,
+        table: None,
+        relative: false,
+        ups_dir: None,
+        table_file: None,
+    };
+    let mut source = open(&path);
+    source.declare_in_memory_impl(&vec![input]).unwrap();
+    let ident = reups::content_ident(
+        "fooA",
+        "v6",
+        &prod_dir.canonicalize().unwrap(),
+        Some(&prod_dir.join("ups/fooA.table")),
+    );
+    assert_eq!(source.lookup_version_ident("fooA", &ident), Some("v6"));
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}